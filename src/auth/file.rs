@@ -0,0 +1,293 @@
+//! Identity provider backed by a users file.
+//!
+//! Small sites and development environments can run without a directory
+//! server by listing their users in a YAML or CSV file, with bcrypt or argon2
+//! password hashes:
+//!
+//! ```yaml
+//! users:
+//!   - username: alice
+//!     password_hash: "$2b$12$..."
+//!     phone_numbers: ["+15551234567"]
+//!     profile:
+//!       displayName: Alice Example
+//! ```
+//!
+//! In CSV, the `username`, `password_hash` and `phone_numbers` columns are
+//! required, several numbers are separated by `;`, an optional `disabled` column
+//! marks disabled accounts, and every other column becomes a profile attribute.
+//!
+//! The file is read once at startup.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::LazyLock;
+use tracing::{debug, error, warn};
+
+use super::identity::IdentityProvider;
+use super::ldap::{AuthenticatedUser, Error};
+use super::password::{self, HashFormat};
+use super::phone::{self, PhoneNormalizer, PhoneSelection};
+
+/// bcrypt hash checked for unknown users; what it matches does not matter.
+static DUMMY_HASH: LazyLock<String> =
+    LazyLock::new(|| bcrypt::hash("unknown user", bcrypt::DEFAULT_COST).unwrap_or_default());
+
+/// A user read from a users or htpasswd file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct StaticUser {
+    /// Username as written in the file
+    pub username: String,
+    /// bcrypt, argon2 or htpasswd password hash
+    pub password_hash: String,
+    /// Phone numbers as written in the file
+    #[serde(default)]
+    pub phone_numbers: Vec<String>,
+    /// Profile attributes
+    #[serde(default)]
+    pub profile: HashMap<String, String>,
+    /// Whether the account is disabled
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+/// Users loaded from a file, looked up case-insensitively.
+pub(crate) struct StaticUsers {
+    /// Users keyed by lowercase username, with normalized phone numbers
+    users: HashMap<String, StaticUser>,
+    phone_normalizer: PhoneNormalizer,
+    phone_selection: PhoneSelection,
+    /// File the users came from, reported with `PhoneNumberNotFound`
+    source: String,
+}
+
+impl StaticUsers {
+    /// Normalizes the users' phone numbers and indexes them by username.
+    ///
+    /// # Arguments
+    /// * `users` - Users as read from the file
+    /// * `phone_normalizer` - Normalizer for the phone numbers
+    /// * `phone_selection` - How to choose between several numbers
+    /// * `source` - File the users came from
+    pub fn new(users: Vec<StaticUser>, phone_normalizer: PhoneNormalizer, phone_selection: PhoneSelection, source: &str) -> Self {
+        let mut indexed = HashMap::with_capacity(users.len());
+        for mut user in users {
+            if HashFormat::detect(&user.password_hash).is_none() {
+                warn!("Unsupported password hash format for {} in {}, the user cannot sign in", user.username, source);
+            }
+            let mut phone_numbers = Vec::new();
+            for raw in user.phone_numbers.iter().filter(|raw| !raw.trim().is_empty()) {
+                match phone_normalizer.normalize(raw) {
+                    Ok(number) if !phone_numbers.contains(&number) => phone_numbers.push(number),
+                    Ok(_) => {}
+                    Err(_) => error!("Phone number of {} in {} is not valid: {}", user.username, source, raw),
+                }
+            }
+            user.phone_numbers = phone_numbers;
+            if indexed.insert(user.username.to_lowercase(), user).is_some() {
+                warn!("Duplicate user in {}, keeping the last entry", source);
+            }
+        }
+        Self {
+            users: indexed,
+            phone_normalizer,
+            phone_selection,
+            source: source.to_string(),
+        }
+    }
+
+    /// Looks a user up by username, ignoring case.
+    fn get(&self, username: &str) -> Result<&StaticUser, Error> {
+        self.users
+            .get(&username.to_lowercase())
+            .ok_or_else(|| Error::UserNotFound(username.to_string()))
+    }
+
+    /// Checks a user's password and selects their phone number.
+    ///
+    /// # Arguments
+    /// * `username` - Username, matched case-insensitively
+    /// * `password` - Password to check
+    /// * `phone_optional` - Return a user without numbers with an empty `phone_number`
+    ///
+    /// # Returns
+    /// * `Result<AuthenticatedUser>` - The user, or the same errors the LDAP client reports
+    pub async fn authenticate(&self, username: &str, password: &str, phone_optional: bool) -> Result<AuthenticatedUser, Error> {
+        if password.is_empty() {
+            error!("Empty password supplied for user: {}", username);
+            return Err(Error::AuthenticationFailed);
+        }
+        // Unknown users are checked against a dummy hash so they take as long as known ones
+        let user = self.get(username);
+        let hash = user.as_ref().map_or_else(|_| DUMMY_HASH.clone(), |user| user.password_hash.clone());
+        let password = password.to_string();
+        let valid = tokio::task::spawn_blocking(move || password::verify_password(&password, &hash))
+            .await
+            .map_err(|e| Error::ServerError(format!("Password check failed: {}", e)))?;
+        let user = user?;
+        if !valid {
+            error!("Invalid credentials for user: {}", user.username);
+            return Err(Error::AuthenticationFailed);
+        }
+        // Only someone who knows the password learns that the account is disabled
+        if user.disabled {
+            return Err(Error::AccountDisabled(user.username.clone()));
+        }
+
+        let (phone_number, profile) = if phone_optional && user.phone_numbers.is_empty() {
+            debug!("User {} has no phone number", user.username);
+            (String::new(), user.profile.clone())
+        } else {
+            phone::select_phone_number(
+                self.phone_selection,
+                &self.phone_normalizer,
+                &user.username,
+                &user.username,
+                user.phone_numbers.clone(),
+                user.profile.clone(),
+                &self.source,
+            )?
+        };
+        Ok(AuthenticatedUser {
+            username: user.username.clone(),
+            dn: user.username.clone(),
+            bind_name: user.username.clone(),
+            phone_number,
+            profile,
+        })
+    }
+
+    /// Returns a user's normalized phone numbers.
+    pub fn lookup_phone_numbers(&self, username: &str) -> Result<Vec<String>, Error> {
+        let user = self.get(username)?;
+        if user.phone_numbers.is_empty() {
+            return Err(Error::PhoneNumberNotFound(self.source.clone()));
+        }
+        Ok(user.phone_numbers.clone())
+    }
+
+    /// Returns a user's profile attributes.
+    pub fn lookup_profile(&self, username: &str) -> Result<HashMap<String, String>, Error> {
+        Ok(self.get(username)?.profile.clone())
+    }
+
+    /// Normalizes a user-supplied phone number.
+    pub fn normalize_phone_number(&self, raw: &str) -> Result<String, Error> {
+        self.phone_normalizer.normalize(raw)
+    }
+}
+
+impl fmt::Debug for StaticUsers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaticUsers")
+            .field("source", &self.source)
+            .field("users", &self.users.len())
+            .finish()
+    }
+}
+
+/// Shape of a YAML users file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UsersFile {
+    users: Vec<StaticUser>,
+}
+
+/// Identity provider backed by a YAML or CSV users file.
+#[derive(Debug)]
+pub struct FileIdentityProvider {
+    users: StaticUsers,
+}
+
+impl FileIdentityProvider {
+    /// Loads a users file; the format is picked by its extension.
+    ///
+    /// # Arguments
+    /// * `path` - `.yml`/`.yaml` or `.csv` users file
+    /// * `phone_normalizer` - Normalizer for the phone numbers
+    /// * `phone_selection` - How to choose between several numbers
+    ///
+    /// # Returns
+    /// * `Result<Self>` - The provider, or error if the file cannot be read or parsed
+    pub fn load(path: &Path, phone_normalizer: PhoneNormalizer, phone_selection: PhoneSelection) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| Error::ServerError(format!("Cannot read users file {}: {}", path.display(), e)))?;
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+        let users = match extension.as_str() {
+            "yml" | "yaml" => {
+                serde_yaml::from_str::<UsersFile>(&contents)
+                    .map_err(|e| Error::ServerError(format!("Invalid users file {}: {}", path.display(), e)))?
+                    .users
+            }
+            "csv" => parse_csv(&contents).map_err(|e| Error::ServerError(format!("Invalid users file {}: {}", path.display(), e)))?,
+            _ => return Err(Error::ServerError(format!("Users file {} must be .yml, .yaml or .csv", path.display()))),
+        };
+        debug!("Loaded {} users from {}", users.len(), path.display());
+
+        Ok(Self {
+            users: StaticUsers::new(users, phone_normalizer, phone_selection, &path.display().to_string()),
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl IdentityProvider for FileIdentityProvider {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<AuthenticatedUser, Error> {
+        self.users.authenticate(username, password, false).await
+    }
+
+    async fn authenticate_without_phone(&self, username: &str, password: &str) -> Result<AuthenticatedUser, Error> {
+        self.users.authenticate(username, password, true).await
+    }
+
+    async fn lookup_phone_numbers(&self, username: &str) -> Result<Vec<String>, Error> {
+        self.users.lookup_phone_numbers(username)
+    }
+
+    async fn lookup_profile(&self, username: &str) -> Result<HashMap<String, String>, Error> {
+        self.users.lookup_profile(username)
+    }
+
+    fn normalize_phone_number(&self, raw: &str) -> Result<String, Error> {
+        self.users.normalize_phone_number(raw)
+    }
+}
+
+/// Parses a CSV users file.
+fn parse_csv(contents: &str) -> Result<Vec<StaticUser>, String> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(contents.as_bytes());
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    for required in ["username", "password_hash", "phone_numbers"] {
+        if !headers.iter().any(|header| header == required) {
+            return Err(format!("missing column {}", required));
+        }
+    }
+
+    let mut users = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let mut user = StaticUser::default();
+        for (header, value) in headers.iter().zip(record.iter()) {
+            match header {
+                "username" => user.username = value.to_string(),
+                "password_hash" => user.password_hash = value.to_string(),
+                "phone_numbers" => user.phone_numbers = value.split(';').map(str::to_string).collect(),
+                "disabled" => user.disabled = value.eq_ignore_ascii_case("true"),
+                _ if value.is_empty() => {}
+                _ => {
+                    user.profile.insert(header.to_string(), value.to_string());
+                }
+            }
+        }
+        if user.username.is_empty() {
+            return Err(format!("empty username on line {}", record.position().map_or(0, |p| p.line())));
+        }
+        users.push(user);
+    }
+    Ok(users)
+}
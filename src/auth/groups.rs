@@ -0,0 +1,153 @@
+//! LDAP group-based authorization.
+//!
+//! Registration can be restricted to members of one or more directory groups.
+//! Membership is checked in one of three ways: the `memberOf` attribute on the
+//! user entry, a lookup of the user's DN in the `member`/`uniqueMember`
+//! attribute of each `groupOfNames`/`groupOfUniqueNames` group, or Active
+//! Directory's transitive `LDAP_MATCHING_RULE_IN_CHAIN` so nested groups count.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use ldap3::Scope;
+use tracing::{debug, error, warn};
+
+pub use crate::config::GroupMembership;
+
+use super::ldap::{Error, LdapClient};
+use super::pool::PooledConnection;
+
+/// OID of Active Directory's transitive membership matching rule.
+const MATCHING_RULE_IN_CHAIN: &str = "1.2.840.113556.1.4.1941";
+
+/// LDAP result code returned when the search base does not exist.
+const NO_SUCH_OBJECT: u32 = 32;
+
+/// Checks whether a user belongs to one of the authorized groups.
+#[derive(Debug, Clone)]
+pub struct GroupAuthorizer {
+    /// Group DNs whose members are authorized
+    groups: Vec<String>,
+    /// How membership is determined
+    membership: GroupMembership,
+}
+
+impl GroupAuthorizer {
+    /// Creates a new authorizer.
+    ///
+    /// # Arguments
+    /// * `groups` - DNs of the authorized groups; empty authorizes everyone
+    /// * `membership` - How membership is determined
+    pub fn new(groups: Vec<String>, membership: GroupMembership) -> Self {
+        Self { groups, membership }
+    }
+
+    /// Returns whether any group restriction is configured.
+    pub fn is_enabled(&self) -> bool {
+        !self.groups.is_empty()
+    }
+
+    /// Returns the attributes that must be fetched with the user entry.
+    pub fn user_attributes(&self) -> &'static [&'static str] {
+        match self.membership {
+            GroupMembership::MemberOf if self.is_enabled() => &["memberOf"],
+            _ => &[],
+        }
+    }
+
+    /// Checks that the user belongs to at least one authorized group.
+    ///
+    /// # Arguments
+    /// * `ldap` - Pooled service-account connection
+    /// * `user_dn` - DN of the user entry
+    /// * `member_of` - `memberOf` values fetched with the user entry
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success, or `NotAuthorized` if the user is in none of the groups
+    pub async fn check(&self, ldap: &mut PooledConnection, user_dn: &str, member_of: &[String]) -> Result<(), Error> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let authorized = match self.membership {
+            GroupMembership::MemberOf => member_of.iter().any(|group| {
+                self.groups.iter().any(|allowed| normalize_dn(allowed) == normalize_dn(group))
+            }),
+            GroupMembership::GroupSearch => self.search_groups(ldap, user_dn).await?,
+            GroupMembership::AdNested => self.search_nested(ldap, user_dn).await?,
+        };
+
+        if authorized {
+            debug!("User {} is a member of an authorized group", user_dn);
+            Ok(())
+        } else {
+            error!("User {} is not a member of any authorized group", user_dn);
+            Err(Error::NotAuthorized(user_dn.to_string()))
+        }
+    }
+
+    /// Looks the user's DN up in the member attributes of each group.
+    async fn search_groups(&self, ldap: &mut PooledConnection, user_dn: &str) -> Result<bool, Error> {
+        let escaped = escape(user_dn);
+        let filter = format!("(|(member={0})(uniqueMember={0}))", escaped);
+
+        for group in &self.groups {
+            let result = ldap
+                .op()
+                .search(group, Scope::Base, &filter, vec!["1.1"])
+                .await?;
+            if result.1.rc == NO_SUCH_OBJECT {
+                warn!("Authorized group {} does not exist", group);
+                continue;
+            }
+            let (entries, _) = result.success().map_err(|e| {
+                error!("Group lookup failed for {}: {:?}", group, e);
+                Error::ServerError(e.to_string())
+            })?;
+            if !entries.is_empty() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Asks Active Directory whether the user is a direct or nested member of
+    /// any of the groups.
+    async fn search_nested(&self, ldap: &mut PooledConnection, user_dn: &str) -> Result<bool, Error> {
+        let clauses: String = self
+            .groups
+            .iter()
+            .map(|group| format!("(memberOf:{}:={})", MATCHING_RULE_IN_CHAIN, escape(group)))
+            .collect();
+        let filter = format!("(|{})", clauses);
+
+        let (entries, _) = ldap
+            .op()
+            .search(user_dn, Scope::Base, &filter, vec!["1.1"])
+            .await?
+            .success()
+            .map_err(|e| {
+                error!("Nested group lookup failed for {}: {:?}", user_dn, e);
+                Error::ServerError(e.to_string())
+            })?;
+        Ok(!entries.is_empty())
+    }
+}
+
+/// Escapes a DN for use as a filter assertion value.
+fn escape(value: &str) -> String {
+    LdapClient::escape_ldap_value(value)
+}
+
+/// Normalizes a DN for comparison: case-insensitive, without spaces around separators.
+fn normalize_dn(dn: &str) -> String {
+    dn.split(',')
+        .map(|rdn| {
+            rdn.split('=')
+                .map(|part| part.trim())
+                .collect::<Vec<_>>()
+                .join("=")
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+        .to_lowercase()
+}
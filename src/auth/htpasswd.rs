@@ -0,0 +1,117 @@
+//! Identity provider backed by an htpasswd file.
+//!
+//! Passwords come from an Apache htpasswd file (`username:hash` lines, with
+//! bcrypt, `$apr1$` or `{SHA}` hashes, as written by `htpasswd -B`, `-m` or
+//! `-s`), and phone numbers from a separate map of `username:number` lines, with
+//! several numbers separated by commas:
+//!
+//! ```text
+//! alice:+15551234567
+//! bob:+15557654321,+15550001111
+//! ```
+//!
+//! Blank lines and lines starting with `#` are ignored in both files. Users in
+//! the htpasswd file without an entry in the map have no phone number. Both
+//! files are read once at startup.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::{debug, warn};
+
+use super::file::{StaticUser, StaticUsers};
+use super::identity::IdentityProvider;
+use super::ldap::{AuthenticatedUser, Error};
+use super::phone::{PhoneNormalizer, PhoneSelection};
+
+/// Identity provider backed by an htpasswd file and a phone number map.
+#[derive(Debug)]
+pub struct HtpasswdIdentityProvider {
+    users: StaticUsers,
+}
+
+impl HtpasswdIdentityProvider {
+    /// Loads an htpasswd file and its phone number map.
+    ///
+    /// # Arguments
+    /// * `htpasswd_path` - htpasswd file
+    /// * `phone_map_path` - File of `username:number[,number...]` lines
+    /// * `phone_normalizer` - Normalizer for the phone numbers
+    /// * `phone_selection` - How to choose between several numbers
+    ///
+    /// # Returns
+    /// * `Result<Self>` - The provider, or error if a file cannot be read or parsed
+    pub fn load(
+        htpasswd_path: &Path,
+        phone_map_path: &Path,
+        phone_normalizer: PhoneNormalizer,
+        phone_selection: PhoneSelection,
+    ) -> Result<Self, Error> {
+        let passwords = read_entries(htpasswd_path)?;
+        let mut phone_numbers: HashMap<String, Vec<String>> = HashMap::new();
+        for (username, numbers) in read_entries(phone_map_path)? {
+            phone_numbers
+                .entry(username.to_lowercase())
+                .or_default()
+                .extend(numbers.split(',').map(|number| number.trim().to_string()));
+        }
+
+        let users: Vec<StaticUser> = passwords
+            .into_iter()
+            .map(|(username, password_hash)| StaticUser {
+                phone_numbers: phone_numbers.remove(&username.to_lowercase()).unwrap_or_default(),
+                username,
+                password_hash,
+                ..StaticUser::default()
+            })
+            .collect();
+        for username in phone_numbers.keys() {
+            warn!("{} in {} is not in the htpasswd file", username, phone_map_path.display());
+        }
+        debug!("Loaded {} users from {}", users.len(), htpasswd_path.display());
+
+        Ok(Self {
+            users: StaticUsers::new(users, phone_normalizer, phone_selection, &phone_map_path.display().to_string()),
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl IdentityProvider for HtpasswdIdentityProvider {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<AuthenticatedUser, Error> {
+        self.users.authenticate(username, password, false).await
+    }
+
+    async fn authenticate_without_phone(&self, username: &str, password: &str) -> Result<AuthenticatedUser, Error> {
+        self.users.authenticate(username, password, true).await
+    }
+
+    async fn lookup_phone_numbers(&self, username: &str) -> Result<Vec<String>, Error> {
+        self.users.lookup_phone_numbers(username)
+    }
+
+    async fn lookup_profile(&self, username: &str) -> Result<HashMap<String, String>, Error> {
+        self.users.lookup_profile(username)
+    }
+
+    fn normalize_phone_number(&self, raw: &str) -> Result<String, Error> {
+        self.users.normalize_phone_number(raw)
+    }
+}
+
+/// Reads the `key:value` lines of an htpasswd-style file.
+fn read_entries(path: &Path) -> Result<Vec<(String, String)>, Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| Error::ServerError(format!("Cannot read {}: {}", path.display(), e)))?;
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| match line.split_once(':') {
+            Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.trim().to_string())),
+            _ => Err(Error::ServerError(format!("Invalid line {} in {}", number, path.display()))),
+        })
+        .collect()
+}
@@ -0,0 +1,144 @@
+//! Identity providers.
+//!
+//! The registration service needs three things from wherever its users live:
+//! a password check, the user's phone numbers and the user's profile. The
+//! [`IdentityProvider`] trait captures those, with the LDAP client as the main
+//! implementation. Small sites and development environments can instead keep
+//! their users in a YAML or CSV file ([`super::file`]) or in an htpasswd file
+//! plus a phone number map ([`super::htpasswd`]), selected with
+//! `registration.identity.provider`.
+//!
+//! Errors are reported with the LDAP client's [`Error`] type, so the gRPC layer
+//! maps every provider's failures to the same status codes.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::info;
+
+use super::file::FileIdentityProvider;
+use super::htpasswd::HtpasswdIdentityProvider;
+use super::ldap::{AuthenticatedUser, Error, LdapClient};
+use super::phone::PhoneNormalizer;
+use crate::config::{IdentityConfig, IdentityProviderKind};
+
+/// Source of users, their passwords, phone numbers and profiles.
+#[tonic::async_trait]
+pub trait IdentityProvider: fmt::Debug + Send + Sync {
+    /// Checks a user's password and selects the phone number to verify.
+    ///
+    /// # Arguments
+    /// * `username` - Username as typed by the user
+    /// * `password` - Password to check
+    ///
+    /// # Returns
+    /// * `Result<AuthenticatedUser>` - The user with a phone number, or error
+    async fn authenticate(&self, username: &str, password: &str) -> Result<AuthenticatedUser, Error>;
+
+    /// Checks a user's password; a user without a phone number is returned with
+    /// an empty `phone_number` instead of failing.
+    ///
+    /// # Arguments
+    /// * `username` - Username as typed by the user
+    /// * `password` - Password to check
+    ///
+    /// # Returns
+    /// * `Result<AuthenticatedUser>` - The user, or error
+    async fn authenticate_without_phone(&self, username: &str, password: &str) -> Result<AuthenticatedUser, Error>;
+
+    /// Looks up a user's phone numbers without a password.
+    ///
+    /// # Arguments
+    /// * `username` - Username to look up
+    ///
+    /// # Returns
+    /// * `Result<Vec<String>>` - The user's numbers in E.164 format, or error
+    async fn lookup_phone_numbers(&self, username: &str) -> Result<Vec<String>, Error>;
+
+    /// Looks up a user's profile attributes without a password.
+    ///
+    /// # Arguments
+    /// * `username` - Username to look up
+    ///
+    /// # Returns
+    /// * `Result<HashMap<String, String>>` - The user's profile, or error
+    async fn lookup_profile(&self, username: &str) -> Result<HashMap<String, String>, Error>;
+
+    /// Normalizes a phone number supplied by a user to E.164 format.
+    ///
+    /// # Arguments
+    /// * `raw` - Phone number as typed
+    ///
+    /// # Returns
+    /// * `Result<String>` - The E.164 number, or `InvalidPhoneNumber`
+    fn normalize_phone_number(&self, raw: &str) -> Result<String, Error>;
+}
+
+#[tonic::async_trait]
+impl IdentityProvider for LdapClient {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<AuthenticatedUser, Error> {
+        self.authenticate_user(username, password).await
+    }
+
+    async fn authenticate_without_phone(&self, username: &str, password: &str) -> Result<AuthenticatedUser, Error> {
+        self.authenticate_user_without_phone(username, password).await
+    }
+
+    async fn lookup_phone_numbers(&self, username: &str) -> Result<Vec<String>, Error> {
+        Ok(self.lookup_user(username).await?.phone_numbers)
+    }
+
+    async fn lookup_profile(&self, username: &str) -> Result<HashMap<String, String>, Error> {
+        Ok(self.lookup_user(username).await?.profile)
+    }
+
+    fn normalize_phone_number(&self, raw: &str) -> Result<String, Error> {
+        LdapClient::normalize_phone_number(self, raw)
+    }
+}
+
+/// Creates the configured identity provider.
+///
+/// Without a `provider` setting, the LDAP directory is used when `use_ldap` is
+/// set and the users file otherwise.
+///
+/// # Arguments
+/// * `config` - Identity provider settings
+/// * `ldap_client` - The LDAP client, `None` when `use_ldap` is off
+///
+/// # Returns
+/// * `Result<Arc<dyn IdentityProvider>>` - The provider, or error if it is
+///   misconfigured or its files cannot be loaded
+pub fn from_config<'a>(config: &'a IdentityConfig, ldap_client: Option<LdapClient>) -> Result<Arc<dyn IdentityProvider>, Error> {
+    let kind = config.provider(ldap_client.is_some());
+    let required = |file: &'a Option<String>, key: &str| -> Result<&'a Path, Error> {
+        file.as_deref()
+            .map(Path::new)
+            .ok_or_else(|| Error::ServerError(format!("identity.{} is required for the {:?} identity provider", key, kind)))
+    };
+
+    match kind {
+        IdentityProviderKind::Ldap => {
+            let ldap_client = ldap_client
+                .ok_or_else(|| Error::ServerError("The ldap identity provider requires use_ldap".to_string()))?;
+            info!("Using the LDAP directory as identity provider");
+            Ok(Arc::new(ldap_client))
+        }
+        IdentityProviderKind::File => {
+            let path = required(&config.users_file, "users_file")?;
+            let normalizer = PhoneNormalizer::new(config.default_phone_region.as_deref())?;
+            info!("Using users file {} as identity provider", path.display());
+            Ok(Arc::new(FileIdentityProvider::load(path, normalizer, config.phone_selection)?))
+        }
+        IdentityProviderKind::Htpasswd => {
+            let htpasswd = required(&config.htpasswd_file, "htpasswd_file")?;
+            let phone_map = required(&config.phone_map_file, "phone_map_file")?;
+            let normalizer = PhoneNormalizer::new(config.default_phone_region.as_deref())?;
+            info!("Using htpasswd file {} as identity provider", htpasswd.display());
+            Ok(Arc::new(HtpasswdIdentityProvider::load(htpasswd, phone_map, normalizer, config.phone_selection)?))
+        }
+    }
+}
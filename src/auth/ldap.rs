@@ -8,30 +8,137 @@
 //! @author Joseph G Noonan
 //! @copyright 2025
 use ldap3::{
-    Ldap, LdapConnAsync,
+    parse_filter,
     result::{LdapError as Ldap3Error},
-    Scope, SearchEntry,
+    Mod, Scope, SearchEntry,
 };
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use thiserror::Error;
-use tracing::{debug, error};
-use tokio::sync::Mutex as TokioMutex;
+use tracing::{debug, error, warn};
+
+use super::groups::GroupAuthorizer;
+use super::phone::{self, PhoneNormalizer};
+use super::policy::{self, AccountState, PasswordPolicy};
+pub use crate::config::GroupMembership;
+pub use crate::config::PhoneSelection;
+use super::pool::{LdapPool, PooledConnection};
+use super::search::{DirectorySearch, SearchConfig};
+use super::servers::ServerSelection;
+use super::sync::{ChangeFeed, ChangeTracking};
+use super::tls::LdapTlsConfig;
+use super::username::{DirectoryType, EmailLookup, UsernameForm, UsernameMapping};
+
+/// Active Directory attribute holding the account flags.
+const USER_ACCOUNT_CONTROL: &str = "userAccountControl";
+
+/// ppolicy operational attribute set while an account is locked.
+const PWD_ACCOUNT_LOCKED_TIME: &str = "pwdAccountLockedTime";
+
+/// Attribute listing the groups of a user entry.
+const MEMBER_OF: &str = "memberOf";
 
 /// Configuration for LDAP connection and operations.
 #[derive(Debug, Clone)]
 pub struct LdapConfig {
-    /// LDAP server URL
-    pub url: String,
+    /// LDAP server URLs, in priority order
+    pub urls: Vec<String>,
+    /// How the server for a new connection is chosen
+    pub server_selection: ServerSelection,
+    /// How long a server stays out of rotation after a connection error
+    pub server_cooldown: Duration,
+    /// Retries, on another server where possible, after a connection error
+    pub max_retries: u32,
     /// DN to bind with for initial connection
     pub bind_dn: String,
     /// Password for bind DN
     pub bind_password: String,
     /// Base DN for user searches
     pub base_dn: String,
+    /// Search bases, paging and referral settings
+    pub search: SearchConfig,
     /// Attribute containing username
     pub username_attribute: String,
-    /// Attribute containing phone number
-    pub phone_number_attribute: String,
+    /// Attributes that may contain the phone number, in order of preference
+    pub phone_number_attributes: Vec<String>,
+    /// How to choose between several numbers in the same attribute
+    pub phone_selection: PhoneSelection,
+    /// Extra attributes returned with the authenticated user
+    pub profile_attributes: Vec<String>,
+    /// DNs of the groups whose members may register; empty allows every user
+    pub authorized_groups: Vec<String>,
+    /// How group membership is checked
+    pub group_membership: GroupMembership,
+    /// Search filter template; `{0}` or `%s` is replaced with the escaped username
+    pub user_filter: String,
+    /// How `user@domain` and `DOMAIN\user` names are looked up
+    pub username_mapping: UsernameMapping,
+    /// Region assumed for phone numbers without a country code (e.g. "US")
+    pub default_phone_region: Option<String>,
+    /// Timeout for establishing a connection
+    pub connection_timeout: Duration,
+    /// Timeout for each LDAP operation
+    pub read_timeout: Duration,
+    /// Number of connections opened when the pool is created
+    pub min_pool_size: usize,
+    /// Maximum number of connections the pool may hold
+    pub max_pool_size: usize,
+    /// How long to wait for a free connection when the pool is exhausted
+    pub pool_timeout: Duration,
+    /// Idle time after which a pooled connection is health-checked or evicted
+    pub idle_timeout: Duration,
+    /// TLS settings for the connection
+    pub tls: LdapTlsConfig,
+}
+
+impl From<crate::config::LdapConfig> for LdapConfig {
+    fn from(config: crate::config::LdapConfig) -> Self {
+        let tls = LdapTlsConfig::from_config(&config);
+        let search = SearchConfig::from_config(&config);
+        let username_mapping = UsernameMapping::from_config(&config);
+        let urls = if config.urls.is_empty() {
+            vec![config.url]
+        } else {
+            config.urls
+        };
+        let phone_number_attributes = if config.phone_number_attributes.is_empty() {
+            vec![config.phone_number_attribute]
+        } else {
+            config.phone_number_attributes
+        };
+        let username_attribute = config
+            .username_attribute
+            .unwrap_or_else(|| username_mapping.default_username_attribute().to_string());
+        let user_filter = config
+            .user_filter
+            .unwrap_or_else(|| username_mapping.default_user_filter(&username_attribute));
+        LdapConfig {
+            urls,
+            server_selection: config.server_selection,
+            server_cooldown: Duration::from_millis(config.server_cooldown),
+            max_retries: config.max_retries,
+            bind_dn: config.bind_dn,
+            bind_password: config.bind_password,
+            base_dn: config.base_dn,
+            search,
+            username_attribute,
+            phone_number_attributes,
+            phone_selection: config.phone_selection,
+            profile_attributes: config.profile_attributes,
+            authorized_groups: config.authorized_groups,
+            group_membership: config.group_membership,
+            user_filter,
+            username_mapping,
+            default_phone_region: config.default_phone_region,
+            connection_timeout: Duration::from_millis(config.connection_timeout),
+            read_timeout: Duration::from_millis(config.read_timeout),
+            min_pool_size: config.min_pool_size as usize,
+            max_pool_size: config.max_pool_size as usize,
+            pool_timeout: Duration::from_millis(config.pool_timeout),
+            idle_timeout: Duration::from_millis(config.idle_timeout),
+            tls,
+        }
+    }
 }
 
 /// Errors that can occur during LDAP operations
@@ -41,30 +148,151 @@ pub enum Error {
     Ldap(#[from] Ldap3Error),
     #[error("User not found: {0}")]
     UserNotFound(String),
+    #[error("Multiple directory entries match user: {0}")]
+    AmbiguousUser(String),
+    #[error("Entry {dn} has no {attribute} to use as the username")]
+    UsernameMissing {
+        /// DN of the entry
+        dn: String,
+        /// The configured username attribute
+        attribute: String,
+    },
+    #[error("Search base does not exist: {0}")]
+    SearchBaseMissing(String),
+    #[error("Invalid user filter: {0}")]
+    InvalidFilter(String),
     #[error("Phone number not found in attribute: {0}")]
     PhoneNumberNotFound(String),
     #[error("Phone number is empty")]
     PhoneNumberEmpty,
+    #[error("Invalid phone number: {0}")]
+    InvalidPhoneNumber(String),
+    #[error("User {username} has {} phone numbers", .candidates.len())]
+    AmbiguousPhoneNumber {
+        /// Canonical username of the user
+        username: String,
+        /// DN of the user's directory entry
+        dn: String,
+        /// Distinct E.164 numbers to choose from
+        candidates: Vec<String>,
+        /// Profile attributes of the user
+        profile: HashMap<String, String>,
+    },
     #[error("Authentication failed")]
     AuthenticationFailed,
+    #[error("Account is locked: {0}")]
+    AccountLocked(String),
+    #[error("Account is disabled: {0}")]
+    AccountDisabled(String),
+    #[error("Account has expired: {0}")]
+    AccountExpired(String),
+    #[error("Password must be changed: {0}")]
+    PasswordMustChange(String),
+    #[error("User is not authorized to register: {0}")]
+    NotAuthorized(String),
+    #[error("Timed out waiting for an LDAP connection")]
+    PoolTimeout,
+    #[error("TLS configuration error: {0}")]
+    TlsConfig(String),
     #[error("Server error: {0}")]
     ServerError(String),
 }
 
+impl Error {
+    /// Returns whether the error means the server could not be reached or went
+    /// away, so the operation may succeed on another connection or server.
+    pub fn is_connection_error(&self) -> bool {
+        match self {
+            Error::Ldap(e) => match e {
+                Ldap3Error::Io { .. }
+                | Ldap3Error::OpSend { .. }
+                | Ldap3Error::ResultRecv { .. }
+                | Ldap3Error::IdScrubSend { .. }
+                | Ldap3Error::Timeout { .. }
+                | Ldap3Error::EndOfStream
+                | Ldap3Error::NativeTLS { .. } => true,
+                // busy, unavailable
+                Ldap3Error::LdapResult { result } => matches!(result.rc, 51 | 52),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
 /// Client for LDAP authentication and user operations.
 ///
 /// Provides methods for connecting to LDAP servers, searching for users,
-/// and validating credentials. The client maintains a connection pool
+/// and validating credentials. The client maintains a bounded connection pool
 /// and handles reconnection as needed.
 #[derive(Debug, Clone)]
 pub struct LdapClient {
     config: LdapConfig,
-    pool: Arc<TokioMutex<Vec<Ldap>>>,
+    pool: LdapPool,
+    phone_normalizer: PhoneNormalizer,
+    authorizer: GroupAuthorizer,
+    search: DirectorySearch,
+}
+
+/// A user whose credentials have been verified.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    /// Canonical username: the entry's `username_attribute`, whatever name the
+    /// user signed in with
+    pub username: String,
+    /// DN of the directory entry
+    pub dn: String,
+    /// Name the user bound with: the DN, or the UPN when binding with UPNs
+    pub bind_name: String,
+    /// Phone number in E.164 format; empty from
+    /// [`LdapClient::authenticate_user_without_phone`] when the entry has none
+    pub phone_number: String,
+    /// Values of the configured `profile_attributes` the entry has, keyed by
+    /// the configured attribute name
+    pub profile: HashMap<String, String>,
+}
+
+/// A user as currently recorded in the directory, looked up without a password.
+#[derive(Debug, Clone)]
+pub struct DirectoryUser {
+    /// Canonical username from `username_attribute`
+    pub username: String,
+    /// DN of the directory entry
+    pub dn: String,
+    /// Distinct E.164 phone numbers from the first usable phone attribute
+    pub phone_numbers: Vec<String>,
+    /// Account state from the Active Directory `userAccountControl` flags or,
+    /// on other directories, the ppolicy `pwdAccountLockedTime`
+    pub account_state: Option<AccountState>,
+    /// Values of the configured `profile_attributes` the entry has
+    pub profile: HashMap<String, String>,
+}
+
+/// A user entry found in the directory.
+#[derive(Debug)]
+struct UserEntry {
+    /// DN of the entry
+    dn: String,
+    /// Canonical username from `username_attribute`
+    username: String,
+    /// Name to bind with: the DN, or the UPN when binding with UPNs
+    bind_name: String,
+    /// Distinct E.164 phone numbers from the first usable phone attribute
+    phone_numbers: Vec<String>,
+    /// Why `phone_numbers` is empty; reported only once the caller has decided
+    /// whether a missing number matters
+    phone_error: Option<Error>,
+    /// Values of `memberOf`, when fetched for the group check
+    member_of: Vec<String>,
+    /// State from the `userAccountControl` flags or `pwdAccountLockedTime`
+    account_state: Option<AccountState>,
+    /// Values of the configured profile attributes
+    profile: HashMap<String, String>,
 }
 
 impl LdapClient {
     /// Escapes special characters in LDAP filter values
-    fn escape_ldap_value(value: &str) -> String {
+    pub(crate) fn escape_ldap_value(value: &str) -> String {
         value
             .replace('\\', "\\5c")
             .replace('*', "\\2a")
@@ -74,161 +302,622 @@ impl LdapClient {
             .replace('/', "\\2f")
     }
 
-    /// Creates a new LDAP client and establishes initial connection.
+    /// Substitutes the escaped value for every `{0}` or `%s` placeholder in a
+    /// filter template.
     ///
     /// # Arguments
-    /// * `config` - LDAP configuration including server URL and credentials
+    /// * `template` - Filter template, e.g. `(&(objectClass=person)(uid={0}))`
+    /// * `value` - Raw value to substitute
     ///
     /// # Returns
-    /// * `Result<Self>` - New client instance or error if connection fails
-    pub async fn new(config: LdapConfig) -> Result<Self, Error> {
-        let (conn, ldap) = LdapConnAsync::new(&config.url).await?;
-        
-        tokio::spawn(async move {
-            conn.drive().await.ok();
-        });
-        
-        let pool = Arc::new(TokioMutex::new(vec![ldap]));
-        
-        Ok(Self { config, pool })
+    /// * `Option<String>` - The filter, or `None` if the template has no placeholder
+    fn build_filter(template: &str, value: &str) -> Option<String> {
+        let escaped = Self::escape_ldap_value(value);
+        let mut filter = String::with_capacity(template.len() + escaped.len());
+        let mut rest = template;
+        let mut substituted = false;
+        while let Some(pos) = rest.find(['{', '%']) {
+            filter.push_str(&rest[..pos]);
+            let tail = &rest[pos..];
+            if let Some(after) = tail.strip_prefix("{0}").or_else(|| tail.strip_prefix("%s")) {
+                filter.push_str(&escaped);
+                substituted = true;
+                rest = after;
+            } else {
+                filter.push_str(&tail[..1]);
+                rest = &tail[1..];
+            }
+        }
+        filter.push_str(rest);
+        substituted.then_some(filter)
     }
-    
-    /// Retrieves a connection from the pool or creates a new one if none are available.
+
+    /// Creates a new LDAP client and pre-warms its connection pool.
+    ///
+    /// # Arguments
+    /// * `config` - LDAP configuration including server URL, credentials and pool limits
     ///
     /// # Returns
-    /// * `Result<Ldap>` - LDAP connection instance
-    async fn get_connection(&self) -> Result<Ldap, Error> {
-        let mut pool = self.pool.lock().await;
-        if let Some(ldap) = pool.pop() {
-            Ok(ldap)
-        } else {
-            let (conn, ldap) = LdapConnAsync::new(&self.config.url).await?;
-            tokio::spawn(async move {
-                conn.drive().await.ok();
-            });
-            Ok(ldap)
+    /// * `Result<Self>` - New client instance or error if connection fails
+    pub async fn new(config: LdapConfig) -> Result<Self, Error> {
+        let mapping = &config.username_mapping;
+        let templates = std::iter::once(config.user_filter.clone())
+            .chain(mapping.upn_filter.clone())
+            .chain(mapping.down_level_filter.clone())
+            .chain(mapping.email_lookup.iter().filter_map(EmailLookup::filter_template));
+        for template in templates {
+            let sample = Self::build_filter(&template, "user").ok_or_else(|| {
+                Error::InvalidFilter(format!("{} has no {{0}} or %s placeholder", template))
+            })?;
+            parse_filter(&sample).map_err(|_| Error::InvalidFilter(template.clone()))?;
         }
+
+        let phone_normalizer = PhoneNormalizer::new(config.default_phone_region.as_deref())?;
+        let authorizer = GroupAuthorizer::new(config.authorized_groups.clone(), config.group_membership);
+        let pool = LdapPool::new(config.clone()).await?;
+        let search = DirectorySearch::new(config.search.clone(), config.read_timeout, config.tls.mode, pool.clone());
+
+        Ok(Self { config, pool, phone_normalizer, authorizer, search })
     }
-    
-    /// Returns a connection to the pool after use.
-    async fn return_connection(&self, ldap: Ldap) {
-        let mut pool = self.pool.lock().await;
-        pool.push(ldap);
-    }
-    
+
     /// Authenticates a user against LDAP.
     ///
+    /// The user is looked up on a pooled service-account connection, and the
+    /// password is checked on a dedicated connection that is closed afterwards,
+    /// so the pool never hands out a connection bound as an end user.
+    ///
     /// # Arguments
     /// * `username` - Username to authenticate
     /// * `password` - Password to check
     ///
     /// # Returns
-    /// * `Result<String>` - User's phone number if authentication succeeds
-    pub async fn authenticate_user(&self, username: &str, password: &str) -> Result<String, Error> {
-        let ldap = self.get_connection().await?;
-        
+    /// * `Result<AuthenticatedUser>` - The user's canonical username and phone number in
+    ///   E.164 format if authentication succeeds. With the `reject` phone selection policy, a user with several numbers gets
+    ///   `AmbiguousPhoneNumber` carrying the candidates, but only after the password
+    ///   has been verified. Users outside the authorized groups get `NotAuthorized`;
+    ///   locked, disabled or expired accounts and passwords that must be changed get
+    ///   their own errors instead of `AuthenticationFailed`. Phone number errors are
+    ///   likewise only reported once the password has been verified.
+    pub async fn authenticate_user(&self, username: &str, password: &str) -> Result<AuthenticatedUser, Error> {
+        self.authenticate(username, password, false).await
+    }
+
+    /// Authenticates a user whose entry may hold no phone number, e.g. to let the
+    /// user supply one that is written back after verification.
+    ///
+    /// # Arguments
+    /// * `username` - Username to authenticate
+    /// * `password` - Password to check
+    ///
+    /// # Returns
+    /// * `Result<AuthenticatedUser>` - As [`LdapClient::authenticate_user`], except that
+    ///   an entry without a phone number (`PhoneNumberNotFound` or `PhoneNumberEmpty`)
+    ///   yields the user with an empty `phone_number`
+    pub async fn authenticate_user_without_phone(&self, username: &str, password: &str) -> Result<AuthenticatedUser, Error> {
+        self.authenticate(username, password, true).await
+    }
+
+    /// Shared implementation of the `authenticate_user` variants.
+    async fn authenticate(&self, username: &str, password: &str, phone_optional: bool) -> Result<AuthenticatedUser, Error> {
+        // An empty password would turn the bind into an unauthenticated bind,
+        // which most servers accept without checking anything
+        if password.is_empty() {
+            error!("Empty password supplied for user: {}", username);
+            return Err(Error::AuthenticationFailed);
+        }
+
         // First find the user and get their DN
-        let (user_dn, phone_number, ldap) = self.find_user(ldap, username).await?;
-        
-        // Return the connection to the pool
-        self.return_connection(ldap).await;
+        let user = self
+            .with_failover(false, |mut ldap| async move {
+                let result = self.find_user(&mut ldap, username).await;
+                (ldap, result)
+            })
+            .await?;
         
-        // Get a new connection for user authentication
-        let mut ldap = self.get_connection().await?;
-        
-        // Bind with admin credentials
-        ldap.simple_bind(&self.config.bind_dn, &self.config.bind_password)
-            .await
-            .map_err(|e| {
-                error!("Admin bind failed: {:?}", e);
-                Error::AuthenticationFailed
-            })?.success()?;
-        
-        // Try to bind with user credentials
-        ldap.simple_bind(&user_dn, password)
-            .await
-            .map_err(|e| {
-                error!("User bind failed: {:?}", e);
-                Error::AuthenticationFailed
-            })?.success()?;
+        // Try to bind with user credentials on a dedicated connection, asking
+        // for the password policy control so account problems can be reported
+        let bind_name = user.bind_name.as_str();
+        let result = self
+            .with_failover(true, |mut ldap| async move {
+                ldap.op().with_controls(vec![policy::request_control()]);
+                let result = ldap.simple_bind(bind_name, password)
+                    .await
+                    .inspect_err(|e| error!("User bind failed: {:?}", e));
+                if result.is_ok() {
+                    ldap.op().unbind().await.ok();
+                }
+                (ldap, result.map_err(Error::from))
+            })
+            .await?;
+
+        let password_policy = PasswordPolicy::from_result(&result);
+        if result.rc != 0 {
+            if let Some(state) = policy::bind_failure_state(&result, &password_policy) {
+                error!("User bind rejected for {}: {:?}", user.dn, state);
+                return Err(state.into_error(&user.dn));
+            }
+            if policy::is_invalid_credentials(&result) {
+                error!("Invalid credentials for user: {}", user.dn);
+                return Err(Error::AuthenticationFailed);
+            }
+            result.success()?;
+        }
+        // A successful bind can still require a password change (e.g. after a reset)
+        if let Some(state) = password_policy.state {
+            error!("User bind for {} requires action: {:?}", user.dn, state);
+            return Err(state.into_error(&user.dn));
+        }
+        if let Some(warning) = password_policy.warning {
+            warn!("Password policy warning for {}: {:?}", user.dn, warning);
+        }
+
+        // Only members of the authorized groups may register
+        if self.authorizer.is_enabled() {
+            let user = &user;
+            self.with_failover(false, |mut ldap| async move {
+                let result = self.authorizer.check(&mut ldap, &user.dn, &user.member_of).await;
+                (ldap, result)
+            })
+            .await?;
+        }
 
+        let UserEntry { username, dn, bind_name, phone_numbers, phone_error, profile, .. } = user;
+        let (phone_number, profile) = match phone_error {
+            Some(Error::PhoneNumberNotFound(_) | Error::PhoneNumberEmpty) if phone_optional => {
+                debug!("User bind successful, entry has no phone number");
+                (String::new(), profile)
+            }
+            Some(e) => return Err(e),
+            None => phone::select_phone_number(
+                self.config.phone_selection,
+                &self.phone_normalizer,
+                &username,
+                &dn,
+                phone_numbers,
+                profile,
+                &self.config.phone_number_attributes.join(","),
+            )?,
+        };
         debug!("User bind successful, returning phone number: {}", phone_number);
         
-        // Return the connection to the pool after we're done using it
-        self.return_connection(ldap).await;
-        
-        Ok(phone_number)
+        Ok(AuthenticatedUser {
+            username,
+            dn,
+            bind_name,
+            phone_number,
+            profile,
+        })
     }
 
-    /// Searches for a user and retrieves their DN and phone number.
+    /// Looks a user up by canonical username without checking a password, e.g. to
+    /// re-check a stored registration.
     ///
     /// # Arguments
-    /// * `ldap` - LDAP connection instance
+    /// * `username` - Canonical username to look up
+    ///
+    /// # Returns
+    /// * `Result<DirectoryUser>` - The user's entry; `UserNotFound` if the user is
+    ///   gone, `NotAuthorized` if outside the authorized groups, or a phone number
+    ///   error if the entry no longer holds a usable number
+    pub async fn lookup_user(&self, username: &str) -> Result<DirectoryUser, Error> {
+        let user = self
+            .with_failover(false, |mut ldap| async move {
+                let result = self.find_user(&mut ldap, username).await;
+                (ldap, result)
+            })
+            .await?;
+
+        if self.authorizer.is_enabled() {
+            let user = &user;
+            self.with_failover(false, |mut ldap| async move {
+                let result = self.authorizer.check(&mut ldap, &user.dn, &user.member_of).await;
+                (ldap, result)
+            })
+            .await?;
+        }
+        if let Some(e) = user.phone_error {
+            return Err(e);
+        }
+
+        Ok(DirectoryUser {
+            account_state: user.account_state,
+            username: user.username,
+            dn: user.dn,
+            phone_numbers: user.phone_numbers,
+            profile: user.profile,
+        })
+    }
+
+    /// Reads the username of the entry with the given DN.
+    ///
+    /// # Arguments
+    /// * `dn` - DN of the entry
+    ///
+    /// # Returns
+    /// * `Result<Option<String>>` - The entry's `username_attribute`, or `None` if the
+    ///   entry does not exist or has none
+    pub async fn username_for_dn(&self, dn: &str) -> Result<Option<String>, Error> {
+        let attribute = self.config.username_attribute.as_str();
+        self.with_failover(false, |mut ldap| async move {
+            let result = match ldap.op().search(dn, Scope::Base, "(objectClass=*)", vec![attribute]).await {
+                Ok(result) if result.1.rc == 32 => Ok(None),
+                Ok(result) => result.success().map_err(Error::from).map(|(entries, _)| {
+                    entries.into_iter().next().and_then(|entry| {
+                        SearchEntry::construct(entry)
+                            .attrs
+                            .into_iter()
+                            .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+                            .and_then(|(_, values)| values.into_iter().next())
+                    })
+                }),
+                Err(e) => Err(Error::from(e)),
+            };
+            (ldap, result)
+        })
+        .await
+    }
+
+    /// Returns the attribute phone numbers are preferably read from.
+    pub fn phone_number_attribute(&self) -> &str {
+        self.config.phone_number_attributes.first().map(String::as_str).unwrap_or_default()
+    }
+
+    /// Normalizes a phone number, e.g. one supplied by a user, to E.164 format.
+    ///
+    /// # Arguments
+    /// * `raw` - Phone number as typed
+    ///
+    /// # Returns
+    /// * `Result<String>` - The E.164 number, or `InvalidPhoneNumber`
+    pub fn normalize_phone_number(&self, raw: &str) -> Result<String, Error> {
+        self.phone_normalizer.normalize(raw)
+    }
+
+    /// Replaces the values of a phone number attribute on a user entry.
+    ///
+    /// Without credentials, the modify runs on a pooled service-account
+    /// connection. With credentials, it runs on a dedicated connection bound as
+    /// the user, which the directory's ACLs must allow to write the attribute.
+    ///
+    /// # Arguments
+    /// * `dn` - DN of the user entry
+    /// * `attribute` - Attribute to write
+    /// * `phone_number` - E.164 number to store
+    /// * `credentials` - Bind name and password of the user, or `None` for the service account
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success, or the error returned by the bind or modify
+    pub async fn write_phone_number(
+        &self,
+        dn: &str,
+        attribute: &str,
+        phone_number: &str,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<(), Error> {
+        let changes = || vec![Mod::Replace(attribute, HashSet::from([phone_number]))];
+        match credentials {
+            None => {
+                self.with_failover(false, |mut ldap| async move {
+                    let result = match ldap.op().modify(dn, changes()).await {
+                        Ok(result) => result.success().map(|_| ()).map_err(Error::from),
+                        Err(e) => Err(Error::from(e)),
+                    };
+                    (ldap, result)
+                })
+                .await
+            }
+            Some((bind_name, password)) => {
+                self.with_failover(true, |mut ldap| async move {
+                    let result = async {
+                        ldap.simple_bind(bind_name, password).await?.success()?;
+                        ldap.op().modify(dn, changes()).await?.success()?;
+                        ldap.op().unbind().await.ok();
+                        Ok(())
+                    }
+                    .await
+                    .inspect_err(|e: &Error| error!("Phone number write as {} failed: {}", bind_name, e));
+                    (ldap, result)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Creates a feed of changes to the user entries under the search bases.
+    ///
+    /// # Arguments
+    /// * `config` - Change tracking settings; `auto` picks DirSync in Active
+    ///   Directory mode and syncrepl otherwise
+    ///
+    /// # Returns
+    /// * `ChangeFeed` - The feed, not yet connected
+    pub fn change_feed(&self, config: &crate::config::WatchConfig) -> ChangeFeed {
+        let ad = self.config.username_mapping.directory_type == DirectoryType::ActiveDirectory;
+        let mode = match config.mode {
+            ChangeTracking::Auto if ad => ChangeTracking::DirSync,
+            ChangeTracking::Auto => ChangeTracking::Syncrepl,
+            mode => mode,
+        };
+        let filter = config.filter.clone().unwrap_or_else(|| {
+            if ad { "(objectClass=user)" } else { "(objectClass=person)" }.to_string()
+        });
+        let mut attributes = vec![self.config.username_attribute.clone(), USER_ACCOUNT_CONTROL.to_string()];
+        if !ad {
+            // Locking an OpenLDAP account only changes this operational attribute
+            attributes.push(PWD_ACCOUNT_LOCKED_TIME.to_string());
+        }
+        attributes.extend(self.config.phone_number_attributes.iter().cloned());
+
+        ChangeFeed::new(
+            self.pool.clone(),
+            self.search.bases().to_vec(),
+            filter,
+            attributes,
+            self.config.username_attribute.clone(),
+            mode,
+            Duration::from_secs(config.poll_interval_secs.max(1)),
+            self.config.read_timeout,
+        )
+    }
+
+    /// Runs an operation on a connection from the pool, retrying on a fresh
+    /// connection (and, through the pool, another server) after a connection
+    /// error, up to `max_retries` times.
+    ///
+    /// # Arguments
+    /// * `dedicated` - Whether the operation needs a dedicated, unbound connection
+    /// * `op` - The operation; it hands the connection back with its result
+    ///
+    /// # Returns
+    /// * `Result<T>` - Result of the last attempt
+    async fn with_failover<T, F, Fut>(&self, dedicated: bool, mut op: F) -> Result<T, Error>
+    where
+        F: FnMut(PooledConnection) -> Fut,
+        Fut: std::future::Future<Output = (PooledConnection, Result<T, Error>)>,
+    {
+        let mut attempt = 0;
+        loop {
+            let ldap = if dedicated {
+                self.pool.get_dedicated().await?
+            } else {
+                self.pool.get().await?
+            };
+            match op(ldap).await {
+                (ldap, Err(e)) if e.is_connection_error() && attempt < self.config.max_retries => {
+                    warn!("LDAP operation on {} failed, retrying: {}", ldap.server_url(), e);
+                    ldap.mark_failed();
+                    attempt += 1;
+                }
+                (_, result) => return result,
+            }
+        }
+    }
+
+    /// Searches for a user and retrieves their DN, canonical username,
+    /// E.164-normalized phone numbers, profile attributes and, when needed for the
+    /// group check, their `memberOf` values.
+    ///
+    /// The username is mapped to a filter and search bases according to its form
+    /// (`user@domain`, `DOMAIN\user` or plain); see [`UsernameMapping`]. Email-style
+    /// names go through the `email_lookup` chain, and the first lookup that finds
+    /// an entry wins.
+    ///
+    /// The phone number attributes are tried in order and the first one holding
+    /// at least one valid number wins; all of its distinct numbers are returned.
+    /// An entry without a usable number is still returned, with the reason in
+    /// `phone_error`.
+    ///
+    /// # Arguments
+    /// * `ldap` - Pooled LDAP connection
     /// * `username` - Username to search for
     ///
     /// # Returns
-    /// * `Result<(String, String, Ldap)>` - User's DN, phone number, and LDAP connection instance
-    async fn find_user(&self, mut ldap: Ldap, username: &str) -> Result<(String, String, Ldap), Error> {
+    /// * `Result<UserEntry>` - The user's entry, `UserNotFound`, or `AmbiguousUser`
+    ///   if a lookup matches more than one entry
+    async fn find_user(&self, ldap: &mut PooledConnection, username: &str) -> Result<UserEntry, Error> {
         debug!("Input username: {}", username);
         
-        // Extract username from email if email format is used
-        let clean_username = if username.contains('@') {
-            debug!("Email format detected, extracting username part");
-            username.split('@').next().unwrap_or(username)
-        } else {
-            username
+        // Pick the filter template, value and bind name for the form of the
+        // username, and the bases to search
+        let mapping = &self.config.username_mapping;
+        let mut bases = self.search.bases().to_vec();
+        let lookups: Vec<(String, &str, bool)> = match mapping.classify(username) {
+            UsernameForm::Upn { local } => {
+                debug!("Email format detected, trying {:?}", mapping.email_lookup);
+                mapping
+                    .email_lookup
+                    .iter()
+                    .map(|step| match (step.filter_template(), &mapping.upn_filter) {
+                        (Some(template), _) => (template, username, false),
+                        (None, Some(filter)) => (filter.clone(), username, mapping.bind_with_upn),
+                        (None, None) => (self.config.user_filter.clone(), local, false),
+                    })
+                    .collect()
+            }
+            UsernameForm::DownLevel { domain, user } => {
+                debug!("Down-level format detected for domain: {}", domain);
+                bases = mapping.domain_bases(domain, &bases).ok_or_else(|| {
+                    error!("Domain {} is not configured for user: {}", domain, username);
+                    Error::UserNotFound(username.to_string())
+                })?;
+                let template = mapping.down_level_filter.as_ref().unwrap_or(&self.config.user_filter);
+                vec![(template.clone(), user, false)]
+            }
+            UsernameForm::Plain => vec![(self.config.user_filter.clone(), username, false)],
         };
-        debug!("Clean username (without domain): {}", clean_username);
         
-        // Escape special characters in the username for LDAP filter
-        let escaped_username = Self::escape_ldap_value(clean_username);
-        debug!("Escaped username: {}", escaped_username);
-        
-        // Construct LDAP filter
-        let filter = format!("({}={})", self.config.username_attribute, escaped_username);
-        debug!("LDAP search parameters:");
-        debug!("  Base DN: {}", self.config.base_dn);
-        debug!("  Username attribute: {}", self.config.username_attribute);
-        debug!("  Filter: {}", filter);
-        debug!("  Phone number attribute: {}", self.config.phone_number_attribute);
-        
-        let (mut entries, result) = ldap.search(
-            &self.config.base_dn,
-            Scope::Subtree,
-            &filter,
-            vec![&self.config.phone_number_attribute],
-        ).await.map_err(|e| {
-            error!("LDAP search failed: {:?}", e);
-            Error::ServerError(e.to_string())
-        })?.success()?;
-        
-        debug!("LDAP search result: {:?}", result);
-        debug!("Number of entries found: {}", entries.len());
-        
-        if entries.is_empty() {
-            error!("No user found with username: {}", username);
-            return Err(Error::UserNotFound(username.to_string()));
+        let mut attributes = self.config.phone_number_attributes.clone();
+        attributes.extend(self.authorizer.user_attributes().iter().map(|a| a.to_string()));
+        attributes.push(USER_ACCOUNT_CONTROL.to_string());
+        if mapping.directory_type != DirectoryType::ActiveDirectory {
+            // Operational, so only returned when asked for
+            attributes.push(PWD_ACCOUNT_LOCKED_TIME.to_string());
         }
+        attributes.push(self.config.username_attribute.clone());
+        attributes.extend(self.config.profile_attributes.iter().cloned());
         
-        let entry = SearchEntry::construct(entries.remove(0));
-        let user_dn = entry.dn;
-        debug!("Found user entry with DN: {}", user_dn);
-        
-        // Extract phone number from the attributes
-        let phone_number = entry.attrs
-            .get(&self.config.phone_number_attribute)
-            .and_then(|vals: &Vec<String>| vals.first().map(|v| v.to_string()))  
-            .ok_or_else(|| {
-                error!("Phone number attribute not found");
-                Error::PhoneNumberNotFound(self.config.phone_number_attribute.clone())
+        for (template, value, bind_upn) in lookups {
+            // Build the search filter from the template, escaping the username
+            let filter = Self::build_filter(&template, value)
+                .ok_or_else(|| Error::InvalidFilter(template.clone()))?;
+            debug!("LDAP search parameters:");
+            debug!("  Search bases: {:?}", bases);
+            debug!("  Filter template: {}", template);
+            debug!("  Filter: {}", filter);
+            debug!("  Phone number attributes: {:?}", self.config.phone_number_attributes);
+            
+            let results = self.search.search(ldap, &bases, &filter, &attributes).await.inspect_err(|e| {
+                error!("LDAP search failed: {:?}", e);
             })?;
+            
+            // The first base (in order) with a match wins
+            let Some((base, mut entries)) = bases.iter().zip(results).find(|(_, entries)| !entries.is_empty()) else {
+                continue;
+            };
+            debug!("Number of entries found under {}: {}", base, entries.len());
+            if entries.len() > 1 {
+                error!("{} entries match username: {} with filter {}", entries.len(), username, filter);
+                return Err(Error::AmbiguousUser(username.to_string()));
+            }
+            
+            let entry = entries.remove(0);
+            debug!("Found user entry with DN: {}", entry.dn);
+            return self.user_entry(entry, username, bind_upn);
+        }
         
-        if phone_number.trim().is_empty() {
+        error!("No user found with username: {}", username);
+        Err(Error::UserNotFound(username.to_string()))
+    }
+
+    /// Builds a [`UserEntry`] from the entry found by [`LdapClient::find_user`].
+    ///
+    /// # Arguments
+    /// * `entry` - The user's directory entry
+    /// * `username` - Name the user signed in with
+    /// * `bind_upn` - Whether to bind with that name instead of the DN
+    fn user_entry(&self, mut entry: SearchEntry, username: &str, bind_upn: bool) -> Result<UserEntry, Error> {
+        let (phone_numbers, phone_error) = match self.extract_phone_numbers(&entry.attrs) {
+            Ok(phone_numbers) => (phone_numbers, None),
+            Err(e) => (Vec::new(), Some(e)),
+        };
+        debug!("Found phone numbers: {:?}", phone_numbers);
+        let first_value = |attribute: &str| {
+            entry
+                .attrs
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+                .and_then(|(_, values)| values.first())
+        };
+        // Active Directory keeps the account flags in userAccountControl; ppolicy
+        // servers such as OpenLDAP lock and disable accounts with pwdAccountLockedTime
+        let account_state = first_value(USER_ACCOUNT_CONTROL)
+            .and_then(|value| value.parse::<i64>().ok())
+            .and_then(|value| policy::account_state_for_flags(value as u32))
+            .or_else(|| first_value(PWD_ACCOUNT_LOCKED_TIME).map(|value| policy::account_state_for_locked_time(value)));
+        // Registrations, sessions and lockouts are keyed on this name, so it must
+        // come from the entry whatever name the user typed
+        let Some(canonical) = entry
+            .attrs
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&self.config.username_attribute))
+            .and_then(|(_, values)| values.first())
+            .cloned()
+        else {
+            error!("Entry {} has no {}, refusing sign-in as {}", entry.dn, self.config.username_attribute, username);
+            return Err(Error::UsernameMissing { dn: entry.dn, attribute: self.config.username_attribute.clone() });
+        };
+        let profile = self.extract_profile(&entry.attrs);
+        let member_of = entry
+            .attrs
+            .keys()
+            .find(|name| name.eq_ignore_ascii_case(MEMBER_OF))
+            .cloned()
+            .and_then(|name| entry.attrs.remove(&name))
+            .unwrap_or_default();
+        Ok(UserEntry {
+            bind_name: if bind_upn { username.to_string() } else { entry.dn.clone() },
+            username: canonical,
+            phone_numbers,
+            phone_error,
+            member_of,
+            account_state,
+            profile,
+            dn: entry.dn,
+        })
+    }
+
+    /// Extracts the configured profile attributes. Attribute names are matched
+    /// case-insensitively and only the first value of each is kept.
+    ///
+    /// # Arguments
+    /// * `attrs` - Attributes of the user entry
+    ///
+    /// # Returns
+    /// * `HashMap<String, String>` - Values keyed by the configured attribute name
+    fn extract_profile(&self, attrs: &HashMap<String, Vec<String>>) -> HashMap<String, String> {
+        self.config
+            .profile_attributes
+            .iter()
+            .filter_map(|attribute| {
+                attrs
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+                    .and_then(|(_, values)| values.first())
+                    .filter(|value| !value.trim().is_empty())
+                    .map(|value| (attribute.clone(), value.clone()))
+            })
+            .collect()
+    }
+
+    /// Extracts the normalized phone numbers from the first usable attribute.
+    ///
+    /// # Arguments
+    /// * `attrs` - Attributes of the user entry
+    ///
+    /// # Returns
+    /// * `Result<Vec<String>>` - Distinct E.164 numbers, never empty
+    fn extract_phone_numbers(&self, attrs: &HashMap<String, Vec<String>>) -> Result<Vec<String>, Error> {
+        let mut found_empty = false;
+        let mut first_invalid = None;
+
+        for attribute in &self.config.phone_number_attributes {
+            let Some(values) = attrs
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+                .map(|(_, values)| values)
+            else {
+                debug!("Phone number attribute {} not present", attribute);
+                continue;
+            };
+
+            let mut phone_numbers: Vec<String> = Vec::new();
+            for value in values {
+                if value.trim().is_empty() {
+                    found_empty = true;
+                    continue;
+                }
+                match self.phone_normalizer.normalize(value) {
+                    Ok(number) if !phone_numbers.contains(&number) => phone_numbers.push(number),
+                    Ok(_) => {}
+                    Err(e) => {
+                        error!("Phone number in {} is not valid: {}", attribute, value);
+                        first_invalid.get_or_insert(e);
+                    }
+                }
+            }
+            if !phone_numbers.is_empty() {
+                return Ok(phone_numbers);
+            }
+        }
+
+        if let Some(e) = first_invalid {
+            return Err(e);
+        }
+        if found_empty {
             error!("Phone number is empty for user");
             return Err(Error::PhoneNumberEmpty);
         }
-        
-        debug!("Found phone number: {}", phone_number);
-        Ok((user_dn, phone_number, ldap))
-   }
+        error!("Phone number attribute not found");
+        Err(Error::PhoneNumberNotFound(self.config.phone_number_attributes.join(",")))
+    }
 }
@@ -0,0 +1,282 @@
+//! Failed sign-in throttling.
+//!
+//! Every RPC that takes a password would otherwise let a client try passwords
+//! against the directory as fast as it can, either to guess one or to trip the
+//! directory's own lockout policy and lock the user out. This module counts
+//! failed sign-ins per account name and per client address, independently of
+//! the directory. Past `free_attempts` failures, each further attempt has to
+//! wait an exponentially growing delay, and at the lockout threshold the key is
+//! locked for `lockout_secs`. Throttled attempts are rejected before they reach
+//! the directory.
+//!
+//! Usernames are counted under their account name, so `alice`, `alice@corp.example`
+//! and `CORP\alice` share one budget; an email alias with another local part is
+//! counted apart, capped only by its address. Attempts admitted by [`LoginLockout::check`]
+//! count as in flight until their outcome is recorded: past `free_attempts`, a
+//! key has one attempt in flight at a time, so parallel requests cannot all get
+//! through before the first failure is recorded.
+//!
+//! A successful sign-in clears the username's count but not the address's, so
+//! an attacker cannot reset their address by signing in to their own account.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+pub use crate::config::LoginLockoutConfig;
+
+use super::ldap::{AuthenticatedUser, Error};
+use super::username::account_name;
+
+/// How long a client refused because of an attempt in flight is told to wait.
+const IN_FLIGHT_RETRY: Duration = Duration::from_secs(1);
+
+/// Why a sign-in attempt was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttled {
+    /// How long the client must wait before trying again
+    pub retry_after: Duration,
+    /// Whether the key is locked out rather than backing off
+    pub locked: bool,
+}
+
+/// Failure count of one username or address.
+#[derive(Debug)]
+struct FailureEntry {
+    /// Failures since the count last started over
+    failures: u32,
+    /// When the last failure happened, or the entry was created
+    last_failure: Instant,
+    /// Attempts are refused until then
+    blocked_until: Option<Instant>,
+    /// Admitted attempts whose outcome is not recorded yet
+    in_flight: u32,
+}
+
+impl FailureEntry {
+    fn new(now: Instant) -> Self {
+        Self { failures: 0, last_failure: now, blocked_until: None, in_flight: 0 }
+    }
+}
+
+/// A sign-in attempt admitted by [`LoginLockout::check`]. It counts as in
+/// flight until its outcome is recorded or it is dropped, e.g. when the
+/// request is cancelled.
+#[derive(Debug)]
+#[must_use = "an attempt counts as in flight until its outcome is recorded"]
+pub struct Attempt<'a> {
+    lockout: &'a LoginLockout,
+    username: String,
+    address: Option<IpAddr>,
+}
+
+impl Attempt<'_> {
+    /// Records the outcome of the attempt; see [`LoginLockout::record`].
+    ///
+    /// # Arguments
+    /// * `result` - Result of the authentication
+    pub async fn record(self, result: &Result<AuthenticatedUser, Error>) {
+        self.lockout.record(&self.username, self.address, result).await;
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        let mut entries = self.lockout.lock();
+        for key in self.lockout.keys(&self.username, self.address) {
+            if let Some(entry) = entries.get_mut(&key) {
+                entry.in_flight = entry.in_flight.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// Counts failed sign-ins and throttles the usernames and addresses they come from.
+#[derive(Debug)]
+pub struct LoginLockout {
+    config: LoginLockoutConfig,
+    /// Entries keyed by `user:<normalized username>` or `addr:<address>`
+    entries: Mutex<HashMap<String, FailureEntry>>,
+}
+
+impl LoginLockout {
+    /// Creates a tracker with no recorded failures.
+    ///
+    /// # Arguments
+    /// * `config` - Backoff and lockout settings
+    pub fn new(config: LoginLockoutConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the metadata header naming the client address, if one is configured.
+    pub fn client_address_header(&self) -> Option<&str> {
+        self.config.client_address_header.as_deref()
+    }
+
+    /// Checks whether a sign-in attempt may go ahead, and counts it as in
+    /// flight if so.
+    ///
+    /// # Arguments
+    /// * `username` - Username as typed by the user
+    /// * `address` - Client address, if known
+    ///
+    /// # Returns
+    /// * `Result<Attempt, Throttled>` - The attempt, to record its outcome with, or
+    ///   how long the longer-blocked of the two keys must wait
+    pub async fn check(&self, username: &str, address: Option<IpAddr>) -> Result<Attempt<'_>, Throttled> {
+        let now = Instant::now();
+        let mut entries = self.lock();
+        self.prune(&mut entries, now);
+
+        let keys = self.keys(username, address);
+        let throttled = keys
+            .iter()
+            .filter_map(|key| {
+                let entry = entries.get(key)?;
+                if let Some(until) = entry.blocked_until.filter(|until| *until > now) {
+                    return Some(Throttled {
+                        retry_after: until - now,
+                        locked: entry.failures >= self.threshold(key),
+                    });
+                }
+                // Past the free attempts, wait for the outcome of the one in flight
+                (entry.in_flight > 0 && entry.failures + entry.in_flight >= self.config.free_attempts)
+                    .then_some(Throttled { retry_after: IN_FLIGHT_RETRY, locked: false })
+            })
+            .max_by_key(|throttled| throttled.retry_after);
+        if let Some(throttled) = throttled {
+            return Err(throttled);
+        }
+
+        for key in keys {
+            entries.entry(key).or_insert_with(|| FailureEntry::new(now)).in_flight += 1;
+        }
+        Ok(Attempt { lockout: self, username: username.to_string(), address })
+    }
+
+    /// Records a failed sign-in for the username and the address.
+    ///
+    /// # Arguments
+    /// * `username` - Username as typed by the user
+    /// * `address` - Client address, if known
+    pub async fn record_failure(&self, username: &str, address: Option<IpAddr>) {
+        let now = Instant::now();
+        let mut entries = self.lock();
+
+        for key in self.keys(username, address) {
+            let threshold = self.threshold(&key);
+            let entry = entries.entry(key.clone()).or_insert_with(|| FailureEntry::new(now));
+            entry.failures += 1;
+            entry.last_failure = now;
+
+            if entry.failures >= threshold {
+                entry.blocked_until = Some(now + Duration::from_secs(self.config.lockout_secs));
+                if entry.failures == threshold {
+                    warn!(target: "audit", "Sign-in locked out: {} failures={} lockout_secs={}", key, entry.failures, self.config.lockout_secs);
+                }
+            } else if entry.failures > self.config.free_attempts {
+                let delay = self.backoff(entry.failures - self.config.free_attempts);
+                entry.blocked_until = Some(now + delay);
+                warn!("Sign-in backing off: {} failures={} delay_secs={}", key, entry.failures, delay.as_secs());
+            }
+        }
+    }
+
+    /// Records the outcome of a sign-in attempt. Wrong passwords, unknown users and
+    /// locked, disabled or expired accounts count as failures, so account states
+    /// cannot be probed without limit; a verified password, even one followed by
+    /// a request to pick a phone number, counts as success. Other errors are not
+    /// counted.
+    ///
+    /// # Arguments
+    /// * `username` - Username as typed by the user
+    /// * `address` - Client address, if known
+    /// * `result` - Result of the authentication
+    pub async fn record(&self, username: &str, address: Option<IpAddr>, result: &Result<AuthenticatedUser, Error>) {
+        match result {
+            Ok(_) | Err(Error::AmbiguousPhoneNumber { .. }) => self.record_success(username).await,
+            Err(
+                Error::AuthenticationFailed
+                | Error::UserNotFound(_)
+                | Error::AccountLocked(_)
+                | Error::AccountDisabled(_)
+                | Error::AccountExpired(_)
+                | Error::PasswordMustChange(_),
+            ) => self.record_failure(username, address).await,
+            Err(_) => {}
+        }
+    }
+
+    /// Clears the username's failures after a successful sign-in.
+    ///
+    /// # Arguments
+    /// * `username` - Username as typed by the user
+    pub async fn record_success(&self, username: &str) {
+        let mut entries = self.lock();
+        let key = Self::user_key(username);
+        // Keep counting the attempts still in flight
+        match entries.get_mut(&key) {
+            Some(entry) if entry.in_flight > 0 => {
+                entry.failures = 0;
+                entry.blocked_until = None;
+            }
+            _ => {
+                entries.remove(&key);
+            }
+        }
+    }
+
+    /// Locks the entries, ignoring poison: no update leaves them inconsistent.
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, FailureEntry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the delay after the given number of failures past `free_attempts`.
+    fn backoff(&self, excess: u32) -> Duration {
+        let secs = self
+            .config
+            .backoff_base_secs
+            .saturating_mul(1u64.checked_shl(excess - 1).unwrap_or(u64::MAX));
+        Duration::from_secs(secs.min(self.config.backoff_max_secs))
+    }
+
+    /// Drops entries that are neither blocked nor recent.
+    fn prune(&self, entries: &mut HashMap<String, FailureEntry>, now: Instant) {
+        let reset_after = Duration::from_secs(self.config.reset_after_secs);
+        entries.retain(|_, entry| {
+            entry.in_flight > 0
+                || entry.blocked_until.is_some_and(|until| until > now)
+                || now.duration_since(entry.last_failure) < reset_after
+        });
+    }
+
+    /// Returns the lockout threshold for a key.
+    fn threshold(&self, key: &str) -> u32 {
+        if key.starts_with("addr:") {
+            self.config.address_lockout_threshold
+        } else {
+            self.config.lockout_threshold
+        }
+    }
+
+    /// Returns the keys an attempt is counted under.
+    fn keys(&self, username: &str, address: Option<IpAddr>) -> Vec<String> {
+        std::iter::once(Self::user_key(username))
+            .chain(address.map(|address| format!("addr:{}", address)))
+            .collect()
+    }
+
+    /// Returns the key of a username: its account name, trimmed and lowercased,
+    /// since the directory matches usernames case-insensitively and resolves
+    /// `user@domain` and `DOMAIN\user` to the same entry as `user`.
+    fn user_key(username: &str) -> String {
+        format!("user:{}", account_name(username.trim()).trim().to_lowercase())
+    }
+}
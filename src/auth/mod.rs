@@ -1,3 +1,30 @@
+pub mod file;
+pub mod groups;
+pub mod htpasswd;
+pub mod identity;
 pub mod ldap;
+pub mod lockout;
+pub mod password;
+pub mod phone;
+pub mod policy;
+pub mod pool;
+pub mod search;
+pub mod servers;
+pub mod sync;
+pub mod tls;
+pub mod username;
 
-pub use ldap::{LdapClient, LdapConfig};
+pub use file::FileIdentityProvider;
+pub use groups::{GroupAuthorizer, GroupMembership};
+pub use htpasswd::HtpasswdIdentityProvider;
+pub use identity::IdentityProvider;
+pub use ldap::{AuthenticatedUser, DirectoryUser, LdapClient, LdapConfig};
+pub use lockout::LoginLockout;
+pub use phone::{mask_phone_number, PhoneNormalizer};
+pub use policy::{AccountState, PasswordPolicy};
+pub use pool::{LdapPool, PooledConnection};
+pub use search::{DirectorySearch, SearchConfig, SearchMode};
+pub use servers::{ServerSelection, ServerSet};
+pub use sync::{ChangeFeed, ChangeTracking, DirectoryChange, SyncEvent};
+pub use tls::{LdapTlsConfig, TlsMode};
+pub use username::{DirectoryType, UsernameMapping};
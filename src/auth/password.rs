@@ -0,0 +1,142 @@
+//! Password hash verification for the file-based identity providers.
+//!
+//! Users files carry bcrypt (`$2a$`, `$2b$`, `$2y$`) or argon2 (`$argon2id$`
+//! and friends, in PHC string format) hashes. htpasswd files may also hold the
+//! older Apache formats: `$apr1$` (MD5-crypt) and `{SHA}` (unsalted SHA-1).
+//! Plain-text and `crypt(3)` DES entries are not accepted.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base64::Engine;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+
+/// Characters of the crypt(3) base-64 alphabet.
+const CRYPT_ALPHABET: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Password hash formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashFormat {
+    /// bcrypt
+    Bcrypt,
+    /// argon2 in PHC string format
+    Argon2,
+    /// Apache MD5-crypt
+    Apr1,
+    /// Base64-encoded, unsalted SHA-1
+    Sha1,
+}
+
+impl HashFormat {
+    /// Detects the format of a hash from its prefix.
+    ///
+    /// # Arguments
+    /// * `hash` - Hash as stored in the users file
+    ///
+    /// # Returns
+    /// * `Option<HashFormat>` - The format, or `None` if it is not supported
+    pub fn detect(hash: &str) -> Option<Self> {
+        if ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix)) {
+            Some(HashFormat::Bcrypt)
+        } else if hash.starts_with("$argon2") {
+            Some(HashFormat::Argon2)
+        } else if hash.starts_with("$apr1$") {
+            Some(HashFormat::Apr1)
+        } else if hash.starts_with("{SHA}") {
+            Some(HashFormat::Sha1)
+        } else {
+            None
+        }
+    }
+}
+
+/// Checks a password against a stored hash.
+///
+/// This is CPU-bound by design; call it from a blocking task.
+///
+/// # Arguments
+/// * `password` - Password to check
+/// * `hash` - Hash as stored in the users file
+///
+/// # Returns
+/// * `bool` - Whether the password matches; `false` for malformed or unsupported hashes
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match HashFormat::detect(hash) {
+        Some(HashFormat::Bcrypt) => bcrypt::verify(password, hash).unwrap_or(false),
+        Some(HashFormat::Argon2) => PasswordHash::new(hash)
+            .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+            .unwrap_or(false),
+        Some(HashFormat::Apr1) => {
+            let salt = hash["$apr1$".len()..].split('$').next().unwrap_or_default();
+            constant_time_eq(apr1(password, salt).as_bytes(), hash.as_bytes())
+        }
+        Some(HashFormat::Sha1) => {
+            let digest = base64::engine::general_purpose::STANDARD.encode(Sha1::digest(password.as_bytes()));
+            constant_time_eq(digest.as_bytes(), &hash.as_bytes()["{SHA}".len()..])
+        }
+        None => false,
+    }
+}
+
+/// Computes an Apache MD5-crypt hash, `$apr1$<salt>$<digest>`.
+fn apr1(password: &str, salt: &str) -> String {
+    const MAGIC: &str = "$apr1$";
+    let password = password.as_bytes();
+    let salt = &salt.as_bytes()[..salt.len().min(8)];
+
+    let alternate = Md5::new().chain_update(password).chain_update(salt).chain_update(password).finalize();
+    let mut context = Md5::new().chain_update(password).chain_update(MAGIC).chain_update(salt);
+    for chunk in (0..password.len()).step_by(16) {
+        context.update(&alternate[..(password.len() - chunk).min(16)]);
+    }
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            context.update([0u8]);
+        } else {
+            context.update(&password[..1]);
+        }
+        length >>= 1;
+    }
+    let mut digest = context.finalize();
+
+    for round in 0u32..1000 {
+        let mut context = Md5::new();
+        if round & 1 == 1 {
+            context.update(password);
+        } else {
+            context.update(digest);
+        }
+        if !round.is_multiple_of(3) {
+            context.update(salt);
+        }
+        if !round.is_multiple_of(7) {
+            context.update(password);
+        }
+        if round & 1 == 1 {
+            context.update(digest);
+        } else {
+            context.update(password);
+        }
+        digest = context.finalize();
+    }
+
+    let mut encoded = String::with_capacity(22);
+    let mut push = |value: u32, count: usize| {
+        for i in 0..count {
+            encoded.push(CRYPT_ALPHABET[((value >> (6 * i)) & 0x3f) as usize] as char);
+        }
+    };
+    for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        push((digest[a] as u32) << 16 | (digest[b] as u32) << 8 | digest[c] as u32, 4);
+    }
+    push(digest[11] as u32, 2);
+
+    format!("{}{}${}", MAGIC, String::from_utf8_lossy(salt), encoded)
+}
+
+/// Compares two byte strings without stopping at the first difference.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,152 @@
+//! Phone number normalization.
+//!
+//! Directory phone attributes are free-form, so the same number can appear as
+//! "(555) 123-4567", "+1 555 123 4567" or "15551234567". This module parses and
+//! validates them with the `phonenumber` crate and formats them as E.164, so the
+//! rate limiter, Twilio and DynamoDB all see a single canonical form.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use phonenumber::{country, metadata::DATABASE, Mode, Type};
+use std::collections::HashMap;
+use tracing::debug;
+
+pub use crate::config::PhoneSelection;
+
+use super::ldap::Error;
+
+/// Parses and formats phone numbers as E.164.
+#[derive(Debug, Clone)]
+pub struct PhoneNormalizer {
+    /// Region assumed for numbers written without a country code
+    default_region: Option<country::Id>,
+}
+
+impl PhoneNormalizer {
+    /// Creates a normalizer for the given default region.
+    ///
+    /// # Arguments
+    /// * `default_region` - ISO 3166-1 alpha-2 region (e.g. "US") used for numbers
+    ///   without a leading `+` and country code; `None` requires international format
+    ///
+    /// # Returns
+    /// * `Result<Self>` - New normalizer or error if the region is unknown
+    pub fn new(default_region: Option<&str>) -> Result<Self, Error> {
+        let default_region = default_region
+            .map(|region| {
+                region
+                    .trim()
+                    .to_ascii_uppercase()
+                    .parse::<country::Id>()
+                    .map_err(|_| Error::ServerError(format!("Unknown default phone region: {}", region)))
+            })
+            .transpose()?;
+
+        Ok(Self { default_region })
+    }
+
+    /// Normalizes a phone number to E.164.
+    ///
+    /// # Arguments
+    /// * `raw` - Phone number as stored in the directory
+    ///
+    /// # Returns
+    /// * `Result<String>` - E.164 number (e.g. "+15551234567") or `InvalidPhoneNumber`
+    pub fn normalize(&self, raw: &str) -> Result<String, Error> {
+        let number = phonenumber::parse(self.default_region, raw.trim())
+            .map_err(|e| {
+                debug!("Failed to parse phone number {}: {}", raw, e);
+                Error::InvalidPhoneNumber(raw.to_string())
+            })?;
+
+        if !phonenumber::is_valid(&number) {
+            debug!("Phone number {} is not valid for its region", raw);
+            return Err(Error::InvalidPhoneNumber(raw.to_string()));
+        }
+
+        Ok(number.format().mode(Mode::E164).to_string())
+    }
+
+    /// Returns whether an E.164 number is a mobile (or possibly mobile) number.
+    ///
+    /// # Arguments
+    /// * `e164` - Number previously returned by [`PhoneNormalizer::normalize`]
+    pub fn is_mobile(&self, e164: &str) -> bool {
+        phonenumber::parse(None, e164)
+            .map(|number| matches!(number.number_type(&DATABASE), Type::Mobile | Type::FixedLineOrMobile))
+            .unwrap_or(false)
+    }
+}
+
+/// Applies a selection policy to a user's phone numbers.
+///
+/// # Arguments
+/// * `policy` - How to choose between several numbers
+/// * `normalizer` - Normalizer used to tell mobile numbers apart
+/// * `username` - Canonical username, reported with `AmbiguousPhoneNumber`
+/// * `dn` - DN of the user's entry, reported with `AmbiguousPhoneNumber`
+/// * `phone_numbers` - Distinct E.164 numbers, in order of preference
+/// * `profile` - Profile attributes, handed back or reported with `AmbiguousPhoneNumber`
+/// * `source` - Where the numbers came from, reported with `PhoneNumberNotFound`
+///
+/// # Returns
+/// * `Result<(String, HashMap<String, String>)>` - The chosen number and the profile,
+///   `AmbiguousPhoneNumber`, or `PhoneNumberNotFound` if there are no numbers
+pub fn select_phone_number(
+    policy: PhoneSelection,
+    normalizer: &PhoneNormalizer,
+    username: &str,
+    dn: &str,
+    mut phone_numbers: Vec<String>,
+    profile: HashMap<String, String>,
+    source: &str,
+) -> Result<(String, HashMap<String, String>), Error> {
+    if phone_numbers.len() > 1 {
+        match policy {
+            PhoneSelection::First => {}
+            PhoneSelection::PreferMobile => {
+                if let Some(pos) = phone_numbers.iter().position(|n| normalizer.is_mobile(n)) {
+                    return Ok((phone_numbers.swap_remove(pos), profile));
+                }
+            }
+            PhoneSelection::Reject => {
+                debug!("User has {} phone numbers, asking the user to choose", phone_numbers.len());
+                return Err(Error::AmbiguousPhoneNumber {
+                    username: username.to_string(),
+                    dn: dn.to_string(),
+                    candidates: phone_numbers,
+                    profile,
+                });
+            }
+        }
+    }
+    phone_numbers
+        .into_iter()
+        .next()
+        .map(|phone_number| (phone_number, profile))
+        .ok_or_else(|| Error::PhoneNumberNotFound(source.to_string()))
+}
+
+/// Masks all but the last four digits of a phone number, e.g. "+*******4567".
+///
+/// # Arguments
+/// * `phone_number` - Phone number to mask
+///
+/// # Returns
+/// * `String` - Masked number that is safe to show before the user is verified
+pub fn mask_phone_number(phone_number: &str) -> String {
+    let digits = phone_number.chars().filter(|c| c.is_ascii_digit()).count();
+    let mut seen = 0;
+    phone_number
+        .chars()
+        .map(|c| {
+            if c.is_ascii_digit() {
+                seen += 1;
+                if seen <= digits.saturating_sub(4) {
+                    return '*';
+                }
+            }
+            c
+        })
+        .collect()
+}
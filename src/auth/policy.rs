@@ -0,0 +1,232 @@
+//! Account state detection for failed binds.
+//!
+//! A rejected bind usually means a mistyped password, but it can also mean the
+//! account is locked, disabled, expired, or has a password that must be changed.
+//! This module tells those cases apart from two sources the server only sends
+//! once it has checked the password: the password policy response control
+//! (draft-behera-ldap-password-policy) returned by OpenLDAP and other
+//! ppolicy-aware servers, and the `data` code Active Directory puts in the bind
+//! diagnostic message. The flags in the user's AD `userAccountControl` and the
+//! ppolicy `pwdAccountLockedTime` are read without a password, so they are never
+//! used to explain a failed bind; that would let anyone find out which accounts
+//! are disabled or locked.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use ldap3::asn1::{parse_tag, parse_uint, StructureTag, TagClass, PL};
+use ldap3::controls::RawControl;
+use ldap3::LdapResult;
+
+use super::ldap::Error;
+
+/// OID of the password policy request and response controls.
+pub const PASSWORD_POLICY_OID: &str = "1.3.6.1.4.1.42.2.27.8.5.1";
+
+/// LDAP result code for invalid credentials.
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// `userAccountControl` flag set on disabled accounts.
+const UF_ACCOUNTDISABLE: u32 = 0x0002;
+/// `userAccountControl` flag set on locked-out accounts.
+const UF_LOCKOUT: u32 = 0x0010;
+/// `userAccountControl` flag set when the password has expired.
+const UF_PASSWORD_EXPIRED: u32 = 0x0080_0000;
+
+/// `pwdAccountLockedTime` an administrator sets to lock an account until it is
+/// unlocked by hand.
+const PERMANENTLY_LOCKED: &str = "000001010000Z";
+
+/// Why an otherwise valid account cannot sign in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountState {
+    /// Too many failed attempts
+    Locked,
+    /// Disabled by an administrator
+    Disabled,
+    /// The account itself has expired
+    Expired,
+    /// The password has expired or was reset and must be changed
+    PasswordMustChange,
+}
+
+impl AccountState {
+    /// Converts the state into the matching error for a user.
+    pub fn into_error(self, user: &str) -> Error {
+        let user = user.to_string();
+        match self {
+            AccountState::Locked => Error::AccountLocked(user),
+            AccountState::Disabled => Error::AccountDisabled(user),
+            AccountState::Expired => Error::AccountExpired(user),
+            AccountState::PasswordMustChange => Error::PasswordMustChange(user),
+        }
+    }
+}
+
+/// Password policy warning returned with a successful bind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyWarning {
+    /// Seconds until the password expires
+    TimeBeforeExpiration(u64),
+    /// Logins left after the password has expired
+    GraceAuthNsRemaining(u64),
+}
+
+/// Parsed password policy response control.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    /// Warning about an upcoming expiry
+    pub warning: Option<PolicyWarning>,
+    /// Account state reported by the server
+    pub state: Option<AccountState>,
+}
+
+impl PasswordPolicy {
+    /// Extracts the password policy response control from a bind result.
+    ///
+    /// # Arguments
+    /// * `result` - Result of the bind operation
+    ///
+    /// # Returns
+    /// * `PasswordPolicy` - The parsed control, or an empty policy if the server sent none
+    pub fn from_result(result: &LdapResult) -> Self {
+        result
+            .ctrls
+            .iter()
+            .find(|ctrl| ctrl.1.ctype == PASSWORD_POLICY_OID)
+            .and_then(|ctrl| ctrl.1.val.as_deref())
+            .and_then(parse_password_policy)
+            .unwrap_or_default()
+    }
+}
+
+/// Builds the password policy request control sent with the user bind.
+pub fn request_control() -> RawControl {
+    RawControl {
+        ctype: PASSWORD_POLICY_OID.to_string(),
+        crit: false,
+        val: None,
+    }
+}
+
+/// Works out why a bind failed.
+///
+/// The password policy control takes precedence, then the Active Directory data
+/// code in the diagnostic message. A rejected bind with neither is a plain bad
+/// password, whatever the entry's `userAccountControl` says.
+///
+/// # Arguments
+/// * `result` - Result of the failed bind
+/// * `policy` - Password policy control parsed from the result
+///
+/// # Returns
+/// * `Option<AccountState>` - The account state, or `None` for a plain credential failure
+pub fn bind_failure_state(result: &LdapResult, policy: &PasswordPolicy) -> Option<AccountState> {
+    policy
+        .state
+        .or_else(|| ad_data_code(&result.text).and_then(account_state_for_data_code))
+}
+
+/// Returns whether a bind result means the credentials were rejected.
+pub fn is_invalid_credentials(result: &LdapResult) -> bool {
+    result.rc == INVALID_CREDENTIALS
+}
+
+/// Extracts the `data` code from an Active Directory bind diagnostic such as
+/// `80090308: LdapErr: DSID-0C09044E, comment: AcceptSecurityContext error, data 775, v2580`.
+fn ad_data_code(text: &str) -> Option<u32> {
+    let start = text.find("data ")? + "data ".len();
+    let code: String = text[start..].chars().take_while(|c| c.is_ascii_hexdigit()).collect();
+    u32::from_str_radix(&code, 16).ok()
+}
+
+/// Maps an Active Directory bind data code to an account state.
+fn account_state_for_data_code(code: u32) -> Option<AccountState> {
+    match code {
+        0x532 | 0x773 => Some(AccountState::PasswordMustChange),
+        0x533 => Some(AccountState::Disabled),
+        0x701 => Some(AccountState::Expired),
+        0x775 => Some(AccountState::Locked),
+        _ => None,
+    }
+}
+
+/// Maps `userAccountControl` flags to an account state, e.g. to re-check a stored
+/// registration. Not for explaining a failed bind.
+pub fn account_state_for_flags(flags: u32) -> Option<AccountState> {
+    if flags & UF_ACCOUNTDISABLE != 0 {
+        Some(AccountState::Disabled)
+    } else if flags & UF_LOCKOUT != 0 {
+        Some(AccountState::Locked)
+    } else if flags & UF_PASSWORD_EXPIRED != 0 {
+        Some(AccountState::PasswordMustChange)
+    } else {
+        None
+    }
+}
+
+/// Maps a ppolicy `pwdAccountLockedTime` value to an account state, e.g. to
+/// re-check a stored registration on OpenLDAP. The administrative lock
+/// `000001010000Z` disables the account; any other value is a lockout after
+/// failed attempts, which may since have expired. Not for explaining a failed bind.
+pub fn account_state_for_locked_time(value: &str) -> AccountState {
+    if value == PERMANENTLY_LOCKED {
+        AccountState::Disabled
+    } else {
+        AccountState::Locked
+    }
+}
+
+/// Parses the value of the password policy response control:
+///
+/// ```text
+/// PasswordPolicyResponseValue ::= SEQUENCE {
+///     warning [0] CHOICE {
+///         timeBeforeExpiration [0] INTEGER (0 .. maxInt),
+///         graceAuthNsRemaining [1] INTEGER (0 .. maxInt) } OPTIONAL,
+///     error   [1] ENUMERATED { passwordExpired (0), accountLocked (1),
+///                              changeAfterReset (2), ... } OPTIONAL }
+/// ```
+fn parse_password_policy(val: &[u8]) -> Option<PasswordPolicy> {
+    let (_, tag) = parse_tag(val).ok()?;
+    let mut policy = PasswordPolicy::default();
+
+    for element in tag.expect_constructed()? {
+        if element.class != TagClass::Context {
+            continue;
+        }
+        match element.id {
+            0 => policy.warning = parse_warning(element),
+            1 => {
+                policy.state = match primitive_uint(element)? {
+                    0 | 2 => Some(AccountState::PasswordMustChange),
+                    1 => Some(AccountState::Locked),
+                    _ => None,
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(policy)
+}
+
+/// Parses the warning choice. The tag is explicit, so the choice is wrapped in a
+/// constructed `[0]`; some servers send it implicitly, which is accepted as well.
+fn parse_warning(element: StructureTag) -> Option<PolicyWarning> {
+    let choice = match element.payload {
+        PL::C(mut inner) if !inner.is_empty() => inner.remove(0),
+        PL::C(_) => return None,
+        PL::P(_) => element,
+    };
+    let id = choice.id;
+    let value = primitive_uint(choice)?;
+    match id {
+        0 => Some(PolicyWarning::TimeBeforeExpiration(value)),
+        1 => Some(PolicyWarning::GraceAuthNsRemaining(value)),
+        _ => None,
+    }
+}
+
+fn primitive_uint(tag: StructureTag) -> Option<u64> {
+    let bytes = tag.expect_primitive()?;
+    parse_uint(&bytes).ok().map(|(_, value)| value)
+}
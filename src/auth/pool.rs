@@ -0,0 +1,456 @@
+//! Bounded LDAP connection pool.
+//!
+//! This module keeps a set of reusable connections to the LDAP server so that
+//! authentication requests do not pay the cost of a TCP (and later TLS) handshake
+//! every time. The pool is bounded by `max_pool_size`, is pre-warmed to
+//! `min_pool_size`, and makes callers wait up to `pool_timeout` for a free
+//! connection once the maximum has been reached. Dead connections are dropped on
+//! checkout, and a background task evicts connections that have been idle for too
+//! long and tops the pool back up to its minimum size.
+//!
+//! With several servers configured, new connections go to the server chosen by
+//! the [`ServerSet`]. A server that refuses connections is taken out of rotation
+//! and the next one is tried, so opening a connection tries every server once.
+//! Retrying failed operations is left to the caller and its `max_retries`.
+//!
+//! Every pooled connection is bound as the service account (`bind_dn`) when it is
+//! opened. Binding as anyone else must go through a dedicated connection from
+//! [`LdapPool::get_dedicated`], or through [`PooledConnection::simple_bind`], which
+//! takes the connection out of rotation so it is never handed out again.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, LdapResult, Scope};
+use ldap3::result::LdapError as Ldap3Error;
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, error, warn};
+
+use super::ldap::{Error, LdapConfig};
+use super::servers::ServerSet;
+use super::tls;
+
+/// A connection sitting in the pool, waiting to be checked out.
+#[derive(Debug)]
+struct IdleConnection {
+    /// The LDAP handle
+    ldap: Ldap,
+    /// Index of the server the connection goes to
+    server: usize,
+    /// When the connection was last returned to the pool
+    idle_since: Instant,
+}
+
+/// Shared state of the pool.
+struct PoolInner {
+    config: LdapConfig,
+    /// Connection settings (timeouts and TLS) shared by every connection
+    settings: LdapConnSettings,
+    /// Servers to connect to and their health
+    servers: ServerSet,
+    /// Idle connections, most recently returned at the back
+    idle: Mutex<VecDeque<IdleConnection>>,
+    /// One permit per connection that may be checked out at the same time
+    permits: Arc<Semaphore>,
+}
+
+impl std::fmt::Debug for PoolInner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PoolInner")
+            .field("config", &self.config)
+            .field("servers", &self.servers)
+            .field("idle", &self.idle)
+            .field("permits", &self.permits)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Bounded pool of LDAP connections.
+///
+/// Cloning the pool is cheap; all clones share the same connections.
+#[derive(Debug, Clone)]
+pub struct LdapPool {
+    inner: Arc<PoolInner>,
+}
+
+/// A connection checked out of the pool.
+///
+/// The connection is returned to the pool when the guard is dropped, unless it
+/// has been closed, rebound with [`PooledConnection::simple_bind`], or explicitly
+/// discarded with [`PooledConnection::discard`].
+#[derive(Debug)]
+pub struct PooledConnection {
+    ldap: Option<Ldap>,
+    /// Index of the server the connection goes to
+    server: usize,
+    pool: Arc<PoolInner>,
+    /// Whether the connection is still bound as the service account
+    reusable: bool,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl LdapPool {
+    /// Creates a new pool and opens `min_pool_size` connections up front.
+    ///
+    /// # Arguments
+    /// * `config` - LDAP configuration including the servers, pool limits and timeouts
+    ///
+    /// # Returns
+    /// * `Result<Self>` - New pool or error if the initial connections cannot be opened
+    pub async fn new(mut config: LdapConfig) -> Result<Self, Error> {
+        if config.urls.is_empty() {
+            return Err(Error::ServerError("No LDAP server URL configured".to_string()));
+        }
+        config.max_pool_size = config.max_pool_size.max(1);
+        config.min_pool_size = config.min_pool_size.min(config.max_pool_size);
+        let settings = tls::connection_settings(&config)?;
+
+        let inner = Arc::new(PoolInner {
+            permits: Arc::new(Semaphore::new(config.max_pool_size)),
+            idle: Mutex::new(VecDeque::with_capacity(config.max_pool_size)),
+            servers: ServerSet::new(&config.urls, config.server_selection, config.server_cooldown),
+            settings,
+            config,
+        });
+
+        for _ in 0..inner.config.min_pool_size {
+            let (server, ldap) = connect(&inner, true).await?;
+            inner.push_idle(ldap, server);
+        }
+        debug!("LDAP pool pre-warmed with {} connection(s)", inner.config.min_pool_size);
+
+        tokio::spawn(maintain(Arc::downgrade(&inner)));
+
+        Ok(Self { inner })
+    }
+
+    /// Checks a connection out of the pool.
+    ///
+    /// Waits up to `pool_timeout` for a free slot when all `max_pool_size`
+    /// connections are in use. Idle connections that have been closed by the server
+    /// or go to a server that is out of rotation are dropped, and connections idle
+    /// for longer than `idle_timeout` are probed before being handed out. A failed
+    /// probe only drops the connection: servers and firewalls close idle sockets,
+    /// so the server is taken out of rotation only if a new connection to it fails
+    /// too. A new connection is opened if no idle one is usable.
+    ///
+    /// # Returns
+    /// * `Result<PooledConnection>` - A live connection or error on timeout/connect failure
+    pub async fn get(&self) -> Result<PooledConnection, Error> {
+        let permit = self.acquire_permit().await?;
+
+        while let Some(mut conn) = self.pop_idle() {
+            if conn.ldap.is_closed() || !self.inner.servers.is_available(conn.server) {
+                debug!("Dropping closed LDAP connection");
+                continue;
+            }
+            if conn.idle_since.elapsed() >= self.inner.config.idle_timeout
+                && !is_healthy(&mut conn.ldap, self.inner.config.read_timeout).await
+            {
+                debug!("Dropping LDAP connection that failed its health check");
+                continue;
+            }
+            return Ok(PooledConnection::new(conn.ldap, conn.server, self.inner.clone(), permit, true));
+        }
+
+        let (server, ldap) = connect(&self.inner, true).await?;
+        Ok(PooledConnection::new(ldap, server, self.inner.clone(), permit, true))
+    }
+
+    /// Opens a dedicated, unbound connection for a credential check.
+    ///
+    /// The connection counts against `max_pool_size` while it is checked out but is
+    /// closed instead of being returned to the pool when dropped, so an end-user
+    /// bind can never leak into a later service-account search.
+    ///
+    /// # Returns
+    /// * `Result<PooledConnection>` - A fresh connection or error on timeout/connect failure
+    pub async fn get_dedicated(&self) -> Result<PooledConnection, Error> {
+        let permit = self.acquire_permit().await?;
+        let (server, ldap) = connect(&self.inner, false).await?;
+        Ok(PooledConnection::new(ldap, server, self.inner.clone(), permit, false))
+    }
+
+    /// Opens a connection to a server outside the configured list, e.g. the
+    /// target of a referral, bound as the service account with the pool's
+    /// timeout and TLS settings. The connection does not count against
+    /// `max_pool_size` and is not returned to the pool.
+    ///
+    /// # Arguments
+    /// * `url` - LDAP URL of the server
+    ///
+    /// # Returns
+    /// * `Result<Ldap>` - A bound connection or error on connect/bind failure
+    pub async fn open_service_connection(&self, url: &str) -> Result<Ldap, Error> {
+        connect_to(&self.inner, url, true).await
+    }
+
+    /// Opens an unbound connection to a server outside the configured list, e.g.
+    /// the target of a referral that must not see the service account's
+    /// credentials. Like [`LdapPool::open_service_connection`], it does not count
+    /// against `max_pool_size` and is not returned to the pool.
+    ///
+    /// # Arguments
+    /// * `url` - LDAP URL of the server
+    ///
+    /// # Returns
+    /// * `Result<Ldap>` - An anonymous connection or error on connect failure
+    pub async fn open_anonymous_connection(&self, url: &str) -> Result<Ldap, Error> {
+        connect_to(&self.inner, url, false).await
+    }
+
+    /// Opens a long-lived connection on the best available server, bound as the
+    /// service account, e.g. for a persistent change-tracking search. The
+    /// connection does not count against `max_pool_size` and is not returned to
+    /// the pool.
+    ///
+    /// # Returns
+    /// * `Result<(Ldap, String)>` - A bound connection and the URL of its server
+    pub async fn open_long_lived(&self) -> Result<(Ldap, String), Error> {
+        let (server, ldap) = connect(&self.inner, true).await?;
+        Ok((ldap, self.inner.servers.url(server).to_string()))
+    }
+
+    /// Returns the number of idle connections currently held by the pool.
+    pub fn idle_count(&self) -> usize {
+        self.inner.idle.lock().map(|idle| idle.len()).unwrap_or(0)
+    }
+
+    async fn acquire_permit(&self) -> Result<OwnedSemaphorePermit, Error> {
+        tokio::time::timeout(
+            self.inner.config.pool_timeout,
+            self.inner.permits.clone().acquire_owned(),
+        )
+        .await
+        .map_err(|_| {
+            warn!("Timed out waiting for an LDAP connection from the pool");
+            Error::PoolTimeout
+        })?
+        .map_err(|e| Error::ServerError(e.to_string()))
+    }
+
+    fn pop_idle(&self) -> Option<IdleConnection> {
+        self.inner.idle.lock().ok()?.pop_back()
+    }
+}
+
+impl PoolInner {
+    fn push_idle(&self, ldap: Ldap, server: usize) {
+        if let Ok(mut idle) = self.idle.lock() {
+            idle.push_back(IdleConnection {
+                ldap,
+                server,
+                idle_since: Instant::now(),
+            });
+        }
+    }
+}
+
+impl PooledConnection {
+    fn new(ldap: Ldap, server: usize, pool: Arc<PoolInner>, permit: OwnedSemaphorePermit, reusable: bool) -> Self {
+        Self {
+            ldap: Some(ldap),
+            server,
+            pool,
+            reusable,
+            _permit: permit,
+        }
+    }
+
+    /// Binds the connection with the given credentials.
+    ///
+    /// Once rebound, the connection no longer carries the service account's
+    /// identity and is closed instead of being returned to the pool.
+    pub async fn simple_bind(&mut self, bind_dn: &str, bind_pw: &str) -> Result<LdapResult, Ldap3Error> {
+        self.reusable = false;
+        self.op().simple_bind(bind_dn, bind_pw).await
+    }
+
+    /// Returns the LDAP handle with the configured read timeout applied to the
+    /// next operation.
+    pub fn op(&mut self) -> &mut Ldap {
+        let read_timeout = self.pool.config.read_timeout;
+        self.deref_mut().with_timeout(read_timeout)
+    }
+
+    /// Drops the connection instead of returning it to the pool.
+    ///
+    /// Use this when an operation failed in a way that leaves the connection in
+    /// an unknown state.
+    pub fn discard(mut self) {
+        self.ldap = None;
+    }
+
+    /// Drops the connection after a connection error and takes its server out
+    /// of rotation, so the next checkout goes elsewhere.
+    pub fn mark_failed(self) {
+        self.pool.servers.mark_down(self.server);
+        self.discard();
+    }
+
+    /// Returns the URL of the server the connection goes to.
+    pub fn server_url(&self) -> &str {
+        self.pool.servers.url(self.server)
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Ldap;
+
+    fn deref(&self) -> &Ldap {
+        self.ldap.as_ref().expect("pooled connection used after discard")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Ldap {
+        self.ldap.as_mut().expect("pooled connection used after discard")
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(mut ldap) = self.ldap.take() {
+            if !self.reusable || ldap.is_closed() {
+                return;
+            }
+            self.pool.push_idle(ldap, self.server);
+        }
+    }
+}
+
+/// Opens a new connection on the best available server, failing over to the
+/// next server after a connection error until every server has been tried once.
+///
+/// # Arguments
+/// * `inner` - Pool state with the servers and connection settings
+/// * `bind` - Whether to bind the connection as the service account
+///
+/// # Returns
+/// * `Result<(usize, Ldap)>` - Index of the server and the connection
+async fn connect(inner: &PoolInner, bind: bool) -> Result<(usize, Ldap), Error> {
+    let mut last_error = None;
+
+    for server in inner.servers.candidates() {
+        let url = inner.servers.url(server);
+        match connect_to(inner, url, bind).await {
+            Ok(ldap) => {
+                inner.servers.mark_up(server);
+                return Ok((server, ldap));
+            }
+            Err(e) if e.is_connection_error() => {
+                warn!("Failed to connect to LDAP server {}: {}", url, e);
+                inner.servers.mark_down(server);
+                last_error = Some(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| Error::ServerError("No LDAP server available".to_string())))
+}
+
+/// Opens a connection to one server, optionally binding it as the service account.
+async fn connect_to(inner: &PoolInner, url: &str, bind: bool) -> Result<Ldap, Error> {
+    let (conn, mut ldap) = LdapConnAsync::with_settings(inner.settings.clone(), url).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = conn.drive().await {
+            debug!("LDAP connection closed: {}", e);
+        }
+    });
+
+    if bind {
+        let config = &inner.config;
+        ldap.with_timeout(config.read_timeout)
+            .simple_bind(&config.bind_dn, &config.bind_password)
+            .await?
+            .success()
+            .map_err(|e| {
+                error!("Service account bind failed on {}: {:?}", url, e);
+                match e {
+                    Ldap3Error::LdapResult { result } if matches!(result.rc, 51 | 52) => {
+                        Error::Ldap(Ldap3Error::LdapResult { result })
+                    }
+                    e => Error::ServerError(format!("Service account bind failed: {}", e)),
+                }
+            })?;
+    }
+    Ok(ldap)
+}
+
+/// Probes a connection with a root DSE read.
+async fn is_healthy(ldap: &mut Ldap, read_timeout: Duration) -> bool {
+    match ldap
+        .with_timeout(read_timeout)
+        .search("", Scope::Base, "(objectClass=*)", vec!["1.1"])
+        .await
+    {
+        Ok(result) => result.success().is_ok(),
+        Err(e) => {
+            debug!("LDAP health check failed: {}", e);
+            false
+        }
+    }
+}
+
+/// Background task that evicts stale idle connections and keeps the pool at
+/// its minimum size. Stops once the pool itself has been dropped.
+async fn maintain(pool: Weak<PoolInner>) {
+    let interval = match pool.upgrade() {
+        Some(inner) => (inner.config.idle_timeout / 2).max(Duration::from_secs(1)),
+        None => return,
+    };
+
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(inner) = pool.upgrade() else {
+            return;
+        };
+
+        let min_pool_size = inner.config.min_pool_size;
+        let evicted = match inner.idle.lock() {
+            Ok(mut idle) => {
+                let before = idle.len();
+                idle.retain_mut(|conn| !conn.ldap.is_closed());
+                while idle.len() > min_pool_size
+                    && idle
+                        .front()
+                        .is_some_and(|conn| conn.idle_since.elapsed() >= inner.config.idle_timeout)
+                {
+                    idle.pop_front();
+                }
+                before - idle.len()
+            }
+            Err(_) => return,
+        };
+        if evicted > 0 {
+            debug!("Evicted {} stale LDAP connection(s)", evicted);
+        }
+
+        // Top up to the minimum; each new connection holds a permit while it is
+        // being opened so that the pool never exceeds its maximum size.
+        loop {
+            let live = inner.idle.lock().map(|idle| idle.len()).unwrap_or(0)
+                + (inner.config.max_pool_size - inner.permits.available_permits());
+            if live >= min_pool_size {
+                break;
+            }
+            let Ok(permit) = inner.permits.clone().try_acquire_owned() else {
+                break;
+            };
+            match connect(&inner, true).await {
+                Ok((server, ldap)) => {
+                    inner.push_idle(ldap, server);
+                    drop(permit);
+                }
+                Err(e) => {
+                    warn!("Failed to replenish LDAP pool: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
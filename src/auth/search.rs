@@ -0,0 +1,264 @@
+//! Directory searches across several bases.
+//!
+//! Users can live under more than one subtree, e.g. `ou=staff` and
+//! `ou=contractors` in different naming contexts. This module runs a subtree
+//! search under each configured base, one after the other or all at once,
+//! requests results in pages when a page size is configured, and optionally
+//! follows the referrals a server returns instead of failing on them, up to a
+//! configured number of hops.
+//!
+//! Referrals only lead to trusted hosts: the configured servers and the
+//! `referral_hosts` allowlist. They are followed on a separate connection, bound
+//! as the service account when it is encrypted and anonymous otherwise, so the
+//! service password never goes to a host it was not meant for. Referrals to
+//! other hosts are skipped.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use ldap3::adapters::{Adapter, PagedResults};
+use ldap3::{get_url_params, parse_refs, Ldap, Scope, SearchEntry};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tracing::{debug, error, warn};
+use url::Url;
+
+pub use crate::config::SearchMode;
+
+use super::ldap::Error;
+use super::pool::{LdapPool, PooledConnection};
+use super::tls::{self, TlsMode};
+
+/// LDAP result code for a referral.
+const REFERRAL: u32 = 10;
+
+/// LDAP result code returned when the search base does not exist.
+const NO_SUCH_OBJECT: u32 = 32;
+
+/// Search settings.
+#[derive(Debug, Clone)]
+pub struct SearchConfig {
+    /// Search bases, in order of preference
+    pub bases: Vec<String>,
+    /// Whether the bases are searched one after the other or all at once
+    pub mode: SearchMode,
+    /// Whether referrals are followed
+    pub follow_referrals: bool,
+    /// Hosts referrals may lead to, as `host` or `host:port`
+    pub referral_hosts: Vec<String>,
+    /// Maximum number of referrals followed in a row
+    pub referral_hop_limit: u32,
+    /// Page size for the paged results control; 0 disables paging
+    pub page_size: u32,
+}
+
+impl SearchConfig {
+    /// Resolves the search settings from the service configuration. Without
+    /// `search_bases`, `base_dn` is the only base. Referrals may lead to the hosts
+    /// of the configured servers and to `referral_hosts`.
+    pub fn from_config(config: &crate::config::LdapConfig) -> Self {
+        let servers = if config.urls.is_empty() { std::slice::from_ref(&config.url) } else { &config.urls[..] };
+        let referral_hosts = servers
+            .iter()
+            .filter_map(|url| Url::parse(url).ok()?.host_str().map(str::to_string))
+            .chain(config.referral_hosts.iter().cloned())
+            .collect();
+        SearchConfig {
+            bases: if config.search_bases.is_empty() {
+                vec![config.base_dn.clone()]
+            } else {
+                config.search_bases.clone()
+            },
+            mode: config.search_mode,
+            follow_referrals: config.follow_referrals,
+            referral_hosts,
+            referral_hop_limit: config.referral_hop_limit,
+            page_size: config.page_size,
+        }
+    }
+}
+
+/// Runs subtree searches over several bases.
+#[derive(Debug, Clone)]
+pub struct DirectorySearch {
+    config: SearchConfig,
+    /// Timeout for each search operation
+    read_timeout: Duration,
+    /// TLS mode that referral URLs must agree with
+    tls_mode: TlsMode,
+    /// Pool used to open connections for referrals
+    pool: LdapPool,
+}
+
+type SearchFuture = Pin<Box<dyn Future<Output = Result<Vec<SearchEntry>, Error>> + Send>>;
+
+impl DirectorySearch {
+    /// Creates a searcher.
+    ///
+    /// # Arguments
+    /// * `config` - Search settings
+    /// * `read_timeout` - Timeout for each search operation
+    /// * `tls_mode` - TLS mode that referral URLs must agree with
+    /// * `pool` - Pool used to open connections for referrals
+    pub fn new(config: SearchConfig, read_timeout: Duration, tls_mode: TlsMode, pool: LdapPool) -> Self {
+        Self { config, read_timeout, tls_mode, pool }
+    }
+
+    /// Returns the configured search bases.
+    pub fn bases(&self) -> &[String] {
+        &self.config.bases
+    }
+
+    /// Searches the subtree under each base.
+    ///
+    /// In sequential mode, bases after the first one with a match are skipped
+    /// and reported as empty.
+    ///
+    /// # Arguments
+    /// * `ldap` - Pooled connection to search on
+    /// * `bases` - Search bases
+    /// * `filter` - Search filter
+    /// * `attrs` - Attributes to return
+    ///
+    /// # Returns
+    /// * `Result<Vec<Vec<SearchEntry>>>` - The entries found under each base, in the order of `bases`
+    pub async fn search(
+        &self,
+        ldap: &mut PooledConnection,
+        bases: &[String],
+        filter: &str,
+        attrs: &[String],
+    ) -> Result<Vec<Vec<SearchEntry>>, Error> {
+        let handle: Ldap = (**ldap).clone();
+
+        match self.config.mode {
+            SearchMode::Sequential => {
+                let mut results = vec![Vec::new(); bases.len()];
+                for (index, base) in bases.iter().enumerate() {
+                    results[index] = self.search_base(handle.clone(), base.clone(), filter.to_string(), attrs.to_vec(), 0).await?;
+                    if !results[index].is_empty() {
+                        break;
+                    }
+                }
+                Ok(results)
+            }
+            SearchMode::Parallel => {
+                // Operations are multiplexed on the one connection
+                let mut tasks = JoinSet::new();
+                for (index, base) in bases.iter().enumerate() {
+                    let search = self.search_base(handle.clone(), base.clone(), filter.to_string(), attrs.to_vec(), 0);
+                    tasks.spawn(async move { (index, search.await) });
+                }
+                let mut results = vec![Vec::new(); bases.len()];
+                while let Some(joined) = tasks.join_next().await {
+                    let (index, entries) = joined.map_err(|e| Error::ServerError(format!("Search task failed: {}", e)))?;
+                    results[index] = entries?;
+                }
+                Ok(results)
+            }
+        }
+    }
+
+    /// Searches one base, following referrals if enabled.
+    fn search_base(&self, mut ldap: Ldap, base: String, filter: String, attrs: Vec<String>, hops: u32) -> SearchFuture {
+        let this = self.clone();
+        Box::pin(async move {
+            debug!("Searching {} with filter {} (hop {})", base, filter, hops);
+            let adapters: Vec<Box<dyn Adapter<String, Vec<String>>>> = if this.config.page_size > 0 {
+                vec![Box::new(PagedResults::new(this.config.page_size as i32))]
+            } else {
+                vec![]
+            };
+            let mut stream = ldap
+                .with_timeout(this.read_timeout)
+                .streaming_search_with(adapters, &base, Scope::Subtree, &filter, attrs.clone())
+                .await?;
+
+            let mut entries = Vec::new();
+            let mut referrals = Vec::new();
+            while let Some(entry) = stream.next().await? {
+                if entry.is_ref() {
+                    referrals.extend(parse_refs(entry.0));
+                } else if !entry.is_intermediate() {
+                    entries.push(SearchEntry::construct(entry));
+                }
+            }
+            let result = stream.finish().await;
+            match result.rc {
+                REFERRAL => referrals.extend(result.refs.iter().cloned()),
+                // A missing base must not look like a directory without users
+                NO_SUCH_OBJECT if hops == 0 => {
+                    error!("Search base {} does not exist", base);
+                    return Err(Error::SearchBaseMissing(base));
+                }
+                _ => {
+                    result.success()?;
+                }
+            }
+
+            if referrals.is_empty() {
+                return Ok(entries);
+            }
+            if !this.config.follow_referrals {
+                warn!("Ignoring {} referral(s) returned for {}", referrals.len(), base);
+                return Ok(entries);
+            }
+            if hops >= this.config.referral_hop_limit {
+                warn!("Referral hop limit reached under {}, ignoring {} referral(s)", base, referrals.len());
+                return Ok(entries);
+            }
+            for referral in referrals {
+                entries.extend(this.follow(&referral, &base, &filter, &attrs, hops + 1).await?);
+            }
+            Ok(entries)
+        })
+    }
+
+    /// Returns whether referrals may lead to a host.
+    fn is_trusted(&self, host: &str, port: Option<u16>) -> bool {
+        self.config.referral_hosts.iter().any(|trusted| match trusted.rsplit_once(':') {
+            Some((name, trusted_port)) if port.is_some_and(|port| trusted_port == port.to_string()) => {
+                name.eq_ignore_ascii_case(host)
+            }
+            Some(_) => false,
+            None => trusted.eq_ignore_ascii_case(host),
+        })
+    }
+
+    /// Follows one referral to a trusted host on a new connection, bound as the
+    /// service account over TLS and anonymous otherwise. Referrals to other hosts
+    /// are skipped.
+    async fn follow(&self, referral: &str, base: &str, filter: &str, attrs: &[String], hops: u32) -> Result<Vec<SearchEntry>, Error> {
+        let url = Url::parse(referral).map_err(|e| Error::ServerError(format!("Invalid referral {}: {}", referral, e)))?;
+        let host = url.host_str().unwrap_or_default();
+        if !self.is_trusted(host, url.port()) {
+            warn!("Skipping referral {} to untrusted host {}", referral, host);
+            return Ok(Vec::new());
+        }
+        let server = match url.port() {
+            Some(port) => format!("{}://{}:{}", url.scheme(), host, port),
+            None => format!("{}://{}", url.scheme(), host),
+        };
+        // A referral must not downgrade the connection security
+        tls::check_scheme(&server, self.tls_mode)?;
+        let params = get_url_params(&url)?;
+        let target_base = if params.base.is_empty() { base.to_string() } else { params.base.into_owned() };
+
+        let encrypted = self.tls_mode != TlsMode::Plain;
+        debug!("Following referral to {} for base {} ({})", server, target_base, if encrypted { "service account" } else { "anonymous" });
+        let connection = if encrypted {
+            self.pool.open_service_connection(&server).await
+        } else {
+            self.pool.open_anonymous_connection(&server).await
+        };
+        let mut ldap = connection.inspect_err(|e| {
+            error!("Failed to follow referral {}: {}", referral, e);
+        })?;
+        let entries = self
+            .search_base(ldap.clone(), target_base, filter.to_string(), attrs.to_vec(), hops)
+            .await;
+        ldap.unbind().await.ok();
+        entries
+    }
+}
@@ -0,0 +1,121 @@
+//! LDAP server selection and health tracking.
+//!
+//! The service can be pointed at several directory servers, e.g. all domain
+//! controllers of a domain. This module decides which server a new connection
+//! goes to, either in priority order or round-robin, and takes a server out of
+//! rotation for `server_cooldown` after a connection error. Servers that are
+//! cooling down are still tried as a last resort, so a blip that marks every
+//! server down does not stop the service from reconnecting.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+pub use crate::config::ServerSelection;
+
+/// Health of a single server.
+#[derive(Debug)]
+struct ServerState {
+    /// LDAP URL of the server
+    url: String,
+    /// Connection errors since the last successful connection
+    consecutive_failures: AtomicU32,
+    /// When the server may be used again after a connection error
+    down_until: Mutex<Option<Instant>>,
+}
+
+/// The configured LDAP servers and their health.
+#[derive(Debug)]
+pub struct ServerSet {
+    servers: Vec<ServerState>,
+    /// How the next server is chosen
+    selection: ServerSelection,
+    /// How long a failed server stays out of rotation
+    cooldown: Duration,
+    /// Starting point of the next round-robin pass
+    next: AtomicUsize,
+}
+
+impl ServerSet {
+    /// Creates a server set.
+    ///
+    /// # Arguments
+    /// * `urls` - Server URLs, in priority order
+    /// * `selection` - How the next server is chosen
+    /// * `cooldown` - How long a failed server stays out of rotation
+    pub fn new(urls: &[String], selection: ServerSelection, cooldown: Duration) -> Self {
+        ServerSet {
+            servers: urls
+                .iter()
+                .map(|url| ServerState {
+                    url: url.clone(),
+                    consecutive_failures: AtomicU32::new(0),
+                    down_until: Mutex::new(None),
+                })
+                .collect(),
+            selection,
+            cooldown,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the URL of a server.
+    pub fn url(&self, server: usize) -> &str {
+        &self.servers[server].url
+    }
+
+    /// Returns whether a server is currently in rotation.
+    pub fn is_available(&self, server: usize) -> bool {
+        self.down_until(server).is_none_or(|until| until <= Instant::now())
+    }
+
+    /// Returns the servers to try for a new connection, best first.
+    ///
+    /// Servers in rotation come first, in priority order or starting at the next
+    /// round-robin position. Servers that are cooling down follow, the one that
+    /// comes back soonest first.
+    pub fn candidates(&self) -> Vec<usize> {
+        let count = self.servers.len();
+        let start = match self.selection {
+            ServerSelection::Priority => 0,
+            ServerSelection::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % count.max(1),
+        };
+
+        let (mut available, mut cooling): (Vec<usize>, Vec<usize>) =
+            (0..count).map(|i| (start + i) % count).partition(|&i| self.is_available(i));
+        cooling.sort_by_key(|&i| self.down_until(i));
+        available.append(&mut cooling);
+        available
+    }
+
+    /// Records a successful connection, putting the server back in rotation.
+    pub fn mark_up(&self, server: usize) {
+        let state = &self.servers[server];
+        if state.consecutive_failures.swap(0, Ordering::Relaxed) > 0 {
+            info!("LDAP server {} is back in rotation", state.url);
+        }
+        if let Ok(mut down_until) = state.down_until.lock() {
+            *down_until = None;
+        }
+    }
+
+    /// Records a connection error, taking the server out of rotation for the cooldown.
+    pub fn mark_down(&self, server: usize) {
+        let state = &self.servers[server];
+        let failures = state.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!(
+            "Taking LDAP server {} out of rotation for {:?} after {} failure(s)",
+            state.url, self.cooldown, failures
+        );
+        if let Ok(mut down_until) = state.down_until.lock() {
+            *down_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+
+    fn down_until(&self, server: usize) -> Option<Instant> {
+        self.servers[server].down_until.lock().ok().and_then(|until| *until)
+    }
+}
@@ -0,0 +1,406 @@
+//! Directory change tracking.
+//!
+//! Instead of re-reading every user on a schedule, the service can follow
+//! changes as the directory makes them. OpenLDAP and other RFC 4533 servers
+//! stream them over a persistent content synchronization (syncrepl) search;
+//! Active Directory answers repeated DirSync searches with whatever changed since
+//! the last one. Either way, the server hands out an opaque cookie that marks how
+//! far the client has read, so a restarted watcher resumes where it left off.
+//!
+//! The first synchronization without a cookie only establishes that position:
+//! the entries it returns are the current state, not changes, and are skipped.
+//! Deletions that syncrepl reports by entryUUID alone cannot be traced back to a
+//! user and are reported as [`SyncEvent::Resync`].
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use bytes::BytesMut;
+use ldap3::asn1::{parse_tag, parse_uint, write, ASNTag, Integer, OctetString, Sequence, Tag};
+use ldap3::controls::{
+    parse_syncinfo, Control, ControlType, EntryState, MakeCritical, RawControl, RefreshMode, SyncInfo, SyncRequest,
+    SyncState,
+};
+use ldap3::{ResultEntry, Scope, SearchEntry};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tracing::{debug, info};
+
+pub use crate::config::ChangeTracking;
+
+use super::ldap::Error;
+use super::pool::LdapPool;
+
+/// OID of the Active Directory DirSync control.
+pub const DIRSYNC_OID: &str = "1.2.840.113556.1.4.841";
+
+/// DirSync flag that limits results to objects and attributes the service
+/// account may read, so it needs no replication rights.
+const DIRSYNC_OBJECT_SECURITY: i64 = 0x0000_0001;
+
+/// Upper bound on the size of one DirSync response.
+const DIRSYNC_MAX_BYTES: i64 = 1 << 20;
+
+/// Attribute Active Directory sets on deleted objects.
+const IS_DELETED: &str = "isDeleted";
+
+/// A change to a watched user entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryChange {
+    /// DN of the entry
+    pub dn: String,
+    /// Username from `username_attribute` or the RDN, if the change carries it
+    pub username: Option<String>,
+    /// Whether the entry was deleted
+    pub deleted: bool,
+}
+
+/// Event produced by a [`ChangeFeed`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncEvent {
+    /// A user entry was added, modified or deleted
+    Changed(DirectoryChange),
+    /// Changes were missed that cannot be attributed to a user; everything
+    /// should be checked again
+    Resync,
+    /// Every change under `base` up to `cookie` has been delivered
+    Cookie {
+        /// Search base the cookie belongs to
+        base: String,
+        /// Opaque resume cookie
+        cookie: Vec<u8>,
+    },
+}
+
+/// Streams changes to user entries under the configured search bases.
+#[derive(Debug, Clone)]
+pub struct ChangeFeed {
+    pool: LdapPool,
+    /// Search bases to watch; DirSync needs naming context roots
+    bases: Vec<String>,
+    /// Filter selecting the watched user entries
+    filter: String,
+    /// Attributes requested with each change
+    attributes: Vec<String>,
+    /// Attribute containing the username
+    username_attribute: String,
+    /// Resolved tracking protocol, never `Auto`
+    mode: ChangeTracking,
+    /// Time between DirSync polls
+    poll_interval: Duration,
+    /// Timeout for each DirSync search
+    read_timeout: Duration,
+}
+
+impl ChangeFeed {
+    /// Creates a change feed.
+    ///
+    /// # Arguments
+    /// * `pool` - Pool used to open the watch connections
+    /// * `bases` - Search bases to watch
+    /// * `filter` - Filter selecting the watched user entries
+    /// * `attributes` - Attributes whose changes matter, requested with each change
+    /// * `username_attribute` - Attribute containing the username
+    /// * `mode` - Tracking protocol; must not be `Auto`
+    /// * `poll_interval` - Time between DirSync polls
+    /// * `read_timeout` - Timeout for each DirSync search
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        pool: LdapPool,
+        bases: Vec<String>,
+        filter: String,
+        attributes: Vec<String>,
+        username_attribute: String,
+        mode: ChangeTracking,
+        poll_interval: Duration,
+        read_timeout: Duration,
+    ) -> Self {
+        Self { pool, bases, filter, attributes, username_attribute, mode, poll_interval, read_timeout }
+    }
+
+    /// Returns the tracking protocol in use.
+    pub fn mode(&self) -> ChangeTracking {
+        self.mode
+    }
+
+    /// Watches every base until a connection fails or the receiver goes away.
+    ///
+    /// # Arguments
+    /// * `cookies` - Resume cookies by search base, from earlier runs
+    /// * `events` - Channel the changes and new cookies are sent to
+    ///
+    /// # Returns
+    /// * `Result<()>` - `Ok` once the receiver is dropped, or the error that ended the watch
+    pub async fn run(&self, cookies: &HashMap<String, Vec<u8>>, events: mpsc::Sender<SyncEvent>) -> Result<(), Error> {
+        let mut tasks = JoinSet::new();
+        for base in &self.bases {
+            let feed = self.clone();
+            let base = base.clone();
+            let cookie = cookies.get(&base).cloned();
+            let events = events.clone();
+            tasks.spawn(async move {
+                match feed.mode {
+                    ChangeTracking::DirSync => feed.dirsync(&base, cookie, &events).await,
+                    _ => feed.syncrepl(&base, cookie, &events).await,
+                }
+            });
+        }
+
+        // Each base runs until its connection fails; one failure restarts them all
+        let result = match tasks.join_next().await {
+            Some(Ok(result)) => result,
+            Some(Err(e)) => Err(Error::ServerError(format!("Change tracking task failed: {}", e))),
+            None => Ok(()),
+        };
+        tasks.abort_all();
+        result
+    }
+
+    /// Follows one base with a refreshAndPersist content synchronization search.
+    async fn syncrepl(&self, base: &str, cookie: Option<Vec<u8>>, events: &mpsc::Sender<SyncEvent>) -> Result<(), Error> {
+        let (mut ldap, url) = self.pool.open_long_lived().await?;
+        info!("Watching {} on {} with syncrepl", base, url);
+
+        // Without a cookie, the refresh phase returns the current content, which
+        // only establishes the starting point
+        let baseline = cookie.is_none();
+        let mut refreshing = true;
+        let mut resync_sent = false;
+        let control = SyncRequest {
+            mode: RefreshMode::RefreshAndPersist,
+            cookie,
+            reload_hint: false,
+        };
+        let mut stream = ldap
+            .with_controls(control.critical())
+            .streaming_search(base, Scope::Subtree, &self.filter, self.attributes.clone())
+            .await?;
+
+        while let Some(entry) = stream.next().await? {
+            if entry.is_ref() {
+                continue;
+            }
+            if entry.is_intermediate() {
+                let (cookie, refresh_done, missed) = match parse_syncinfo(entry) {
+                    SyncInfo::NewCookie(cookie) => (Some(cookie), false, false),
+                    SyncInfo::RefreshDelete { cookie, refresh_done } => (cookie, refresh_done, false),
+                    // Entries left out of a present phase were deleted
+                    SyncInfo::RefreshPresent { cookie, refresh_done } => (cookie, refresh_done, true),
+                    SyncInfo::SyncIdSet { cookie, .. } => (cookie, false, true),
+                };
+                if missed && !baseline && !resync_sent {
+                    debug!("Deletions under {} reported without DNs", base);
+                    resync_sent = true;
+                    send(events, SyncEvent::Resync).await?;
+                }
+                if refresh_done {
+                    debug!("Refresh phase for {} complete", base);
+                    refreshing = false;
+                }
+                if let Some(cookie) = cookie {
+                    send(events, SyncEvent::Cookie { base: base.to_string(), cookie }).await?;
+                }
+                continue;
+            }
+
+            let Some(state) = sync_state(&entry) else {
+                continue;
+            };
+            let change = match state.state {
+                EntryState::Present => None,
+                EntryState::Delete => Some(self.change(SearchEntry::construct(entry), true)),
+                _ if baseline && refreshing => None,
+                _ => Some(self.change(SearchEntry::construct(entry), false)),
+            };
+            if let Some(change) = change {
+                send(events, SyncEvent::Changed(change)).await?;
+            }
+            if let Some(cookie) = state.cookie {
+                send(events, SyncEvent::Cookie { base: base.to_string(), cookie }).await?;
+            }
+        }
+
+        // A persistent search only ends when the server gives up on it
+        let result = stream.finish().await;
+        ldap.unbind().await.ok();
+        result.success()?;
+        Err(Error::ServerError(format!("Content synchronization of {} ended", base)))
+    }
+
+    /// Polls one base with DirSync searches.
+    async fn dirsync(&self, base: &str, mut cookie: Option<Vec<u8>>, events: &mpsc::Sender<SyncEvent>) -> Result<(), Error> {
+        let (mut ldap, url) = self.pool.open_long_lived().await?;
+        info!("Watching {} on {} with DirSync every {:?}", base, url, self.poll_interval);
+
+        // Without a cookie, the first pass returns every object, which only
+        // establishes the starting point
+        let mut baseline = cookie.is_none();
+        let mut attributes = self.attributes.clone();
+        attributes.push(IS_DELETED.to_string());
+
+        loop {
+            let control = DirSync {
+                flags: DIRSYNC_OBJECT_SECURITY,
+                max_bytes: DIRSYNC_MAX_BYTES,
+                cookie: cookie.clone().unwrap_or_default(),
+            };
+            let mut stream = ldap
+                .with_controls(control.critical())
+                .with_timeout(self.read_timeout)
+                .streaming_search(base, Scope::Subtree, &self.filter, attributes.clone())
+                .await?;
+
+            let mut changes = Vec::new();
+            while let Some(entry) = stream.next().await? {
+                if baseline || entry.is_ref() || entry.is_intermediate() {
+                    continue;
+                }
+                let entry = SearchEntry::construct(entry);
+                let deleted = entry
+                    .attrs
+                    .get(IS_DELETED)
+                    .and_then(|values| values.first())
+                    .is_some_and(|value| value.eq_ignore_ascii_case("TRUE"));
+                changes.push(self.change(entry, deleted));
+            }
+            let result = stream.finish().await;
+            let response = result
+                .ctrls
+                .iter()
+                .find(|ctrl| ctrl.1.ctype == DIRSYNC_OID)
+                .and_then(|ctrl| ctrl.1.val.as_deref())
+                .and_then(DirSyncResponse::parse);
+            result.success()?;
+            let response = response.ok_or_else(|| Error::ServerError(format!("No DirSync response control for {}", base)))?;
+
+            for change in changes {
+                send(events, SyncEvent::Changed(change)).await?;
+            }
+            send(events, SyncEvent::Cookie { base: base.to_string(), cookie: response.cookie.clone() }).await?;
+            cookie = Some(response.cookie);
+
+            if !response.more_results {
+                if baseline {
+                    debug!("Initial DirSync of {} complete", base);
+                    baseline = false;
+                }
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        }
+    }
+
+    /// Builds a change from an entry, taking the username from
+    /// `username_attribute` or, failing that, from the RDN.
+    fn change(&self, entry: SearchEntry, deleted: bool) -> DirectoryChange {
+        let username = entry
+            .attrs
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&self.username_attribute))
+            .and_then(|(_, values)| values.first())
+            .cloned()
+            .or_else(|| {
+                let rdn = entry.dn.split(',').next()?;
+                let (attribute, value) = rdn.split_once('=')?;
+                attribute
+                    .trim()
+                    .eq_ignore_ascii_case(&self.username_attribute)
+                    .then(|| value.trim().to_string())
+            });
+        DirectoryChange { dn: entry.dn, username, deleted }
+    }
+}
+
+/// Sends an event, failing once the receiver is gone.
+async fn send(events: &mpsc::Sender<SyncEvent>, event: SyncEvent) -> Result<(), Error> {
+    events
+        .send(event)
+        .await
+        .map_err(|_| Error::ServerError("Change tracking receiver closed".to_string()))
+}
+
+/// Extracts the sync state control from a syncrepl search entry.
+fn sync_state(entry: &ResultEntry) -> Option<SyncState> {
+    entry
+        .1
+        .iter()
+        .find(|ctrl| matches!(ctrl, Control(Some(ControlType::SyncState), raw) if raw.val.is_some()))
+        .map(|ctrl| ctrl.1.parse::<SyncState>())
+}
+
+/// DirSync request control.
+///
+/// ```text
+/// DirSyncRequestValue ::= SEQUENCE {
+///     Flags      INTEGER,
+///     MaxBytes   INTEGER,
+///     Cookie     OCTET STRING }
+/// ```
+#[derive(Debug, Clone)]
+struct DirSync {
+    flags: i64,
+    max_bytes: i64,
+    cookie: Vec<u8>,
+}
+
+impl MakeCritical for DirSync {}
+
+impl From<DirSync> for RawControl {
+    fn from(dirsync: DirSync) -> RawControl {
+        let capacity = dirsync.cookie.len() + 24;
+        let value = Tag::Sequence(Sequence {
+            inner: vec![
+                Tag::Integer(Integer {
+                    inner: dirsync.flags,
+                    ..Default::default()
+                }),
+                Tag::Integer(Integer {
+                    inner: dirsync.max_bytes,
+                    ..Default::default()
+                }),
+                Tag::OctetString(OctetString {
+                    inner: dirsync.cookie,
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        })
+        .into_structure();
+        let mut buf = BytesMut::with_capacity(capacity);
+        write::encode_into(&mut buf, value).expect("encoded");
+        RawControl {
+            ctype: DIRSYNC_OID.to_string(),
+            crit: true,
+            val: Some(buf.to_vec()),
+        }
+    }
+}
+
+/// DirSync response control.
+///
+/// ```text
+/// DirSyncResponseValue ::= SEQUENCE {
+///     MoreResults     INTEGER,
+///     unused          INTEGER,
+///     CookieServer    OCTET STRING }
+/// ```
+#[derive(Debug, Clone)]
+struct DirSyncResponse {
+    more_results: bool,
+    cookie: Vec<u8>,
+}
+
+impl DirSyncResponse {
+    fn parse(val: &[u8]) -> Option<Self> {
+        let (_, tag) = parse_tag(val).ok()?;
+        let mut elements = tag.expect_constructed()?.into_iter();
+        let more_results = elements.next()?.expect_primitive()?;
+        let (_, more_results) = parse_uint(&more_results).ok()?;
+        let _unused = elements.next()?;
+        let cookie = elements.next()?.expect_primitive()?;
+        Some(DirSyncResponse {
+            more_results: more_results != 0,
+            cookie,
+        })
+    }
+}
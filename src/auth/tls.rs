@@ -0,0 +1,128 @@
+//! TLS settings for LDAP connections.
+//!
+//! This module turns the TLS part of the LDAP configuration (mode, PEM CA bundle,
+//! optional client certificate and hostname verification) into the connection
+//! settings used by `ldap3`. The PEM files are read once, when the connection
+//! pool is created, so a bad path or certificate fails at startup rather than on
+//! the first registration attempt.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use ldap3::LdapConnSettings;
+use native_tls::{Certificate, Identity, TlsConnector};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+pub use crate::config::TlsMode;
+
+use super::ldap::{Error, LdapConfig};
+
+/// TLS configuration for LDAP connections.
+#[derive(Debug, Clone)]
+pub struct LdapTlsConfig {
+    /// How the connection is secured
+    pub mode: TlsMode,
+    /// PEM bundle of CA certificates to trust in addition to the system roots
+    pub ca_bundle: Option<PathBuf>,
+    /// PEM client certificate presented to the server
+    pub client_cert: Option<PathBuf>,
+    /// PEM (PKCS#8) private key for the client certificate
+    pub client_key: Option<PathBuf>,
+    /// Whether the server certificate must match the host name in the URL
+    pub verify_hostname: bool,
+}
+
+impl LdapTlsConfig {
+    /// Resolves the TLS settings from the service configuration.
+    ///
+    /// When `tls_mode` is not set, `ldaps://` URLs use LDAPS, `use_ssl` on an
+    /// `ldap://` URL uses StartTLS, and anything else stays in plain text. With
+    /// several URLs, the first one decides.
+    pub fn from_config(config: &crate::config::LdapConfig) -> Self {
+        let url = config.urls.first().unwrap_or(&config.url);
+        let mode = config.tls_mode.unwrap_or(if url.starts_with("ldaps://") {
+            TlsMode::Ldaps
+        } else if config.use_ssl {
+            TlsMode::StartTls
+        } else {
+            TlsMode::Plain
+        });
+
+        LdapTlsConfig {
+            mode,
+            ca_bundle: config.ca_bundle.as_ref().map(PathBuf::from),
+            client_cert: config.client_cert.as_ref().map(PathBuf::from),
+            client_key: config.client_key.as_ref().map(PathBuf::from),
+            verify_hostname: config.hostname_verification.unwrap_or(true),
+        }
+    }
+}
+
+/// Builds the `ldap3` connection settings for the configured TLS mode and timeouts.
+///
+/// # Arguments
+/// * `config` - LDAP configuration
+///
+/// # Returns
+/// * `Result<LdapConnSettings>` - Connection settings or error if the TLS material is invalid
+pub fn connection_settings(config: &LdapConfig) -> Result<LdapConnSettings, Error> {
+    let tls = &config.tls;
+    for url in &config.urls {
+        check_scheme(url, tls.mode)?;
+    }
+
+    let settings = LdapConnSettings::new().set_conn_timeout(config.connection_timeout);
+    if tls.mode == TlsMode::Plain {
+        return Ok(settings);
+    }
+
+    let mut builder = TlsConnector::builder();
+    if let Some(path) = &tls.ca_bundle {
+        let pem = read(path)?;
+        let certs = Certificate::stack_from_pem(&pem)
+            .map_err(|e| Error::TlsConfig(format!("invalid CA bundle {}: {}", path.display(), e)))?;
+        debug!("Loaded {} CA certificate(s) from {}", certs.len(), path.display());
+        for cert in certs {
+            builder.add_root_certificate(cert);
+        }
+    }
+    match (&tls.client_cert, &tls.client_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let identity = Identity::from_pkcs8(&read(cert_path)?, &read(key_path)?)
+                .map_err(|e| Error::TlsConfig(format!("invalid client certificate or key: {}", e)))?;
+            builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(Error::TlsConfig(
+                "client_cert and client_key must be set together".to_string(),
+            ))
+        }
+    }
+    builder.danger_accept_invalid_hostnames(!tls.verify_hostname);
+
+    let connector = builder
+        .build()
+        .map_err(|e| Error::TlsConfig(format!("failed to build TLS connector: {}", e)))?;
+
+    Ok(settings
+        .set_connector(connector)
+        .set_starttls(tls.mode == TlsMode::StartTls))
+}
+
+/// Rejects URL schemes that contradict the TLS mode, since `ldap3` picks LDAPS
+/// purely from the scheme.
+pub(crate) fn check_scheme(url: &str, mode: TlsMode) -> Result<(), Error> {
+    let ldaps = url.starts_with("ldaps://");
+    match mode {
+        TlsMode::Ldaps if !ldaps => Err(Error::TlsConfig(format!("tls_mode ldaps requires an ldaps:// URL, got {}", url))),
+        TlsMode::Plain | TlsMode::StartTls if ldaps => {
+            Err(Error::TlsConfig(format!("tls_mode {:?} requires an ldap:// URL, got {}", mode, url)))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, Error> {
+    std::fs::read(path).map_err(|e| Error::TlsConfig(format!("failed to read {}: {}", path.display(), e)))
+}
@@ -0,0 +1,193 @@
+//! Username mapping.
+//!
+//! Users sign in with whatever name they know: a plain login, an email-style
+//! `user@domain` name or, on Active Directory, a down-level `DOMAIN\user` name.
+//! This module classifies the name and holds the rules that turn each form into
+//! a directory search. In Active Directory mode, `user@domain` names are searched
+//! by `userPrincipalName` and `DOMAIN\user` names by `sAMAccountName`, so users
+//! with the same login in different domains of a forest stay distinct.
+//!
+//! Email-style names go through a lookup chain: the username rules first, then
+//! attributes holding email addresses such as `mail`, so `j.smith@example.com`
+//! still finds the entry whose uid is `jsmith`.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use std::collections::HashMap;
+
+pub use crate::config::DirectoryType;
+
+/// Active Directory search for a user by `userPrincipalName`.
+pub const AD_UPN_FILTER: &str = "(&(objectCategory=person)(objectClass=user)(userPrincipalName={0}))";
+
+/// Active Directory search for a user by `sAMAccountName`.
+pub const AD_SAM_FILTER: &str = "(&(objectCategory=person)(objectClass=user)(sAMAccountName={0}))";
+
+/// Canonical username attribute of generic directories.
+pub const DEFAULT_USERNAME_ATTRIBUTE: &str = "uid";
+
+/// Canonical username attribute in Active Directory mode.
+pub const AD_USERNAME_ATTRIBUTE: &str = "userPrincipalName";
+
+/// One step of the lookup chain for email-style names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmailLookup {
+    /// The username rules: `upn_filter`, or `user_filter` with the local part
+    Username,
+    /// Active Directory `proxyAddresses`, whose values carry an `smtp:` prefix
+    ProxyAddresses,
+    /// Any other attribute holding plain email addresses, e.g. `mail`
+    Attribute(String),
+}
+
+impl EmailLookup {
+    fn parse(step: &str) -> Self {
+        match step {
+            "username" => EmailLookup::Username,
+            _ if step.eq_ignore_ascii_case("proxyAddresses") => EmailLookup::ProxyAddresses,
+            _ => EmailLookup::Attribute(step.to_string()),
+        }
+    }
+
+    /// Returns the filter template for an attribute lookup, or `None` for the
+    /// username rules.
+    pub fn filter_template(&self) -> Option<String> {
+        match self {
+            EmailLookup::Username => None,
+            EmailLookup::ProxyAddresses => Some("(proxyAddresses=smtp:{0})".to_string()),
+            EmailLookup::Attribute(attribute) => Some(format!("({}={{0}})", attribute)),
+        }
+    }
+}
+
+/// The form of a username as typed by the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsernameForm<'a> {
+    /// `user@domain`
+    Upn {
+        /// Part before the `@`
+        local: &'a str,
+    },
+    /// `DOMAIN\user`
+    DownLevel {
+        /// NetBIOS domain name
+        domain: &'a str,
+        /// Account name within the domain
+        user: &'a str,
+    },
+    /// Anything else
+    Plain,
+}
+
+/// Returns the account part of a username in any of its forms: `user` for
+/// `user`, `user@domain` and `DOMAIN\user`. Unlike [`UsernameMapping::classify`]
+/// it does not depend on the configuration, so it keys every alias of an
+/// account the same way.
+///
+/// # Arguments
+/// * `username` - Name as typed by the user
+pub fn account_name(username: &str) -> &str {
+    let user = username.split_once('\\').map_or(username, |(_, user)| user);
+    user.split_once('@').map_or(user, |(local, _)| local)
+}
+
+/// Resolved username mapping rules.
+#[derive(Debug, Clone)]
+pub struct UsernameMapping {
+    /// Kind of directory server
+    pub directory_type: DirectoryType,
+    /// Filter for `user@domain` names; `None` drops the domain and uses `user_filter`
+    pub upn_filter: Option<String>,
+    /// Filter for `DOMAIN\user` names; `None` leaves such names as they are
+    pub down_level_filter: Option<String>,
+    /// Search base per NetBIOS domain, keyed by the upper-cased domain name
+    pub domains: HashMap<String, String>,
+    /// Whether to bind with the UPN instead of the entry's DN
+    pub bind_with_upn: bool,
+    /// Lookups tried in order for `user@domain` names
+    pub email_lookup: Vec<EmailLookup>,
+}
+
+impl UsernameMapping {
+    /// Resolves the mapping rules from the service configuration, filling in the
+    /// Active Directory defaults when `directory_type` is `active_directory`.
+    pub fn from_config(config: &crate::config::LdapConfig) -> Self {
+        let mapping = &config.username_mapping;
+        let ad = config.directory_type == DirectoryType::ActiveDirectory;
+
+        UsernameMapping {
+            directory_type: config.directory_type,
+            upn_filter: mapping
+                .upn_filter
+                .clone()
+                .or_else(|| ad.then(|| AD_UPN_FILTER.to_string())),
+            down_level_filter: mapping
+                .down_level_filter
+                .clone()
+                .or_else(|| ad.then(|| AD_SAM_FILTER.to_string())),
+            domains: mapping
+                .domains
+                .iter()
+                .map(|(domain, base_dn)| (domain.to_ascii_uppercase(), base_dn.clone()))
+                .collect(),
+            bind_with_upn: mapping.bind_with_upn.unwrap_or(ad),
+            email_lookup: if mapping.email_lookup.is_empty() {
+                let mailboxes = if ad { "proxyAddresses" } else { "mailAlternateAddress" };
+                ["username", "mail", mailboxes].into_iter().map(EmailLookup::parse).collect()
+            } else {
+                mapping.email_lookup.iter().map(|step| EmailLookup::parse(step)).collect()
+            },
+        }
+    }
+
+    /// Returns the default canonical username attribute for the directory type.
+    pub fn default_username_attribute(&self) -> &'static str {
+        match self.directory_type {
+            DirectoryType::ActiveDirectory => AD_USERNAME_ATTRIBUTE,
+            DirectoryType::Generic => DEFAULT_USERNAME_ATTRIBUTE,
+        }
+    }
+
+    /// Returns the default user filter for the directory type.
+    ///
+    /// # Arguments
+    /// * `username_attribute` - Attribute used by generic directories
+    pub fn default_user_filter(&self, username_attribute: &str) -> String {
+        match self.directory_type {
+            DirectoryType::ActiveDirectory => AD_SAM_FILTER.to_string(),
+            DirectoryType::Generic => format!("({}={{0}})", username_attribute),
+        }
+    }
+
+    /// Classifies a username. `DOMAIN\user` is only recognized when a
+    /// down-level filter is configured.
+    ///
+    /// # Arguments
+    /// * `username` - Name as typed by the user
+    pub fn classify<'a>(&self, username: &'a str) -> UsernameForm<'a> {
+        if self.down_level_filter.is_some() {
+            if let Some((domain, user)) = username.split_once('\\') {
+                return UsernameForm::DownLevel { domain, user };
+            }
+        }
+        match username.split_once('@') {
+            Some((local, _)) => UsernameForm::Upn { local },
+            None => UsernameForm::Plain,
+        }
+    }
+
+    /// Returns the search bases for a NetBIOS domain.
+    ///
+    /// # Arguments
+    /// * `domain` - Domain from a `DOMAIN\user` name
+    /// * `bases` - Default search bases, used when no domains are configured
+    ///
+    /// # Returns
+    /// * `Option<Vec<String>>` - The search bases, or `None` if the domain is not accepted
+    pub fn domain_bases(&self, domain: &str, bases: &[String]) -> Option<Vec<String>> {
+        if self.domains.is_empty() {
+            return Some(bases.to_vec());
+        }
+        self.domains.get(&domain.to_ascii_uppercase()).map(|base| vec![base.clone()])
+    }
+}
@@ -1,18 +1,19 @@
-/// Configuration Module
-///
-/// Provides configuration management for the Signal Registration Service.
-/// Handles loading and parsing of YAML configuration files and environment variables.
-/// Supports multiple environments (development, production) and local overrides.
-///
-/// # Copyright
-/// Copyright (c) 2025 Signal Messenger, LLC
-/// All rights reserved.
-///
-/// # License
-/// Licensed under the AGPLv3 license.
-/// Please see the LICENSE file in the root directory for details.
+//! Configuration Module
+//!
+//! Provides configuration management for the Signal Registration Service.
+//! Handles loading and parsing of YAML configuration files and environment variables.
+//! Supports multiple environments (development, production) and local overrides.
+//!
+//! # Copyright
+//! Copyright (c) 2025 Signal Messenger, LLC
+//! All rights reserved.
+//!
+//! # License
+//! Licensed under the AGPLv3 license.
+//! Please see the LICENSE file in the root directory for details.
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use thiserror::Error;
 use config::{Config as ConfigFile, File, Environment};
 
@@ -28,10 +29,17 @@ pub struct Application {
 pub struct Metrics {
     /// Whether metrics collection is enabled
     pub enabled: bool,
+    /// Port of the Prometheus `/metrics` endpoint
+    #[serde(default = "default_metrics_port")]
+    pub port: u16,
     /// Metrics export configuration
     pub export: MetricsExport,
 }
 
+fn default_metrics_port() -> u16 {
+    9090
+}
+
 /// Metrics export configuration
 #[derive(Debug, Deserialize, Serialize)]
 pub struct MetricsExport {
@@ -51,7 +59,7 @@ pub struct DatadogConfig {
 pub struct RateLimits {
     /// Check verification code rate limits
     #[serde(rename = "check_verification_code")]
-    pub check_verification_code: DelayConfig,
+    pub check_verification_code: CheckCodeConfig,
     /// Leaky bucket rate limits
     pub leaky_bucket: LeakyBucketConfig,
     /// SMS verification code rate limits
@@ -72,6 +80,23 @@ pub struct DelayConfig {
     pub delays_seconds: Option<String>,
 }
 
+/// Verification code check configuration
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CheckCodeConfig {
+    /// Delay in seconds
+    pub delays: u64,
+    /// Java-compatible delay string (ignored)
+    #[serde(rename = "delays_seconds", skip_serializing_if = "Option::is_none")]
+    pub delays_seconds: Option<String>,
+    /// Codes a session may check per phone number before it is invalidated
+    #[serde(default = "default_max_check_attempts")]
+    pub max_attempts: u32,
+}
+
+fn default_max_check_attempts() -> u32 {
+    5
+}
+
 /// Voice delay configuration
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct VoiceDelayConfig {
@@ -116,6 +141,15 @@ pub struct LeakyBucketConfig {
 pub struct LdapConfig {
     /// LDAP server URL
     pub url: String,
+    /// LDAP server URLs in priority order; when set, replaces `url`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub urls: Vec<String>,
+    /// How the server for a new connection is chosen
+    #[serde(default)]
+    pub server_selection: ServerSelection,
+    /// How long in milliseconds a server stays out of rotation after a connection error
+    #[serde(default = "default_server_cooldown")]
+    pub server_cooldown: u64,
     /// Base DN for LDAP searches
     pub base_dn: String,
     /// Whether to use SSL
@@ -126,8 +160,29 @@ pub struct LdapConfig {
     pub bind_password: String,
     /// Phone number attribute
     pub phone_number_attribute: String,
-    /// Username attribute
-    pub username_attribute: String,
+    /// Ordered phone number attributes to try; defaults to `[phone_number_attribute]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub phone_number_attributes: Vec<String>,
+    /// How to choose between several numbers in the same attribute
+    #[serde(default)]
+    pub phone_selection: PhoneSelection,
+    /// Attribute holding the canonical username; defaults to `uid`, or
+    /// `userPrincipalName` in Active Directory mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username_attribute: Option<String>,
+    /// Extra attributes fetched with the user entry and stored with the
+    /// registration, e.g. `displayName` or `department`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profile_attributes: Vec<String>,
+    /// Region (ISO 3166-1 alpha-2, e.g. "US") assumed for phone numbers without a country code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_phone_region: Option<String>,
+    /// DNs of the groups whose members may register; empty allows every user
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authorized_groups: Vec<String>,
+    /// How membership in `authorized_groups` is checked
+    #[serde(default)]
+    pub group_membership: GroupMembership,
     /// Connection timeout in milliseconds
     pub connection_timeout: u64,
     /// Read timeout in milliseconds
@@ -138,23 +193,193 @@ pub struct LdapConfig {
     pub max_pool_size: u32,
     /// Pool timeout in milliseconds
     pub pool_timeout: u64,
-    /// Maximum number of retries
+    /// Maximum number of retries, on another server where possible, after a connection error
     pub max_retries: u32,
-    /// Java-specific user filter (ignored)
+    /// Idle time in milliseconds after which pooled connections are health-checked or evicted
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+    /// User search filter template; `{0}` or `%s` is replaced with the escaped username.
+    /// Defaults to `(<username_attribute>={0})`, or a `sAMAccountName` search in
+    /// Active Directory mode
     #[serde(rename = "user_filter", skip_serializing_if = "Option::is_none")]
     pub user_filter: Option<String>,
-    /// Java-specific trust store path (ignored)
+    /// Search bases for users, in order of preference; defaults to `[base_dn]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub search_bases: Vec<String>,
+    /// Whether `search_bases` are searched one after the other or all at once
+    #[serde(default)]
+    pub search_mode: SearchMode,
+    /// Whether to follow referrals returned by searches
+    #[serde(default)]
+    pub follow_referrals: bool,
+    /// Hosts, besides those in `url` and `urls`, whose referrals may be followed
+    /// with the service account over TLS; `host` or `host:port`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub referral_hosts: Vec<String>,
+    /// Maximum number of referrals followed in a row
+    #[serde(default = "default_referral_hop_limit")]
+    pub referral_hop_limit: u32,
+    /// Page size for the paged results control; 0 disables paging
+    #[serde(default)]
+    pub page_size: u32,
+    /// Kind of directory server; `active_directory` turns on UPN and `DOMAIN\user` handling
+    #[serde(default)]
+    pub directory_type: DirectoryType,
+    /// How `user@domain` and `DOMAIN\user` names are looked up
+    #[serde(default)]
+    pub username_mapping: UsernameMappingConfig,
+    /// TLS mode; derived from the URL scheme and `use_ssl` when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_mode: Option<TlsMode>,
+    /// Path to a PEM bundle of CA certificates trusted for the LDAP server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<String>,
+    /// Path to a PEM client certificate for mutual TLS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<String>,
+    /// Path to the PEM (PKCS#8) private key for `client_cert`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+    /// Whether to verify the server certificate's hostname (defaults to true)
+    #[serde(rename = "hostnameVerification", alias = "hostname_verification", skip_serializing_if = "Option::is_none")]
+    pub hostname_verification: Option<bool>,
+    /// Java-specific trust store path (ignored, use `ca_bundle`)
     #[serde(rename = "trustStore", skip_serializing_if = "Option::is_none")]
     pub trust_store: Option<String>,
-    /// Java-specific trust store password (ignored)
+    /// Java-specific trust store password (ignored, use `ca_bundle`)
     #[serde(rename = "trustStorePassword", skip_serializing_if = "Option::is_none")]
     pub trust_store_password: Option<String>,
-    /// Java-specific trust store type (ignored)
+    /// Java-specific trust store type (ignored, use `ca_bundle`)
     #[serde(rename = "trustStoreType", skip_serializing_if = "Option::is_none")]
     pub trust_store_type: Option<String>,
-    /// Java-specific hostname verification (ignored)
-    #[serde(rename = "hostnameVerification", skip_serializing_if = "Option::is_none")]
-    pub hostname_verification: Option<bool>,
+}
+
+/// Policy for multi-valued phone number attributes
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PhoneSelection {
+    /// Use the first value
+    #[default]
+    First,
+    /// Use the first mobile-type number, falling back to the first value
+    PreferMobile,
+    /// Treat several distinct numbers as ambiguous and let the user choose
+    Reject,
+}
+
+/// How LDAP group membership is determined
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupMembership {
+    /// Compare the user's `memberOf` attribute with the group DNs
+    #[default]
+    MemberOf,
+    /// Look the user's DN up in each group's `member`/`uniqueMember` attribute
+    GroupSearch,
+    /// Active Directory transitive lookup that includes nested groups
+    AdNested,
+}
+
+/// How the server for a new LDAP connection is chosen
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerSelection {
+    /// Always prefer the first server in rotation
+    #[default]
+    Priority,
+    /// Spread connections over the servers in rotation
+    RoundRobin,
+}
+
+/// How several search bases are searched
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    /// One base after the other; the first base with a match wins
+    #[default]
+    Sequential,
+    /// All bases at once; the first base (in order) with a match still wins
+    Parallel,
+}
+
+/// Kind of directory server
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectoryType {
+    /// Any LDAPv3 server, e.g. OpenLDAP
+    #[default]
+    Generic,
+    /// Microsoft Active Directory
+    ActiveDirectory,
+}
+
+/// Rules for mapping the username a user types to a directory search
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct UsernameMappingConfig {
+    /// Filter for `user@domain` names, with `{0}` replaced by the whole name.
+    /// When unset (the default outside Active Directory mode), the domain is
+    /// dropped and `user_filter` is used with the part before the `@`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upn_filter: Option<String>,
+    /// Filter for `DOMAIN\user` names, with `{0}` replaced by the user part.
+    /// When unset (the default outside Active Directory mode), such names are
+    /// not treated specially
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub down_level_filter: Option<String>,
+    /// Search base for each NetBIOS domain accepted in `DOMAIN\user` names;
+    /// empty searches `base_dn` for every domain
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub domains: HashMap<String, String>,
+    /// Whether to bind with the UPN itself instead of the entry's DN
+    /// (defaults to true in Active Directory mode)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_with_upn: Option<bool>,
+    /// Lookups tried in order for `user@domain` names until one finds an entry:
+    /// `username` (the username rules above) or an attribute holding email
+    /// addresses, e.g. `mail`, `mailAlternateAddress` or `proxyAddresses`.
+    /// Defaults to `[username, mail, mailAlternateAddress]`, or
+    /// `[username, mail, proxyAddresses]` in Active Directory mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub email_lookup: Vec<String>,
+}
+
+/// Protocol used to track directory changes
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeTracking {
+    /// DirSync in Active Directory mode, syncrepl otherwise
+    #[default]
+    Auto,
+    /// RFC 4533 content synchronization (OpenLDAP)
+    Syncrepl,
+    /// Active Directory DirSync
+    #[serde(rename = "dirsync")]
+    DirSync,
+}
+
+/// How the connection to the LDAP server is secured
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    /// Unencrypted `ldap://` connection
+    Plain,
+    /// TLS from the first byte on an `ldaps://` connection
+    Ldaps,
+    /// `ldap://` connection upgraded with the StartTLS extended operation
+    StartTls,
+}
+
+fn default_idle_timeout() -> u64 {
+    60000
+}
+
+fn default_server_cooldown() -> u64 {
+    30000
+}
+
+fn default_referral_hop_limit() -> u32 {
+    3
 }
 
 /// DynamoDB configuration
@@ -170,6 +395,266 @@ pub struct DynamoDbConfig {
     pub endpoint: Option<String>,
 }
 
+/// Directory reconciliation configuration
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconciliationConfig {
+    /// Whether the server runs the reconciliation periodically
+    pub enabled: bool,
+    /// Seconds between periodic runs
+    pub interval_secs: u64,
+    /// Report registrations that would be revoked without deleting them
+    pub dry_run: bool,
+    /// Registrations read from DynamoDB per page
+    pub page_size: u32,
+    /// Most registrations one run may delete; a run that would delete more
+    /// deletes none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_deletions: Option<usize>,
+    /// Largest share of the scanned registrations one run may delete, from 0 to 1
+    pub max_deletion_fraction: f64,
+}
+
+impl Default for ReconciliationConfig {
+    fn default() -> Self {
+        ReconciliationConfig {
+            enabled: false,
+            interval_secs: 86400,
+            dry_run: false,
+            page_size: 100,
+            max_deletions: None,
+            max_deletion_fraction: 0.1,
+        }
+    }
+}
+
+/// Directory change tracking configuration
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchConfig {
+    /// Whether the server watches the directory for changes
+    pub enabled: bool,
+    /// Change tracking protocol
+    pub mode: ChangeTracking,
+    /// Filter selecting the watched user entries; defaults to `(objectClass=person)`,
+    /// or `(objectClass=user)` in Active Directory mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    /// File the resume cookies are persisted to
+    pub cookie_file: String,
+    /// Seconds between DirSync polls
+    pub poll_interval_secs: u64,
+    /// Seconds to wait before reconnecting after an error
+    pub reconnect_delay_secs: u64,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        WatchConfig {
+            enabled: false,
+            mode: ChangeTracking::Auto,
+            filter: None,
+            cookie_file: "data/ldap_sync_cookies.json".to_string(),
+            poll_interval_secs: 30,
+            reconnect_delay_secs: 10,
+        }
+    }
+}
+
+/// Where users and their phone numbers come from
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentityProviderKind {
+    /// The LDAP directory
+    Ldap,
+    /// A YAML or CSV file of users with bcrypt or argon2 password hashes
+    File,
+    /// An htpasswd file plus a file mapping usernames to phone numbers
+    Htpasswd,
+}
+
+/// Identity provider configuration
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentityConfig {
+    /// Identity provider; defaults to `ldap` when `use_ldap` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<IdentityProviderKind>,
+    /// Users file for the `file` provider, `.yml`/`.yaml` or `.csv`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub users_file: Option<String>,
+    /// htpasswd file for the `htpasswd` provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub htpasswd_file: Option<String>,
+    /// File of `username:number[,number...]` lines for the `htpasswd` provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone_map_file: Option<String>,
+    /// Region assumed for file phone numbers without a country code (e.g. "US")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_phone_region: Option<String>,
+    /// How to choose between several numbers of a file user
+    pub phone_selection: PhoneSelection,
+}
+
+impl IdentityConfig {
+    /// Returns the configured provider, or the default for the `use_ldap` setting:
+    /// `ldap` when it is set, `file` otherwise.
+    pub fn provider(&self, use_ldap: bool) -> IdentityProviderKind {
+        self.provider.unwrap_or(if use_ldap {
+            IdentityProviderKind::Ldap
+        } else {
+            IdentityProviderKind::File
+        })
+    }
+}
+
+/// Failed sign-in throttling configuration
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoginLockoutConfig {
+    /// Whether failed sign-ins are throttled
+    pub enabled: bool,
+    /// Failures allowed before backoff starts
+    pub free_attempts: u32,
+    /// Delay in seconds after the first failure past `free_attempts`; doubles with each further failure
+    pub backoff_base_secs: u64,
+    /// Longest backoff delay in seconds
+    pub backoff_max_secs: u64,
+    /// Failures for one username that lock it out
+    pub lockout_threshold: u32,
+    /// Failures from one client address that lock it out
+    pub address_lockout_threshold: u32,
+    /// Seconds a lockout lasts
+    pub lockout_secs: u64,
+    /// Seconds without failures after which the count starts over
+    pub reset_after_secs: u64,
+    /// Metadata header carrying the client address when behind a proxy (e.g. "x-forwarded-for");
+    /// the connection's peer address is used otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_address_header: Option<String>,
+}
+
+impl Default for LoginLockoutConfig {
+    fn default() -> Self {
+        LoginLockoutConfig {
+            enabled: true,
+            free_attempts: 3,
+            backoff_base_secs: 1,
+            backoff_max_secs: 60,
+            lockout_threshold: 10,
+            address_lockout_threshold: 50,
+            lockout_secs: 900,
+            reset_after_secs: 900,
+            client_address_header: None,
+        }
+    }
+}
+
+/// Where registration sessions are kept
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionStoreKind {
+    /// In this process; sessions are lost on restart and not shared between replicas
+    #[default]
+    Memory,
+    /// A DynamoDB table with native TTL, shared between replicas
+    Dynamodb,
+}
+
+/// Registration session storage configuration
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Session store
+    pub store: SessionStoreKind,
+    /// DynamoDB table of the `dynamodb` store, keyed by `session_id` with TTL on `expires_at`
+    pub table_name: String,
+    /// Most live sessions the store holds before new registrations are refused; 0 for no limit
+    pub max_sessions: usize,
+    /// Seconds between runs of the background task deleting expired sessions
+    pub reap_interval_secs: u64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            store: SessionStoreKind::Memory,
+            table_name: "registration_sessions".to_string(),
+            max_sessions: 100_000,
+            reap_interval_secs: 60,
+        }
+    }
+}
+
+/// Whose bind writes a verified phone number to the directory
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteBackBind {
+    /// The service account (`bind_dn`)
+    #[default]
+    Service,
+    /// The user, with the password given to StartRegistration
+    User,
+}
+
+/// Phone number write-back configuration
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PhoneWriteBackConfig {
+    /// Whether users without a directory phone number may supply one
+    pub enabled: bool,
+    /// Attribute the verified number is written to; defaults to the first
+    /// phone number attribute
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attribute: Option<String>,
+    /// Whose bind writes the number; approved numbers are always written by the
+    /// service account
+    pub bind_as: WriteBackBind,
+    /// Hold verified numbers until an operator approves them
+    pub require_approval: bool,
+    /// DynamoDB table holding the numbers waiting for approval
+    pub approval_table: String,
+}
+
+impl Default for PhoneWriteBackConfig {
+    fn default() -> Self {
+        PhoneWriteBackConfig {
+            enabled: false,
+            attribute: None,
+            bind_as: WriteBackBind::Service,
+            require_approval: false,
+            approval_table: "phone_number_approvals".to_string(),
+        }
+    }
+}
+
+/// Self-service phone number change configuration
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PhoneChangeConfig {
+    /// Whether users may move their registration to a new number
+    pub enabled: bool,
+    /// Also require a code sent to the old number
+    pub confirm_old_number: bool,
+    /// Attribute the new number is written to; defaults to the first phone
+    /// number attribute
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attribute: Option<String>,
+    /// Whose bind writes the new number
+    pub bind_as: WriteBackBind,
+}
+
+impl Default for PhoneChangeConfig {
+    fn default() -> Self {
+        PhoneChangeConfig {
+            enabled: false,
+            confirm_old_number: false,
+            attribute: None,
+            bind_as: WriteBackBind::Service,
+        }
+    }
+}
+
 /// Twilio configuration
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TwilioConfig {
@@ -224,6 +709,27 @@ pub struct RegistrationConfig {
     pub twilio: TwilioConfig,
     /// Rate limiting configuration
     pub rate_limits: RateLimits,
+    /// Identity provider configuration
+    #[serde(default)]
+    pub identity: IdentityConfig,
+    /// Failed sign-in throttling configuration
+    #[serde(default)]
+    pub login_lockout: LoginLockoutConfig,
+    /// Registration session storage configuration
+    #[serde(default)]
+    pub sessions: SessionConfig,
+    /// Directory reconciliation configuration
+    #[serde(default)]
+    pub reconciliation: ReconciliationConfig,
+    /// Directory change tracking configuration
+    #[serde(default)]
+    pub watch: WatchConfig,
+    /// Phone number write-back configuration
+    #[serde(default)]
+    pub phone_write_back: PhoneWriteBackConfig,
+    /// Self-service phone number change configuration
+    #[serde(default)]
+    pub phone_change: PhoneChangeConfig,
 }
 
 /// Environment configuration
@@ -294,7 +800,7 @@ impl Config {
     ///
     /// # Examples
     /// ```no_run
-    /// use registration_service::config::Config;
+    /// use rust_ldap_registration::config::Config;
     ///
     /// let config = Config::new().expect("Failed to load configuration");
     /// println!("LDAP URL: {}", config.registration().ldap.url);
@@ -11,13 +11,15 @@ use aws_sdk_dynamodb::error::SdkError;
 use aws_sdk_dynamodb::operation::delete_item::DeleteItemError;
 use aws_sdk_dynamodb::operation::get_item::GetItemError;
 use aws_sdk_dynamodb::operation::put_item::PutItemError;
-use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::operation::scan::ScanError;
+use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsError;
+use aws_sdk_dynamodb::types::{AttributeValue, Delete, Put, ReturnValue, Select, TransactWriteItem};
 use aws_config::meta::region::RegionProviderChain;
 use aws_config::Region;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 use thiserror::Error;
-use tracing::{info, error};
+use tracing::info;
 
 /// Configuration for DynamoDB connection and table settings
 #[derive(Debug, Clone)]
@@ -29,16 +31,63 @@ pub struct DynamoDbConfig {
 }
 
 /// Represents a user registration record in DynamoDB.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RegistrationRecord {
     /// User's username
     pub username: String,
+    /// DN of the user's directory entry; empty on older records
+    #[serde(default)]
+    pub dn: String,
     /// User's phone number (primary key)
     pub phone_number: String,
     /// Signal registration ID
     pub registration_id: String,
+    /// Profile attributes fetched from LDAP, keyed by attribute name
+    #[serde(default)]
+    pub profile: HashMap<String, String>,
+    /// Numbers the registration was moved away from, oldest first
+    #[serde(default)]
+    pub phone_history: Vec<PhoneHistoryEntry>,
 }
 
+/// A phone number a registration used to have.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhoneHistoryEntry {
+    /// Previous phone number in E.164 format
+    pub phone_number: String,
+    /// When the registration moved away from it, in RFC 3339 format
+    pub changed_at: String,
+}
+
+/// A verified phone number waiting for approval before it is written to the directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhoneApproval {
+    /// Verified phone number in E.164 format (primary key)
+    pub phone_number: String,
+    /// Canonical username
+    pub username: String,
+    /// DN of the user's directory entry
+    pub dn: String,
+    /// Attribute the number is to be written to
+    pub attribute: String,
+    /// When the number was verified, in RFC 3339 format
+    pub requested_at: String,
+}
+
+/// A registration session as stored in the sessions table.
+#[derive(Debug, Clone)]
+pub struct SessionItem {
+    /// Session ID (primary key)
+    pub session_id: String,
+    /// Incremented by every update; updates are conditional on it
+    pub version: u64,
+    /// When the session expires, in seconds since the Unix epoch (the table's TTL attribute)
+    pub expires_at: u64,
+    /// The session, serialized as JSON
+    pub data: String,
+}
+
+/// The DynamoDB operations the client uses.
 #[async_trait::async_trait]
 pub trait DynamoDbOps: std::fmt::Debug + Send + Sync {
     async fn put_item(
@@ -64,8 +113,27 @@ pub trait DynamoDbOps: std::fmt::Debug + Send + Sync {
         aws_sdk_dynamodb::operation::delete_item::DeleteItemOutput,
         SdkError<DeleteItemError>,
     >;
+
+    async fn scan(
+        &self,
+        input: aws_sdk_dynamodb::operation::scan::ScanInput,
+    ) -> Result<
+        aws_sdk_dynamodb::operation::scan::ScanOutput,
+        SdkError<ScanError>,
+    >;
+
+    async fn transact_write_items(
+        &self,
+        input: aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsInput,
+    ) -> Result<
+        aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsOutput,
+        SdkError<TransactWriteItemsError>,
+    >;
 }
 
+/// Position to resume a scan from: the key of the last record returned.
+pub type ScanPosition = HashMap<String, AttributeValue>;
+
 #[async_trait::async_trait]
 impl DynamoDbOps for AwsDynamoDbClient {
     async fn put_item(
@@ -78,6 +146,9 @@ impl DynamoDbOps for AwsDynamoDbClient {
         self.put_item()
             .set_item(input.item().cloned())
             .set_table_name(input.table_name().map(|s| s.to_string()))
+            .set_condition_expression(input.condition_expression().map(|s| s.to_string()))
+            .set_expression_attribute_names(input.expression_attribute_names().cloned())
+            .set_expression_attribute_values(input.expression_attribute_values().cloned())
             .send()
             .await
     }
@@ -92,6 +163,7 @@ impl DynamoDbOps for AwsDynamoDbClient {
         self.get_item()
             .set_key(input.key().cloned())
             .set_table_name(input.table_name().map(|s| s.to_string()))
+            .set_consistent_read(input.consistent_read())
             .send()
             .await
     }
@@ -106,6 +178,43 @@ impl DynamoDbOps for AwsDynamoDbClient {
         self.delete_item()
             .set_key(input.key().cloned())
             .set_table_name(input.table_name().map(|s| s.to_string()))
+            .set_condition_expression(input.condition_expression().map(|s| s.to_string()))
+            .set_expression_attribute_names(input.expression_attribute_names().cloned())
+            .set_expression_attribute_values(input.expression_attribute_values().cloned())
+            .set_return_values(input.return_values().cloned())
+            .send()
+            .await
+    }
+
+    async fn scan(
+        &self,
+        input: aws_sdk_dynamodb::operation::scan::ScanInput,
+    ) -> Result<
+        aws_sdk_dynamodb::operation::scan::ScanOutput,
+        SdkError<ScanError>,
+    > {
+        self.scan()
+            .set_table_name(input.table_name().map(|s| s.to_string()))
+            .set_limit(input.limit())
+            .set_exclusive_start_key(input.exclusive_start_key().cloned())
+            .set_filter_expression(input.filter_expression().map(|s| s.to_string()))
+            .set_expression_attribute_names(input.expression_attribute_names().cloned())
+            .set_expression_attribute_values(input.expression_attribute_values().cloned())
+            .set_select(input.select().cloned())
+            .send()
+            .await
+    }
+
+    async fn transact_write_items(
+        &self,
+        input: aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsInput,
+    ) -> Result<
+        aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsOutput,
+        SdkError<TransactWriteItemsError>,
+    > {
+        self.transact_write_items()
+            .set_transact_items(Some(input.transact_items().to_vec()))
+            .set_client_request_token(input.client_request_token().map(|s| s.to_string()))
             .send()
             .await
     }
@@ -147,34 +256,43 @@ impl DynamoDbClient {
         })
     }
 
+    /// Creates a client on top of another implementation of the DynamoDB
+    /// operations, e.g. an in-memory table in tests.
+    ///
+    /// # Arguments
+    /// * `client` - Implementation of the DynamoDB operations
+    /// * `config` - Table settings
+    pub fn with_ops(client: Box<dyn DynamoDbOps>, config: DynamoDbConfig) -> Self {
+        Self { client, config }
+    }
+
     /// Stores a new registration record in DynamoDB.
     ///
     /// # Arguments
     /// * `username` - Username associated with the registration
+    /// * `dn` - DN of the user's directory entry
     /// * `phone_number` - User's verified phone number
     /// * `registration_id` - Signal registration ID
+    /// * `profile` - Profile attributes fetched from LDAP
     ///
     /// # Returns
     /// * `Result<()>` - Success or error if storage fails
     pub async fn save_registration(
         &self,
         username: &str,
+        dn: &str,
         phone_number: &str,
         registration_id: &str,
+        profile: &HashMap<String, String>,
     ) -> Result<(), Error> {
-        let mut item = HashMap::new();
-        item.insert(
-            "phone_number".to_string(),
-            AttributeValue::S(phone_number.to_string()),
-        );
-        item.insert(
-            "username".to_string(),
-            AttributeValue::S(username.to_string()),
-        );
-        item.insert(
-            "registration_id".to_string(),
-            AttributeValue::S(registration_id.to_string()),
-        );
+        let item = Self::record_item(&RegistrationRecord {
+            username: username.to_string(),
+            dn: dn.to_string(),
+            phone_number: phone_number.to_string(),
+            registration_id: registration_id.to_string(),
+            profile: profile.clone(),
+            phone_history: Vec::new(),
+        });
 
         let input = aws_sdk_dynamodb::operation::put_item::PutItemInput::builder()
             .table_name(&self.config.table_name)
@@ -219,27 +337,537 @@ impl DynamoDbClient {
             .await
             .map_err(Error::GetItemError)?;
 
-        if let Some(item) = output.item {
-            let username = item
-                .get("username")
-                .and_then(|av| av.as_s().ok())
-                .ok_or_else(|| Error::ParseError("username".to_string()))?
-                .to_string();
+        output
+            .item
+            .as_ref()
+            .map(Self::parse_record)
+            .transpose()
+            .map_err(|name| Error::ParseError(name.to_string()))
+    }
+
+    /// Retrieves one page of registration records.
+    ///
+    /// # Arguments
+    /// * `page_size` - Maximum number of records to return
+    /// * `start` - Position returned with the previous page, or `None` for the first page
+    ///
+    /// # Returns
+    /// * `Result<(Vec<RegistrationRecord>, Option<ScanPosition>)>` - The records and the
+    ///   position of the next page, or `None` after the last page
+    pub async fn scan_registrations(
+        &self,
+        page_size: u32,
+        start: Option<ScanPosition>,
+    ) -> Result<(Vec<RegistrationRecord>, Option<ScanPosition>), Error> {
+        let input = aws_sdk_dynamodb::operation::scan::ScanInput::builder()
+            .table_name(&self.config.table_name)
+            .limit(page_size.clamp(1, i32::MAX as u32) as i32)
+            .set_exclusive_start_key(start)
+            .build()
+            .map_err(Error::BuildError)?;
+
+        let output = self.client
+            .scan(input)
+            .await
+            .map_err(Error::ScanError)?;
+
+        let records = output
+            .items()
+            .iter()
+            .map(Self::parse_record)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|name| Error::ParseError(name.to_string()))?;
+        Ok((records, output.last_evaluated_key))
+    }
+
+    /// Retrieves every registration record of a user.
+    ///
+    /// The table is keyed by phone number, so this scans the whole table.
+    ///
+    /// # Arguments
+    /// * `username` - Username to look up
+    ///
+    /// # Returns
+    /// * `Result<Vec<RegistrationRecord>>` - The user's registration records
+    pub async fn find_registrations_by_username(&self, username: &str) -> Result<Vec<RegistrationRecord>, Error> {
+        let mut records = Vec::new();
+        let mut start = None;
+        loop {
+            let input = aws_sdk_dynamodb::operation::scan::ScanInput::builder()
+                .table_name(&self.config.table_name)
+                .filter_expression("#username = :username")
+                .expression_attribute_names("#username", "username")
+                .expression_attribute_values(":username", AttributeValue::S(username.to_string()))
+                .set_exclusive_start_key(start)
+                .build()
+                .map_err(Error::BuildError)?;
+
+            let output = self.client
+                .scan(input)
+                .await
+                .map_err(Error::ScanError)?;
+
+            for item in output.items() {
+                records.push(Self::parse_record(item).map_err(|name| Error::ParseError(name.to_string()))?);
+            }
+            match output.last_evaluated_key {
+                Some(key) => start = Some(key),
+                None => return Ok(records),
+            }
+        }
+    }
 
-            let registration_id = item
-                .get("registration_id")
+    /// Moves a registration to another phone number in one transaction.
+    ///
+    /// The record under the new number is only written if no registration holds
+    /// that number yet, and the old record is only deleted if it still belongs to
+    /// the same user and directory entry; otherwise nothing changes.
+    ///
+    /// # Arguments
+    /// * `from` - The registration as currently stored
+    /// * `to` - The registration to store under its new `phone_number`
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success or error if the transaction was cancelled or failed
+    pub async fn move_registration(&self, from: &RegistrationRecord, to: &RegistrationRecord) -> Result<(), Error> {
+        let put = Put::builder()
+            .table_name(&self.config.table_name)
+            .set_item(Some(Self::record_item(to)))
+            .condition_expression("attribute_not_exists(phone_number)")
+            .build()
+            .map_err(Error::BuildError)?;
+        let delete = Delete::builder()
+            .table_name(&self.config.table_name)
+            .key("phone_number", AttributeValue::S(from.phone_number.clone()))
+            .condition_expression("#username = :username AND #dn = :dn")
+            .expression_attribute_names("#username", "username")
+            .expression_attribute_names("#dn", "dn")
+            .expression_attribute_values(":username", AttributeValue::S(from.username.clone()))
+            .expression_attribute_values(":dn", AttributeValue::S(from.dn.clone()))
+            .build()
+            .map_err(Error::BuildError)?;
+
+        let input = aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsInput::builder()
+            .transact_items(TransactWriteItem::builder().put(put).build())
+            .transact_items(TransactWriteItem::builder().delete(delete).build())
+            .build()
+            .map_err(Error::BuildError)?;
+
+        self.client
+            .transact_write_items(input)
+            .await
+            .map_err(Error::TransactWriteError)?;
+
+        info!("Moved registration of {} to phone number: {}", to.username, to.phone_number);
+        Ok(())
+    }
+
+    /// Stores a phone number waiting for approval, replacing an earlier request
+    /// for the same number.
+    ///
+    /// # Arguments
+    /// * `approval` - The pending approval
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success or error if storage fails
+    pub async fn save_phone_approval(&self, approval: &PhoneApproval) -> Result<(), Error> {
+        let item = HashMap::from([
+            ("phone_number".to_string(), AttributeValue::S(approval.phone_number.clone())),
+            ("username".to_string(), AttributeValue::S(approval.username.clone())),
+            ("dn".to_string(), AttributeValue::S(approval.dn.clone())),
+            ("attribute".to_string(), AttributeValue::S(approval.attribute.clone())),
+            ("requested_at".to_string(), AttributeValue::S(approval.requested_at.clone())),
+        ]);
+
+        let input = aws_sdk_dynamodb::operation::put_item::PutItemInput::builder()
+            .table_name(&self.config.table_name)
+            .set_item(Some(item))
+            .build()
+            .map_err(Error::BuildError)?;
+
+        self.client
+            .put_item(input)
+            .await
+            .map_err(Error::PutItemError)?;
+        Ok(())
+    }
+
+    /// Retrieves the pending approval for a phone number.
+    ///
+    /// # Arguments
+    /// * `phone_number` - Phone number to look up
+    ///
+    /// # Returns
+    /// * `Result<Option<PhoneApproval>>` - The pending approval if found
+    pub async fn get_phone_approval(&self, phone_number: &str) -> Result<Option<PhoneApproval>, Error> {
+        let input = aws_sdk_dynamodb::operation::get_item::GetItemInput::builder()
+            .table_name(&self.config.table_name)
+            .key("phone_number", AttributeValue::S(phone_number.to_string()))
+            .build()
+            .map_err(Error::BuildError)?;
+
+        let output = self.client
+            .get_item(input)
+            .await
+            .map_err(Error::GetItemError)?;
+
+        output
+            .item
+            .as_ref()
+            .map(Self::parse_approval)
+            .transpose()
+            .map_err(|name| Error::ParseError(name.to_string()))
+    }
+
+    /// Retrieves every pending approval.
+    ///
+    /// # Returns
+    /// * `Result<Vec<PhoneApproval>>` - The pending approvals, oldest first
+    pub async fn list_phone_approvals(&self) -> Result<Vec<PhoneApproval>, Error> {
+        let mut approvals = Vec::new();
+        let mut start = None;
+        loop {
+            let input = aws_sdk_dynamodb::operation::scan::ScanInput::builder()
+                .table_name(&self.config.table_name)
+                .set_exclusive_start_key(start)
+                .build()
+                .map_err(Error::BuildError)?;
+
+            let output = self.client
+                .scan(input)
+                .await
+                .map_err(Error::ScanError)?;
+
+            for item in output.items() {
+                approvals.push(Self::parse_approval(item).map_err(|name| Error::ParseError(name.to_string()))?);
+            }
+            match output.last_evaluated_key {
+                Some(key) => start = Some(key),
+                None => break,
+            }
+        }
+        approvals.sort_by(|a, b| a.requested_at.cmp(&b.requested_at));
+        Ok(approvals)
+    }
+
+    /// Deletes the pending approval for a phone number.
+    ///
+    /// # Arguments
+    /// * `phone_number` - Phone number of the approval to delete
+    ///
+    /// # Returns
+    /// * `Result<()>` - Success or error if deletion fails
+    pub async fn delete_phone_approval(&self, phone_number: &str) -> Result<(), Error> {
+        let input = aws_sdk_dynamodb::operation::delete_item::DeleteItemInput::builder()
+            .table_name(&self.config.table_name)
+            .key("phone_number", AttributeValue::S(phone_number.to_string()))
+            .build()
+            .map_err(Error::BuildError)?;
+
+        self.client
+            .delete_item(input)
+            .await
+            .map_err(Error::DeleteItemError)?;
+        Ok(())
+    }
+
+    /// Stores a session.
+    ///
+    /// Without an expected version the session is only written if no session has
+    /// its ID yet; with one, only if the stored session still has that version.
+    ///
+    /// # Arguments
+    /// * `session` - The session, with its new version
+    /// * `expected_version` - Version the stored session must have, or `None` to create it
+    ///
+    /// # Returns
+    /// * `Result<bool>` - Whether the session was written, or error if storage fails
+    pub async fn put_session(&self, session: &SessionItem, expected_version: Option<u64>) -> Result<bool, Error> {
+        let builder = aws_sdk_dynamodb::operation::put_item::PutItemInput::builder()
+            .table_name(&self.config.table_name)
+            .item("session_id", AttributeValue::S(session.session_id.clone()))
+            .item("version", AttributeValue::N(session.version.to_string()))
+            .item("expires_at", AttributeValue::N(session.expires_at.to_string()))
+            .item("data", AttributeValue::S(session.data.clone()));
+        let builder = match expected_version {
+            None => builder.condition_expression("attribute_not_exists(session_id)"),
+            Some(version) => builder
+                .condition_expression("#version = :version")
+                .expression_attribute_names("#version", "version")
+                .expression_attribute_values(":version", AttributeValue::N(version.to_string())),
+        };
+        let input = builder.build().map_err(Error::BuildError)?;
+
+        match self.client.put_item(input).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => Ok(false),
+            Err(e) => Err(Error::PutItemError(e)),
+        }
+    }
+
+    /// Retrieves a session with a strongly consistent read.
+    ///
+    /// # Arguments
+    /// * `session_id` - ID of the session
+    ///
+    /// # Returns
+    /// * `Result<Option<SessionItem>>` - The session if found
+    pub async fn get_session(&self, session_id: &str) -> Result<Option<SessionItem>, Error> {
+        let input = aws_sdk_dynamodb::operation::get_item::GetItemInput::builder()
+            .table_name(&self.config.table_name)
+            .key("session_id", AttributeValue::S(session_id.to_string()))
+            .consistent_read(true)
+            .build()
+            .map_err(Error::BuildError)?;
+
+        let output = self.client
+            .get_item(input)
+            .await
+            .map_err(Error::GetItemError)?;
+
+        output
+            .item
+            .as_ref()
+            .map(Self::parse_session)
+            .transpose()
+            .map_err(|name| Error::ParseError(name.to_string()))
+    }
+
+    /// Deletes a session.
+    ///
+    /// # Arguments
+    /// * `session_id` - ID of the session
+    /// * `expected_version` - Version the stored session must have, or `None` to delete any version
+    ///
+    /// # Returns
+    /// * `Result<bool>` - Whether a session was deleted, or error if deletion fails
+    pub async fn delete_session(&self, session_id: &str, expected_version: Option<u64>) -> Result<bool, Error> {
+        let builder = aws_sdk_dynamodb::operation::delete_item::DeleteItemInput::builder()
+            .table_name(&self.config.table_name)
+            .key("session_id", AttributeValue::S(session_id.to_string()));
+        let builder = match expected_version {
+            None => builder.return_values(ReturnValue::AllOld),
+            Some(version) => builder
+                .condition_expression("#version = :version")
+                .expression_attribute_names("#version", "version")
+                .expression_attribute_values(":version", AttributeValue::N(version.to_string())),
+        };
+        let input = builder.build().map_err(Error::BuildError)?;
+
+        match self.client.delete_item(input).await {
+            Ok(output) => Ok(expected_version.is_some() || output.attributes.is_some_and(|item| !item.is_empty())),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => Ok(false),
+            Err(e) => Err(Error::DeleteItemError(e)),
+        }
+    }
+
+    /// Retrieves every session, optionally only those that expired before a time.
+    ///
+    /// # Arguments
+    /// * `expired_before` - Only return sessions whose `expires_at` is earlier, in
+    ///   seconds since the Unix epoch
+    ///
+    /// # Returns
+    /// * `Result<Vec<SessionItem>>` - The sessions
+    pub async fn scan_sessions(&self, expired_before: Option<u64>) -> Result<Vec<SessionItem>, Error> {
+        let mut sessions = Vec::new();
+        let mut start = None;
+        loop {
+            let mut builder = aws_sdk_dynamodb::operation::scan::ScanInput::builder()
+                .table_name(&self.config.table_name)
+                .set_exclusive_start_key(start);
+            if let Some(now) = expired_before {
+                builder = builder
+                    .filter_expression("#expires_at < :now")
+                    .expression_attribute_names("#expires_at", "expires_at")
+                    .expression_attribute_values(":now", AttributeValue::N(now.to_string()));
+            }
+            let input = builder.build().map_err(Error::BuildError)?;
+
+            let output = self.client
+                .scan(input)
+                .await
+                .map_err(Error::ScanError)?;
+
+            for item in output.items() {
+                sessions.push(Self::parse_session(item).map_err(|name| Error::ParseError(name.to_string()))?);
+            }
+            match output.last_evaluated_key {
+                Some(key) => start = Some(key),
+                None => return Ok(sessions),
+            }
+        }
+    }
+
+    /// Counts the sessions that have not expired, including those TTL has yet to delete.
+    ///
+    /// # Arguments
+    /// * `now` - The current time, in seconds since the Unix epoch
+    ///
+    /// # Returns
+    /// * `Result<usize>` - Number of live sessions
+    pub async fn count_sessions(&self, now: u64) -> Result<usize, Error> {
+        let mut count = 0;
+        let mut start = None;
+        loop {
+            let input = aws_sdk_dynamodb::operation::scan::ScanInput::builder()
+                .table_name(&self.config.table_name)
+                .select(Select::Count)
+                .filter_expression("#expires_at >= :now")
+                .expression_attribute_names("#expires_at", "expires_at")
+                .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+                .set_exclusive_start_key(start)
+                .build()
+                .map_err(Error::BuildError)?;
+
+            let output = self.client
+                .scan(input)
+                .await
+                .map_err(Error::ScanError)?;
+
+            count += output.count().max(0) as usize;
+            match output.last_evaluated_key {
+                Some(key) => start = Some(key),
+                None => return Ok(count),
+            }
+        }
+    }
+
+    /// Parses a session from a DynamoDB item, failing with the name of the
+    /// first missing or malformed attribute.
+    fn parse_session(item: &HashMap<String, AttributeValue>) -> Result<SessionItem, &'static str> {
+        let string = |name: &'static str| item.get(name).and_then(|av| av.as_s().ok()).cloned().ok_or(name);
+        let number = |name: &'static str| {
+            item.get(name)
+                .and_then(|av| av.as_n().ok())
+                .and_then(|n| n.parse().ok())
+                .ok_or(name)
+        };
+
+        Ok(SessionItem {
+            session_id: string("session_id")?,
+            version: number("version")?,
+            expires_at: number("expires_at")?,
+            data: string("data")?,
+        })
+    }
+
+    /// Parses a pending approval from a DynamoDB item, failing with the name of
+    /// the first missing attribute.
+    fn parse_approval(item: &HashMap<String, AttributeValue>) -> Result<PhoneApproval, &'static str> {
+        let attribute = |name: &'static str| {
+            item.get(name)
                 .and_then(|av| av.as_s().ok())
-                .ok_or_else(|| Error::ParseError("registration_id".to_string()))?
-                .to_string();
-
-            Ok(Some(RegistrationRecord {
-                username,
-                phone_number: phone_number.to_string(),
-                registration_id,
-            }))
-        } else {
-            Ok(None)
+                .cloned()
+                .ok_or(name)
+        };
+
+        Ok(PhoneApproval {
+            phone_number: attribute("phone_number")?,
+            username: attribute("username")?,
+            dn: attribute("dn")?,
+            attribute: attribute("attribute")?,
+            requested_at: attribute("requested_at")?,
+        })
+    }
+
+    /// Builds the DynamoDB item for a registration record.
+    fn record_item(record: &RegistrationRecord) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+        item.insert(
+            "phone_number".to_string(),
+            AttributeValue::S(record.phone_number.clone()),
+        );
+        item.insert(
+            "username".to_string(),
+            AttributeValue::S(record.username.clone()),
+        );
+        item.insert(
+            "registration_id".to_string(),
+            AttributeValue::S(record.registration_id.clone()),
+        );
+        if !record.dn.is_empty() {
+            item.insert("dn".to_string(), AttributeValue::S(record.dn.clone()));
+        }
+        if !record.profile.is_empty() {
+            item.insert(
+                "profile".to_string(),
+                AttributeValue::M(
+                    record
+                        .profile
+                        .iter()
+                        .map(|(name, value)| (name.clone(), AttributeValue::S(value.clone())))
+                        .collect(),
+                ),
+            );
         }
+        if !record.phone_history.is_empty() {
+            item.insert(
+                "phone_history".to_string(),
+                AttributeValue::L(
+                    record
+                        .phone_history
+                        .iter()
+                        .map(|entry| {
+                            AttributeValue::M(HashMap::from([
+                                ("phone_number".to_string(), AttributeValue::S(entry.phone_number.clone())),
+                                ("changed_at".to_string(), AttributeValue::S(entry.changed_at.clone())),
+                            ]))
+                        })
+                        .collect(),
+                ),
+            );
+        }
+        item
+    }
+
+    /// Parses a registration record from a DynamoDB item, failing with the name
+    /// of the first missing attribute.
+    fn parse_record(item: &HashMap<String, AttributeValue>) -> Result<RegistrationRecord, &'static str> {
+        let attribute = |name: &'static str| {
+            item.get(name)
+                .and_then(|av| av.as_s().ok())
+                .cloned()
+                .ok_or(name)
+        };
+
+        // Records written before profiles were stored have none
+        let profile = item
+            .get("profile")
+            .and_then(|av| av.as_m().ok())
+            .map(|map| {
+                map.iter()
+                    .filter_map(|(name, av)| av.as_s().ok().map(|value| (name.clone(), value.clone())))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let phone_history = item
+            .get("phone_history")
+            .and_then(|av| av.as_l().ok())
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|entry| {
+                        let entry = entry.as_m().ok()?;
+                        let value = |name: &str| entry.get(name).and_then(|av| av.as_s().ok()).cloned();
+                        Some(PhoneHistoryEntry {
+                            phone_number: value("phone_number")?,
+                            changed_at: value("changed_at").unwrap_or_default(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(RegistrationRecord {
+            username: attribute("username")?,
+            // Records written before DNs were stored have none
+            dn: attribute("dn").unwrap_or_default(),
+            phone_number: attribute("phone_number")?,
+            registration_id: attribute("registration_id")?,
+            profile,
+            phone_history,
+        })
     }
 
     /// Deletes a registration record by phone number.
@@ -282,6 +910,10 @@ pub enum Error {
     GetItemError(SdkError<GetItemError>),
     #[error("Failed to delete item: {0}")]
     DeleteItemError(SdkError<DeleteItemError>),
+    #[error("Failed to scan table: {0}")]
+    ScanError(SdkError<ScanError>),
+    #[error("Failed to write transaction: {0}")]
+    TransactWriteError(SdkError<TransactWriteItemsError>),
     #[error("Failed to parse {0} from DynamoDB response")]
     ParseError(String),
 }
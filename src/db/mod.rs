@@ -1,3 +1,3 @@
 pub mod dynamodb;
 
-pub use dynamodb::{DynamoDbClient, DynamoDbConfig, RegistrationRecord};
+pub use dynamodb::{DynamoDbClient, DynamoDbConfig, PhoneApproval, PhoneHistoryEntry, RegistrationRecord, ScanPosition, SessionItem};
@@ -1,43 +1,132 @@
 //! gRPC server implementation for the Signal Registration Service.
 //!
 //! This module implements the gRPC service endpoints defined in the proto files,
-//! handling user registration and LDAP validation requests. It manages user sessions,
-//! rate limiting, and coordinates between various backend services (LDAP, Twilio, DynamoDB).
+//! handling user registration and LDAP validation requests. It manages user sessions
+//! through a `SessionStore`, rate limiting, and coordinates between various backend
+//! services (LDAP, Twilio, DynamoDB).
 //!
 //! @author Joseph G Noonan
 //! @copyright 2025
 use tonic::{Request, Response, Status};
-use crate::auth::ldap::{LdapClient, Error};
-use crate::twilio::TwilioClient;
+use crate::auth::identity::IdentityProvider;
+use crate::auth::ldap::{AuthenticatedUser, Error};
+use crate::auth::lockout::{Attempt, LoginLockout, Throttled};
+use crate::auth::phone::mask_phone_number;
+use crate::twilio::{CheckOutcome, CodeSends, ResendPolicy, TwilioClient, VerificationChannel};
 use crate::db::dynamodb::DynamoDbClient;
 use crate::twilio::rate_limit::RateLimiter;
+use crate::writeback::{PhoneWriteBack, WriteBackOutcome};
+use crate::phone_change::{self, PhoneNumberChanger};
+use crate::session::{self, epoch_secs, MemorySessionStore, Session, SessionMetrics, SessionReaper, SessionStore};
 use crate::proto::registration::{
     StartRegistrationRequest,
     StartRegistrationResponse,
+    SelectPhoneNumberRequest,
     VerifyCodeRequest,
     VerifyCodeResponse,
     CompleteRegistrationRequest,
     CompleteRegistrationResponse,
+    StartPhoneNumberChangeRequest,
+    StartPhoneNumberChangeResponse,
+    CompletePhoneNumberChangeRequest,
+    ResendCodeRequest,
+    ResendCodeResponse,
     registration_service_server::RegistrationService,
 };
-use tracing::{error, debug};
+use tonic::metadata::MetadataValue;
+use tonic::Code;
+use tracing::{error, debug, warn};
+use std::net::IpAddr;
 use std::time::{SystemTime, Duration};
 use std::sync::Arc;
-use std::collections::HashMap;
-use tokio::sync::Mutex;
 use uuid::Uuid;
 
-/// Represents a user registration session with associated state and timing information.
-#[derive(Debug)]
-struct Session {
-    /// Username associated with the session
-    username: String,
-    /// Phone number being verified
-    phone_number: String,
-    /// Timestamp when the session was created
-    created_at: SystemTime,
-    /// Whether the session has been verified
-    verified: bool,
+/// Handle for invalidating sessions from outside the gRPC handlers, e.g. when
+/// the directory reports that a user was disabled.
+#[derive(Debug, Clone)]
+pub struct SessionHandle {
+    sessions: Arc<dyn SessionStore>,
+}
+
+impl SessionHandle {
+    /// Returns the usernames with a session in progress.
+    pub async fn usernames(&self) -> Result<Vec<String>, session::Error> {
+        let mut usernames: Vec<String> = self
+            .sessions
+            .list()
+            .await?
+            .into_iter()
+            .map(|(_, session)| session.username)
+            .collect();
+        usernames.sort();
+        usernames.dedup();
+        Ok(usernames)
+    }
+
+    /// Removes the sessions of a user.
+    ///
+    /// # Arguments
+    /// * `username` - Canonical username, compared case-insensitively
+    /// * `phone_numbers` - The user's current numbers; when given, only sessions for
+    ///   a number that is no longer among them are removed
+    ///
+    /// # Returns
+    /// * `Result<usize>` - Number of sessions removed
+    pub async fn invalidate_user(&self, username: &str, phone_numbers: Option<&[String]>) -> Result<usize, session::Error> {
+        let mut removed = 0;
+        for (id, session) in self.sessions.list().await? {
+            if !session.username.eq_ignore_ascii_case(username) {
+                continue;
+            }
+            let invalid = match phone_numbers {
+                None => true,
+                // The number being verified is not meant to be in the directory yet
+                Some(_) if session.write_back.is_some() || session.change.is_some() => false,
+                Some(numbers) => {
+                    let stale = |number: &String| !numbers.contains(number);
+                    session.phone_candidates.iter().any(stale)
+                        || (!session.phone_number.is_empty() && stale(&session.phone_number))
+                }
+            };
+            if invalid && self.sessions.delete(&id, None).await? {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Maps a throttled sign-in to `RESOURCE_EXHAUSTED` with a `retry-after` header
+/// giving the wait in whole seconds
+impl From<Throttled> for Status {
+    fn from(throttled: Throttled) -> Self {
+        let retry_after = throttled.retry_after.as_secs_f64().ceil() as u64;
+        let mut status = if throttled.locked {
+            Status::resource_exhausted("Too many failed sign-in attempts, account temporarily locked")
+        } else {
+            Status::resource_exhausted("Too many failed sign-in attempts, try again later")
+        };
+        status.metadata_mut().insert("retry-after", MetadataValue::from(retry_after));
+        status
+    }
+}
+
+/// Returns the client address of a request: the first address in the configured
+/// proxy header if there is one, the connection's peer address otherwise.
+///
+/// # Arguments
+/// * `request` - The incoming request
+/// * `header` - Metadata header set by a trusted proxy, e.g. "x-forwarded-for"
+///
+/// # Returns
+/// * `Option<IpAddr>` - The address, or `None` if it cannot be determined
+pub(crate) fn client_address<T>(request: &Request<T>, header: Option<&str>) -> Option<IpAddr> {
+    header
+        .and_then(|header| request.metadata().get(header))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .and_then(|value| value.trim().parse().ok())
+        .or_else(|| request.remote_addr().map(|addr| addr.ip()))
 }
 
 /// Maps LDAP errors to gRPC status codes
@@ -49,32 +138,117 @@ impl From<Error> for Status {
                 Status::not_found(format!("Phone number not found in attribute: {}", attr)),
             Error::PhoneNumberEmpty => 
                 Status::invalid_argument("Phone number is empty"),
+            Error::InvalidPhoneNumber(number) => 
+                Status::invalid_argument(format!("Invalid phone number: {}", number)),
+            Error::AmbiguousPhoneNumber { candidates, .. } => 
+                Status::failed_precondition(format!("User has {} phone numbers", candidates.len())),
             Error::UserNotFound(msg) => 
                 Status::not_found(format!("User not found: {}", msg)),
+            Error::AmbiguousUser(msg) => 
+                Status::failed_precondition(format!("Multiple directory entries match user: {}", msg)),
+            Error::UsernameMissing { .. } => 
+                Status::failed_precondition("Directory entry has no username"),
+            Error::SearchBaseMissing(base) => 
+                Status::internal(format!("Search base does not exist: {}", base)),
+            Error::InvalidFilter(msg) => 
+                Status::internal(format!("Invalid user filter: {}", msg)),
             Error::AuthenticationFailed => 
                 Status::unauthenticated("Authentication failed"),
+            Error::AccountLocked(_) => 
+                Status::permission_denied("Account is locked"),
+            Error::AccountDisabled(_) => 
+                Status::permission_denied("Account is disabled"),
+            Error::AccountExpired(_) => 
+                Status::permission_denied("Account has expired"),
+            Error::PasswordMustChange(_) => 
+                Status::failed_precondition("Password must be changed"),
+            Error::NotAuthorized(_) => 
+                Status::permission_denied("User is not authorized to register"),
+            Error::PoolTimeout => 
+                Status::unavailable("Timed out waiting for an LDAP connection"),
+            Error::TlsConfig(msg) => 
+                Status::internal(format!("TLS configuration error: {}", msg)),
             Error::ServerError(msg) => 
                 Status::internal(format!("Server error: {}", msg)),
         }
     }
 }
 
+/// Maps session store errors to gRPC status codes
+impl From<session::Error> for Status {
+    fn from(error: session::Error) -> Self {
+        match error {
+            session::Error::Full(_) =>
+                Status::resource_exhausted("Too many registrations in progress, try again later"),
+            e => Status::internal(format!("Session store error: {}", e)),
+        }
+    }
+}
+
+/// Maps phone number change errors to gRPC status codes
+impl From<phone_change::Error> for Status {
+    fn from(error: phone_change::Error) -> Self {
+        match error {
+            phone_change::Error::Ldap(e) => Status::from(e),
+            phone_change::Error::Db(e) => Status::internal(format!("DynamoDB error: {}", e)),
+            phone_change::Error::NoRegistration(_) =>
+                Status::not_found("No registration to change"),
+            phone_change::Error::AmbiguousRegistration(_, count) =>
+                Status::failed_precondition(format!("User has {} registrations", count)),
+            phone_change::Error::NumberInUse =>
+                Status::already_exists("Phone number is already registered"),
+            phone_change::Error::SameNumber =>
+                Status::invalid_argument("New phone number is the registered one"),
+        }
+    }
+}
+
+/// Codes a session may check per phone number unless configured otherwise,
+/// matching Twilio Verify's default.
+pub const DEFAULT_MAX_CHECK_ATTEMPTS: u32 = 5;
+
+/// Which of a session's phone numbers a code is checked for
+#[derive(Debug, Clone, Copy)]
+enum CheckedNumber {
+    /// The number being registered or changed to
+    Current,
+    /// The registered number of a phone number change
+    Old,
+}
+
+impl CheckedNumber {
+    /// Returns the codes sent to this number of a session.
+    fn sends(self, session: &mut Session) -> &mut CodeSends {
+        match self {
+            CheckedNumber::Current => &mut session.sends,
+            CheckedNumber::Old => &mut session.old_number_sends,
+        }
+    }
+}
+
 /// Main server implementation for the registration service.
 ///
 /// Handles all gRPC endpoints related to user registration, including:
 /// - Starting registration process
 /// - Verifying phone numbers
+/// - Resending verification codes
 /// - Completing registration
 ///
 /// The server maintains session state and coordinates between LDAP authentication,
 /// Twilio phone verification, and DynamoDB persistence.
 pub struct RegistrationServer {
-    ldap_client: Arc<LdapClient>,
+    identity: Arc<dyn IdentityProvider>,
     twilio_client: Arc<TwilioClient>,
     dynamodb_client: Arc<DynamoDbClient>,
     rate_limiter: Arc<RateLimiter>,
-    sessions: Arc<Mutex<HashMap<String, Session>>>,
+    sessions: Arc<dyn SessionStore>,
+    session_metrics: Arc<SessionMetrics>,
     session_timeout: Duration,
+    max_check_attempts: u32,
+    resend_policy: ResendPolicy,
+    write_back: Option<Arc<PhoneWriteBack>>,
+    phone_change: Option<Arc<PhoneNumberChanger>>,
+    lockout: Option<Arc<LoginLockout>>,
 }
 
 #[tonic::async_trait]
@@ -90,64 +264,139 @@ impl RegistrationService for RegistrationServer {
     ///
     /// # Flow
     /// 1. Validates username exists in LDAP
-    /// 2. Creates new session
-    /// 3. Returns session token to client
+    /// 2. Falls back to the number in the request when the directory has none
+    ///    and phone write-back is enabled
+    /// 3. Creates new session
+    /// 4. Returns session token to client
     async fn start_registration(
         &self,
         request: Request<StartRegistrationRequest>,
     ) -> Result<Response<StartRegistrationResponse>, Status> {
+        let address = self.client_address(&request);
         let req = request.into_inner();
         
         debug!("Received validation request for user: {}", req.username);
         debug!("Attempting LDAP authentication...");
         
         // Authenticate with LDAP and get phone number
-        let phone_number = self.ldap_client
-            .authenticate_user(&req.username, &req.password)
-            .await
-            .map_err(|e: Error| {
+        let channel: VerificationChannel = req.channel
+            .parse()
+            .map_err(|e: anyhow::Error| Status::invalid_argument(e.to_string()))?;
+        let attempt = self.check_lockout(&req.username, address).await?;
+        let result = if self.write_back.is_some() && !req.phone_number.is_empty() {
+            self.identity.authenticate_without_phone(&req.username, &req.password).await
+        } else {
+            self.identity.authenticate(&req.username, &req.password).await
+        };
+        Self::record_sign_in(attempt, &result).await;
+        let user = match result {
+            Ok(user) => user,
+            Err(Error::AmbiguousPhoneNumber { username, dn, candidates, profile }) => {
+                debug!("LDAP authentication successful, user must choose between {} phone numbers", candidates.len());
+                let masked = candidates.iter().map(|n| mask_phone_number(n)).collect();
+                let session = Session {
+                    dn,
+                    phone_candidates: candidates,
+                    profile,
+                    ..Session::new(&username, String::new(), self.session_timeout)
+                };
+                let session_id = self.create_session(session).await?;
+                return Ok(Response::new(StartRegistrationResponse {
+                    session_id,
+                    phone_number: String::new(),
+                    verification_code_length: 6,
+                    verification_timeout_seconds: self.session_timeout.as_secs() as i32,
+                    phone_number_candidates: masked,
+                }));
+            }
+            Err(e) => {
                 error!("LDAP authentication failed: {}", e);
-                Status::from(e)
-           })?;
+                return Err(Status::from(e));
+            }
+        };
         
-        debug!("LDAP authentication successful, sending verification code...");
+        debug!("LDAP authentication successful for {}, sending verification code...", user.username);
+        let (phone_number, write_back) = match &self.write_back {
+            Some(write_back) if user.phone_number.is_empty() => {
+                let phone_number = self.identity.normalize_phone_number(&req.phone_number)?;
+                let write = write_back.prepare(&user, &phone_number, &req.password);
+                (phone_number, Some(write))
+            }
+            _ => {
+                if !req.phone_number.is_empty() {
+                    debug!("Ignoring supplied phone number, the directory has one for {}", user.username);
+                }
+                (user.phone_number.clone(), None)
+            }
+        };
+        self.send_code(&phone_number, channel).await?;
         
-        // Check rate limit
-        if !self.rate_limiter.check_rate_limit(&phone_number).await {
-            return Err(Status::resource_exhausted("Too many verification attempts"));
-        }
+        // Create session under the canonical username
+        let mut session = Session {
+            dn: user.dn,
+            profile: user.profile,
+            write_back,
+            ..Session::new(&user.username, phone_number.clone(), self.session_timeout)
+        };
+        session.sends.record(channel, session.created_at);
+        let session_id = self.create_session(session).await?;
         
-        // Start Twilio verification
-        self.twilio_client
-            .send_verification_code(&phone_number, match req.channel.as_str() {
-                "sms" => crate::twilio::VerificationChannel::Sms,
-                "voice" => crate::twilio::VerificationChannel::Voice,
-                _ => return Err(Status::invalid_argument("Invalid channel. Must be 'sms' or 'voice'")),
-            })
-            .await
-            .map_err(|e| {
-                error!("Failed to send verification code: {}", e);
-                Status::internal(format!("Failed to send verification code: {}", e))
-            })?;
+        Ok(Response::new(StartRegistrationResponse {
+            session_id,
+            phone_number,
+            verification_code_length: 6,
+            verification_timeout_seconds: self.session_timeout.as_secs() as i32,
+            phone_number_candidates: Vec::new(),
+        }))
+    }
+
+    /// Selects one of the phone numbers offered by `start_registration`.
+    ///
+    /// # Arguments
+    /// * `request` - Contains the session token, candidate index and channel
+    ///
+    /// # Returns
+    /// * Success: Response with the chosen (unmasked) phone number
+    /// * Error: Status with error details if the session or index is invalid
+    ///
+    /// # Flow
+    /// 1. Validates the session is waiting for a phone number choice
+    /// 2. Sends the verification code to the chosen number
+    /// 3. Records the number on the session
+    async fn select_phone_number(
+        &self,
+        request: Request<SelectPhoneNumberRequest>,
+    ) -> Result<Response<StartRegistrationResponse>, Status> {
+        let req = request.into_inner();
         
-        debug!("Verification code sent successfully");
+        debug!("Received phone number selection for session: {}", req.session_id);
         
-        // Create session
-        let session_id = Uuid::new_v4().to_string();
-        let session = Session {
-            username: req.username.clone(),
-            phone_number: phone_number.clone(),
-            verified: false,
-            created_at: SystemTime::now(),
-        };
+        let channel: VerificationChannel = req.channel
+            .parse()
+            .map_err(|e: anyhow::Error| Status::invalid_argument(e.to_string()))?;
+        let mut session = self.load_session(&req.session_id).await?;
+        if !session.phone_number.is_empty() {
+            return Err(Status::failed_precondition("Phone number already selected"));
+        }
+        let phone_number = session
+            .phone_candidates
+            .get(req.candidate_index as usize)
+            .cloned()
+            .ok_or_else(|| Status::invalid_argument("Invalid phone number candidate"))?;
         
-        self.sessions.lock().await.insert(session_id.clone(), session);
+        self.send_code(&phone_number, channel).await?;
+        
+        session.phone_number = phone_number.clone();
+        session.phone_candidates.clear();
+        session.sends.record(channel, SystemTime::now());
+        self.save_session(&req.session_id, &mut session).await?;
         
         Ok(Response::new(StartRegistrationResponse {
-            session_id,
+            session_id: req.session_id,
             phone_number,
             verification_code_length: 6,
             verification_timeout_seconds: self.session_timeout.as_secs() as i32,
+            phone_number_candidates: Vec::new(),
         }))
     }
 
@@ -164,6 +413,7 @@ impl RegistrationService for RegistrationServer {
     /// 1. Validates session exists and is valid
     /// 2. Verifies code with Twilio
     /// 3. Updates session state
+    /// 4. Writes a user-supplied number to the directory, or queues it for approval
     async fn verify_code(
         &self,
         request: Request<VerifyCodeRequest>,
@@ -172,51 +422,47 @@ impl RegistrationService for RegistrationServer {
         
         debug!("Received verification code for session: {}", req.session_id);
         
-        // Get session
-        let mut sessions = self.sessions.lock().await;
-        let session = sessions
-            .get_mut(&req.session_id)
-            .ok_or_else(|| {
-                error!("Session not found");
-                Status::not_found("Session not found")
-            })?;
-            
-        // Check if session is expired
-        if SystemTime::now()
-            .duration_since(session.created_at)
-            .unwrap_or_default() > self.session_timeout
-        {
-            sessions.remove(&req.session_id);
-            return Ok(Response::new(VerifyCodeResponse {
-                success: false,
-                message: "Session expired".to_string(),
-                remaining_attempts: 0,
-            }));
+        // Get session, checking that it has not expired
+        let mut session = match self.load_session(&req.session_id).await {
+            Err(status) if status.code() == Code::DeadlineExceeded => {
+                return Ok(Response::new(VerifyCodeResponse {
+                    success: false,
+                    message: "Session expired".to_string(),
+                    remaining_attempts: 0,
+                }));
+            }
+            result => result?,
+        };
+        
+        if session.phone_number.is_empty() {
+            return Err(Status::failed_precondition("Phone number not selected"));
         }
         
         // Verify code with Twilio
-        let valid = self.twilio_client
-            .verify_code(&session.phone_number, &req.code)
-            .await
-            .map_err(|e| {
-                error!("Failed to verify code: {}", e);
-                Status::internal(format!("Failed to verify code: {}", e))
-            })?;
-            
-        if !valid {
-            return Ok(Response::new(VerifyCodeResponse {
-                success: false,
-                message: "Invalid verification code".to_string(),
-                remaining_attempts: 2, // TODO: Get actual remaining attempts from Twilio
-            }));
+        if let Some(response) = self.check_session_code(&req.session_id, &mut session, CheckedNumber::Current, &req.code).await? {
+            return Ok(Response::new(response));
         }
         
-        // Mark session as verified
+        // Mark session as verified; only the request that stores this submits the write-back
         session.verified = true;
+        let write = session.write_back.take();
+        self.save_session(&req.session_id, &mut session).await?;
+        
+        let message = match (write, &self.write_back) {
+            (Some(write), Some(write_back)) => match write_back.submit(write).await {
+                Ok(WriteBackOutcome::Written) => "Code verified successfully",
+                Ok(WriteBackOutcome::AwaitingApproval) => "Code verified successfully; phone number awaiting approval",
+                Err(e) => {
+                    warn!("Verified phone number could not be written back: {}", e);
+                    "Code verified successfully; phone number could not be saved to the directory"
+                }
+            },
+            _ => "Code verified successfully",
+        };
         
         Ok(Response::new(VerifyCodeResponse {
             success: true,
-            message: "Code verified successfully".to_string(),
+            message: message.to_string(),
             remaining_attempts: 0,
         }))
     }
@@ -244,13 +490,19 @@ impl RegistrationService for RegistrationServer {
         debug!("Received complete registration request for session: {}", req.session_id);
         
         // Get and remove session
-        let mut sessions = self.sessions.lock().await;
-        let session = sessions
-            .remove(&req.session_id)
+        let session = self.sessions
+            .get(&req.session_id)
+            .await?
             .ok_or_else(|| {
                 error!("Session not found");
                 Status::not_found("Session not found")
             })?;
+        if session.change.is_some() {
+            return Err(Status::failed_precondition("Session is a phone number change"));
+        }
+        if !self.sessions.delete(&req.session_id, Some(session.version)).await? {
+            return Err(Status::aborted("Session was modified concurrently, try again"));
+        }
             
         // Check if session is verified
         if !session.verified {
@@ -263,8 +515,10 @@ impl RegistrationService for RegistrationServer {
         // Save registration
         match self.dynamodb_client.save_registration(
             &session.username,
+            &session.dn,
             &session.phone_number,
             &format!("{}", req.registration_id),
+            &session.profile,
         ).await {
             Ok(_) => Ok(Response::new(CompleteRegistrationResponse {
                 success: true,
@@ -279,13 +533,268 @@ impl RegistrationService for RegistrationServer {
             }
         }
     }
+
+    /// Starts moving a registration to a new phone number.
+    ///
+    /// # Arguments
+    /// * `request` - Contains the credentials, the new number and the channel
+    ///
+    /// # Returns
+    /// * Success: Response with the session token and whether the old number must confirm
+    /// * Error: Status with error details if changes are disabled, authentication
+    ///   fails or the registration cannot be moved to the number
+    ///
+    /// # Flow
+    /// 1. Authenticates the user with LDAP
+    /// 2. Finds the user's registration and checks the new number is free
+    /// 3. Sends a code to the new number, and to the old number if required
+    /// 4. Creates a session for `VerifyCode`, `VerifyOldPhoneNumber` and
+    ///    `CompletePhoneNumberChange`
+    async fn start_phone_number_change(
+        &self,
+        request: Request<StartPhoneNumberChangeRequest>,
+    ) -> Result<Response<StartPhoneNumberChangeResponse>, Status> {
+        let address = self.client_address(&request);
+        let req = request.into_inner();
+        let changer = self
+            .phone_change
+            .as_ref()
+            .ok_or_else(|| Status::unimplemented("Phone number changes are not enabled"))?;
+        
+        debug!("Received phone number change request for user: {}", req.username);
+        
+        let channel: VerificationChannel = req.channel
+            .parse()
+            .map_err(|e: anyhow::Error| Status::invalid_argument(e.to_string()))?;
+        let new_phone_number = self.identity.normalize_phone_number(&req.new_phone_number)?;
+        let attempt = self.check_lockout(&req.username, address).await?;
+        let result = self.identity.authenticate_without_phone(&req.username, &req.password).await;
+        Self::record_sign_in(attempt, &result).await;
+        let user = result.inspect_err(|e| error!("LDAP authentication failed: {}", e))?;
+        let change = changer.prepare(&user, &new_phone_number, &req.password).await?;
+        let old_phone_number = change.registration.phone_number.clone();
+        
+        self.send_code(&new_phone_number, channel).await?;
+        if changer.confirms_old_number() {
+            self.send_code(&old_phone_number, channel).await?;
+        }
+        
+        let mut session = Session {
+            dn: user.dn,
+            profile: user.profile,
+            change: Some(change),
+            ..Session::new(&user.username, new_phone_number.clone(), self.session_timeout)
+        };
+        session.sends.record(channel, session.created_at);
+        if changer.confirms_old_number() {
+            session.old_number_sends.record(channel, session.created_at);
+        }
+        let session_id = self.create_session(session).await?;
+        
+        Ok(Response::new(StartPhoneNumberChangeResponse {
+            session_id,
+            new_phone_number,
+            old_phone_number: mask_phone_number(&old_phone_number),
+            old_number_confirmation_required: changer.confirms_old_number(),
+            verification_code_length: 6,
+            verification_timeout_seconds: self.session_timeout.as_secs() as i32,
+        }))
+    }
+
+    /// Confirms a phone number change with the code sent to the old number.
+    ///
+    /// # Arguments
+    /// * `request` - Contains session token and verification code
+    ///
+    /// # Returns
+    /// * Success: Response indicating whether the code was valid
+    /// * Error: Status with error details if the session is not a phone number change
+    async fn verify_old_phone_number(
+        &self,
+        request: Request<VerifyCodeRequest>,
+    ) -> Result<Response<VerifyCodeResponse>, Status> {
+        let req = request.into_inner();
+        
+        debug!("Received old number verification code for session: {}", req.session_id);
+        
+        let mut session = match self.load_session(&req.session_id).await {
+            Err(status) if status.code() == Code::DeadlineExceeded => {
+                return Ok(Response::new(VerifyCodeResponse {
+                    success: false,
+                    message: "Session expired".to_string(),
+                    remaining_attempts: 0,
+                }));
+            }
+            result => result?,
+        };
+        if session.change.is_none() {
+            return Err(Status::failed_precondition("Session is not a phone number change"));
+        }
+        
+        if let Some(response) = self.check_session_code(&req.session_id, &mut session, CheckedNumber::Old, &req.code).await? {
+            return Ok(Response::new(response));
+        }
+        
+        session.old_number_verified = true;
+        self.save_session(&req.session_id, &mut session).await?;
+        Ok(Response::new(VerifyCodeResponse {
+            success: true,
+            message: "Code verified successfully".to_string(),
+            remaining_attempts: 0,
+        }))
+    }
+
+    /// Completes a phone number change.
+    ///
+    /// # Arguments
+    /// * `request` - Contains the session token
+    ///
+    /// # Returns
+    /// * Success: Response indicating whether the registration was moved
+    /// * Error: Status with error details if the session is not a phone number change
+    ///
+    /// # Flow
+    /// 1. Validates the new number, and the old one if required, were verified
+    /// 2. Moves the registration in DynamoDB and writes the number to LDAP
+    /// 3. Cleans up session
+    async fn complete_phone_number_change(
+        &self,
+        request: Request<CompletePhoneNumberChangeRequest>,
+    ) -> Result<Response<CompleteRegistrationResponse>, Status> {
+        let req = request.into_inner();
+        let changer = self
+            .phone_change
+            .as_ref()
+            .ok_or_else(|| Status::unimplemented("Phone number changes are not enabled"))?;
+        
+        debug!("Received complete phone number change request for session: {}", req.session_id);
+        
+        let session = self.sessions
+            .get(&req.session_id)
+            .await?
+            .ok_or_else(|| Status::not_found("Session not found"))?;
+        let Some(change) = session.change else {
+            return Err(Status::failed_precondition("Session is not a phone number change"));
+        };
+        if !session.verified {
+            return Ok(Response::new(CompleteRegistrationResponse {
+                success: false,
+                message: "New phone number not verified".to_string(),
+            }));
+        }
+        if changer.confirms_old_number() && !session.old_number_verified {
+            return Ok(Response::new(CompleteRegistrationResponse {
+                success: false,
+                message: "Old phone number not confirmed".to_string(),
+            }));
+        }
+        if !self.sessions.delete(&req.session_id, Some(session.version)).await? {
+            return Err(Status::aborted("Session was modified concurrently, try again"));
+        }
+        
+        match changer.apply(change).await {
+            Ok(_) => Ok(Response::new(CompleteRegistrationResponse {
+                success: true,
+                message: "Phone number changed successfully".to_string(),
+            })),
+            Err(e) => {
+                error!("Failed to change phone number: {}", e);
+                Ok(Response::new(CompleteRegistrationResponse {
+                    success: false,
+                    message: format!("Failed to change phone number: {}", e),
+                }))
+            }
+        }
+    }
+
+    /// Sends the code for a session's number, or the old number of a phone
+    /// number change, again on either channel.
+    ///
+    /// # Arguments
+    /// * `request` - Contains the session token, channel and which number to send to
+    ///
+    /// # Returns
+    /// * Success: Response telling whether a code was sent and when codes may
+    ///   next be sent to the number on each channel
+    /// * Error: Status with error details if the session has no such number to verify
+    ///
+    /// # Flow
+    /// 1. Validates the session is waiting for a code for the number
+    /// 2. Refuses the send while the channel is cooling down
+    /// 3. Records the send on the session, so a racing resend is aborted
+    /// 4. Sends the code via Twilio, and takes the recorded send back if that fails
+    async fn resend_code(
+        &self,
+        request: Request<ResendCodeRequest>,
+    ) -> Result<Response<ResendCodeResponse>, Status> {
+        let req = request.into_inner();
+        
+        debug!("Received resend request for session: {}", req.session_id);
+        
+        let channel: VerificationChannel = req.channel
+            .parse()
+            .map_err(|e: anyhow::Error| Status::invalid_argument(e.to_string()))?;
+        let number = if req.old_phone_number { CheckedNumber::Old } else { CheckedNumber::Current };
+        let mut session = self.load_session(&req.session_id).await?;
+        let phone_number = match number {
+            CheckedNumber::Current => {
+                if session.phone_number.is_empty() {
+                    return Err(Status::failed_precondition("No phone number selected"));
+                }
+                if session.verified {
+                    return Err(Status::failed_precondition("Phone number already verified"));
+                }
+                session.phone_number.clone()
+            }
+            CheckedNumber::Old => {
+                let confirms = self.phone_change.as_ref().is_some_and(|changer| changer.confirms_old_number());
+                let Some(change) = session.change.as_ref().filter(|_| confirms) else {
+                    return Err(Status::failed_precondition("Session has no old phone number to confirm"));
+                };
+                if session.old_number_verified {
+                    return Err(Status::failed_precondition("Old phone number already verified"));
+                }
+                change.registration.phone_number.clone()
+            }
+        };
+        
+        let now = SystemTime::now();
+        match self.resend_policy.next_send(number.sends(&mut session), channel) {
+            Some(next) if next <= now => {}
+            next => {
+                let message = match next {
+                    Some(next) => format!(
+                        "Wait {} seconds before sending another {} code",
+                        next.duration_since(now).unwrap_or_default().as_secs_f64().ceil(),
+                        channel
+                    ),
+                    None => format!("No more {} codes may be sent", channel),
+                };
+                return Ok(Response::new(self.resend_response(number.sends(&mut session), false, message)));
+            }
+        }
+        
+        let previous = number.sends(&mut session).clone();
+        number.sends(&mut session).record(channel, now);
+        self.save_session(&req.session_id, &mut session).await?;
+        if let Err(status) = self.send_code(&phone_number, channel).await {
+            // A code that was never sent must not hold up the next one
+            *number.sends(&mut session) = previous;
+            if let Err(e) = self.save_session(&req.session_id, &mut session).await {
+                warn!("Cannot take back the failed send for session {}: {}", req.session_id, e.message());
+            }
+            return Err(status);
+        }
+        
+        Ok(Response::new(self.resend_response(number.sends(&mut session), true, "Verification code sent".to_string())))
+    }
 }
 
 impl RegistrationServer {
     /// Creates a new instance of the registration server.
     ///
     /// # Arguments
-    /// * `ldap_client` - Client for LDAP authentication and user validation
+    /// * `identity` - Identity provider for authentication and phone number lookup
     /// * `twilio_client` - Client for phone number verification via Twilio
     /// * `dynamodb_client` - Client for persistent storage in DynamoDB
     /// * `rate_limiter` - Rate limiter to prevent abuse
@@ -294,31 +803,302 @@ impl RegistrationServer {
     /// # Returns
     /// A new `RegistrationServer` instance configured with the provided clients
     pub fn new(
-        ldap_client: LdapClient,
+        identity: Arc<dyn IdentityProvider>,
         twilio_client: TwilioClient,
         dynamodb_client: DynamoDbClient,
         rate_limiter: RateLimiter,
         session_timeout_secs: u64,
     ) -> Self {
         Self {
-            ldap_client: Arc::new(ldap_client),
+            identity,
             twilio_client: Arc::new(twilio_client),
             dynamodb_client: Arc::new(dynamodb_client),
             rate_limiter: Arc::new(rate_limiter),
-            sessions: Arc::new(Mutex::new(HashMap::new())),
+            sessions: Arc::new(MemorySessionStore::new()),
+            session_metrics: Arc::new(SessionMetrics::new()),
             session_timeout: Duration::from_secs(session_timeout_secs),
+            max_check_attempts: DEFAULT_MAX_CHECK_ATTEMPTS,
+            resend_policy: ResendPolicy::default(),
+            write_back: None,
+            phone_change: None,
+            lockout: None,
+        }
+    }
+
+    /// Sets how many codes a session may check per phone number before it is
+    /// invalidated.
+    ///
+    /// # Arguments
+    /// * `max_check_attempts` - Codes allowed per number; at least one
+    pub fn with_max_check_attempts(mut self, max_check_attempts: u32) -> Self {
+        self.max_check_attempts = max_check_attempts.max(1);
+        self
+    }
+
+    /// Sets when `ResendCode` may send codes again; without a policy codes may
+    /// be resent at any time.
+    ///
+    /// # Arguments
+    /// * `resend_policy` - Cooldowns per channel
+    pub fn with_resend_policy(mut self, resend_policy: ResendPolicy) -> Self {
+        self.resend_policy = resend_policy;
+        self
+    }
+
+    /// Keeps sessions in the given store instead of in memory.
+    ///
+    /// # Arguments
+    /// * `sessions` - Session store, e.g. one shared between replicas
+    pub fn with_session_store(mut self, sessions: Arc<dyn SessionStore>) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
+    /// Throttles sign-ins after repeated failures.
+    ///
+    /// # Arguments
+    /// * `lockout` - Failure tracker, shared with the other password-checking services
+    pub fn with_login_lockout(mut self, lockout: Arc<LoginLockout>) -> Self {
+        self.lockout = Some(lockout);
+        self
+    }
+
+    /// Lets registered users move their registration to a new phone number.
+    ///
+    /// # Arguments
+    /// * `changer` - Changer used once the numbers are verified
+    pub fn with_phone_change(mut self, changer: Arc<PhoneNumberChanger>) -> Self {
+        self.phone_change = Some(changer);
+        self
+    }
+
+    /// Lets users without a directory phone number supply one, which is written
+    /// back once verified.
+    ///
+    /// # Arguments
+    /// * `write_back` - Write-back used after successful verification
+    pub fn with_phone_write_back(mut self, write_back: Arc<PhoneWriteBack>) -> Self {
+        self.write_back = Some(write_back);
+        self
+    }
+
+    /// Returns the session metrics.
+    pub fn session_metrics(&self) -> Arc<SessionMetrics> {
+        self.session_metrics.clone()
+    }
+
+    /// Returns a reaper for the server's session store, to run in the background.
+    ///
+    /// # Arguments
+    /// * `interval` - Time between runs
+    pub fn session_reaper(&self, interval: Duration) -> SessionReaper {
+        SessionReaper::new(self.sessions.clone(), self.session_metrics.clone(), interval)
+    }
+
+    /// Returns a handle for invalidating sessions.
+    pub fn session_handle(&self) -> SessionHandle {
+        SessionHandle {
+            sessions: self.sessions.clone(),
+        }
+    }
+
+    /// Returns the client address of a request, if sign-ins are throttled.
+    fn client_address<T>(&self, request: &Request<T>) -> Option<IpAddr> {
+        let lockout = self.lockout.as_ref()?;
+        client_address(request, lockout.client_address_header())
+    }
+
+    /// Refuses a sign-in attempt while the username or address is throttled.
+    ///
+    /// # Returns
+    /// * `Result<Option<Attempt>>` - The admitted attempt, if sign-ins are throttled
+    async fn check_lockout(&self, username: &str, address: Option<IpAddr>) -> Result<Option<Attempt<'_>>, Status> {
+        match &self.lockout {
+            Some(lockout) => lockout.check(username, address).await.map(Some).map_err(|throttled| {
+                warn!("Refusing sign-in for {}: retry in {:?}", username, throttled.retry_after);
+                Status::from(throttled)
+            }),
+            None => Ok(None),
+        }
+    }
+
+    /// Records the outcome of a sign-in attempt, if sign-ins are throttled.
+    async fn record_sign_in(attempt: Option<Attempt<'_>>, result: &Result<AuthenticatedUser, Error>) {
+        if let Some(attempt) = attempt {
+            attempt.record(result).await;
+        }
+    }
+
+    /// Checks the rate limit and sends a verification code via Twilio.
+    async fn send_code(&self, phone_number: &str, channel: VerificationChannel) -> Result<(), Status> {
+        // Check rate limit
+        if !self.rate_limiter.check_rate_limit(phone_number).await {
+            return Err(Status::resource_exhausted("Too many verification attempts"));
+        }
+        
+        // Start Twilio verification
+        self.twilio_client
+            .send_verification_code(phone_number, channel)
+            .await
+            .map_err(|e| {
+                error!("Failed to send verification code: {}", e);
+                Status::internal(format!("Failed to send verification code: {}", e))
+            })?;
+        
+        debug!("Verification code sent successfully");
+        Ok(())
+    }
+
+    /// Stores a new session and returns its ID.
+    async fn create_session(&self, session: Session) -> Result<String, Status> {
+        let session_id = Uuid::new_v4().to_string();
+        self.sessions.create(&session_id, session).await.map_err(|e| {
+            if let session::Error::Full(live) = e {
+                warn!("Refusing new session, the session store is full ({} sessions)", live);
+                self.session_metrics.record_rejected();
+            }
+            Status::from(e)
+        })?;
+        Ok(session_id)
+    }
+
+    /// Loads a session, deleting it if it has expired.
+    ///
+    /// # Returns
+    /// * `Result<Session>` - The session, or `NOT_FOUND` or `DEADLINE_EXCEEDED`
+    async fn load_session(&self, session_id: &str) -> Result<Session, Status> {
+        let session = self.sessions
+            .get(session_id)
+            .await?
+            .ok_or_else(|| {
+                error!("Session not found");
+                Status::not_found("Session not found")
+            })?;
+        if session.is_expired(SystemTime::now()) {
+            self.session_metrics.record_expired();
+            self.sessions.delete(session_id, None).await?;
+            return Err(Status::deadline_exceeded("Session expired"));
+        }
+        Ok(session)
+    }
+
+    /// Checks a code for one of a session's numbers. The attempt is counted
+    /// against the session before Twilio is asked, so racing requests cannot
+    /// check more codes than allowed; once the attempts run out here or at
+    /// Twilio, the session is deleted. An approved session must be saved
+    /// with the version it has on return, so only one racing request can
+    /// apply the verification. A number that is already verified is not
+    /// checked again, so a client retrying a lost response is told it succeeded.
+    ///
+    /// # Arguments
+    /// * `session_id` - ID of the session
+    /// * `session` - The session as loaded, saved with the attempt counted
+    /// * `number` - Which number the code was sent to
+    /// * `code` - Code submitted by the user
+    ///
+    /// # Returns
+    /// * `Result<Option<VerifyCodeResponse>>` - `None` if the code was approved,
+    ///   otherwise the response to return
+    async fn check_session_code(
+        &self,
+        session_id: &str,
+        session: &mut Session,
+        number: CheckedNumber,
+        code: &str,
+    ) -> Result<Option<VerifyCodeResponse>, Status> {
+        let phone_number = match (number, &session.change) {
+            (CheckedNumber::Current, _) => session.phone_number.clone(),
+            (CheckedNumber::Old, Some(change)) => change.registration.phone_number.clone(),
+            (CheckedNumber::Old, None) => return Err(Status::failed_precondition("Session is not a phone number change")),
+        };
+        let verified = match number {
+            CheckedNumber::Current => session.verified,
+            CheckedNumber::Old => session.old_number_verified,
+        };
+        if verified {
+            return Ok(Some(VerifyCodeResponse {
+                success: true,
+                message: "Phone number already verified".to_string(),
+                remaining_attempts: 0,
+            }));
+        }
+        let attempts = match number {
+            CheckedNumber::Current => &mut session.check_attempts,
+            CheckedNumber::Old => &mut session.old_number_check_attempts,
+        };
+        if *attempts >= self.max_check_attempts {
+            self.sessions.delete(session_id, None).await?;
+            return Ok(Some(Self::attempts_exhausted()));
+        }
+        *attempts += 1;
+        let remaining = self.max_check_attempts - *attempts;
+        self.save_session(session_id, session).await?;
+        
+        let outcome = self.twilio_client
+            .check_code(&phone_number, code)
+            .await
+            .map_err(|e| {
+                error!("Failed to verify code: {}", e);
+                Status::internal(format!("Failed to verify code: {}", e))
+            })?;
+        match outcome {
+            CheckOutcome::Approved => Ok(None),
+            CheckOutcome::Rejected if remaining > 0 => Ok(Some(VerifyCodeResponse {
+                success: false,
+                message: "Invalid verification code".to_string(),
+                remaining_attempts: remaining as i32,
+            })),
+            _ => {
+                warn!("Verification attempts of session {} ran out ({:?}), invalidating it", session_id, outcome);
+                self.sessions.delete(session_id, None).await?;
+                Ok(Some(Self::attempts_exhausted()))
+            }
+        }
+    }
+
+    /// Returns the response for a session whose verification attempts ran out.
+    fn attempts_exhausted() -> VerifyCodeResponse {
+        VerifyCodeResponse {
+            success: false,
+            message: "Too many invalid codes, start the registration again".to_string(),
+            remaining_attempts: 0,
+        }
+    }
+
+    /// Builds a `ResendCode` response with the next send time of each channel.
+    fn resend_response(&self, sends: &CodeSends, success: bool, message: String) -> ResendCodeResponse {
+        let now = SystemTime::now();
+        let next_send_at = |channel| {
+            self.resend_policy
+                .next_send(sends, channel)
+                .map_or(0, |next| epoch_secs(next.max(now)))
+        };
+        ResendCodeResponse {
+            success,
+            message,
+            next_sms_at: next_send_at(VerificationChannel::Sms),
+            next_voice_at: next_send_at(VerificationChannel::Voice),
+        }
+    }
+
+    /// Stores a modified session, failing with `ABORTED` if another request
+    /// changed it since it was loaded.
+    async fn save_session(&self, session_id: &str, session: &mut Session) -> Result<(), Status> {
+        if self.sessions.compare_and_update(session_id, session).await? {
+            Ok(())
+        } else {
+            Err(Status::aborted("Session was modified concurrently, try again"))
         }
     }
 
     /// Removes expired sessions from the session store.
     ///
-    /// This is called periodically to prevent memory leaks from abandoned sessions.
-    pub async fn cleanup_expired_sessions(&self) {
-        let mut sessions = self.sessions.lock().await;
-        sessions.retain(|_, session| {
-            SystemTime::now()
-                .duration_since(session.created_at)
-                .unwrap_or_default() <= self.session_timeout
-        });
+    /// The reaper from `session_reaper` calls this periodically to prevent memory
+    /// leaks from abandoned sessions.
+    ///
+    /// # Returns
+    /// * `Result<usize>` - Number of sessions removed
+    pub async fn cleanup_expired_sessions(&self) -> Result<usize, session::Error> {
+        self.session_reaper(Duration::ZERO).reap_once().await
     }
-}
\ No newline at end of file
+}
@@ -8,18 +8,27 @@
 //! @copyright 2025
 
 use tonic::{Request, Response, Status};
-use tracing::{info, error, debug};
+use tracing::{info, error, debug, warn};
 
 use crate::proto::org::signal::registration::ldap::rpc::{
     validate_credentials_response::Result as ValidateCredentialsResult,
     ValidateCredentialsResponse, ValidateCredentialsRequest, ValidateCredentialsError,
+    ValidateCredentialsErrorType, UserProfile,
 };
 
 pub use crate::proto::org::signal::registration::ldap::rpc::ldap_validation_service_server::{
     LdapValidationService, LdapValidationServiceServer
 };
 
-use crate::auth::ldap::{LdapClient, Error as LdapError};
+use std::sync::Arc;
+
+use crate::auth::identity::IdentityProvider;
+use crate::auth::ldap::Error as LdapError;
+use crate::auth::lockout::LoginLockout;
+use crate::grpc::client_address;
+
+/// LDAP attribute whose value becomes the profile's display name.
+const DISPLAY_NAME_ATTRIBUTE: &str = "displayName";
 
 /// Server implementation for LDAP validation service.
 ///
@@ -27,19 +36,29 @@ use crate::auth::ldap::{LdapClient, Error as LdapError};
 /// associated user information such as phone numbers.
 #[derive(Debug)]
 pub struct LdapValidationServer {
-    ldap_client: LdapClient,
+    identity: Arc<dyn IdentityProvider>,
+    lockout: Option<Arc<LoginLockout>>,
 }
 
 impl LdapValidationServer {
     /// Creates a new LDAP validation server instance.
     ///
     /// # Arguments
-    /// * `ldap_client` - Client for LDAP operations
+    /// * `identity` - Identity provider that checks the credentials
     ///
     /// # Returns
     /// A new `LdapValidationServer` instance
-    pub fn new(ldap_client: LdapClient) -> Self {
-        Self { ldap_client }
+    pub fn new(identity: Arc<dyn IdentityProvider>) -> Self {
+        Self { identity, lockout: None }
+    }
+
+    /// Throttles credential checks after repeated failures.
+    ///
+    /// # Arguments
+    /// * `lockout` - Failure tracker, shared with the registration service
+    pub fn with_login_lockout(mut self, lockout: Arc<LoginLockout>) -> Self {
+        self.lockout = Some(lockout);
+        self
     }
 }
 
@@ -51,47 +70,92 @@ impl LdapValidationService for LdapValidationServer {
     /// * `request` - Contains the username and password to validate
     ///
     /// # Returns
-    /// * Success: Response with user's phone number if authentication is successful
+    /// * Success: Response with user's phone number and profile if authentication is successful
     /// * Error: Status with error details if validation fails
     async fn validate_credentials(
         &self,
         request: Request<ValidateCredentialsRequest>,
     ) -> Result<Response<ValidateCredentialsResponse>, Status> {
+        let address = self
+            .lockout
+            .as_ref()
+            .and_then(|lockout| client_address(&request, lockout.client_address_header()));
         let request = request.into_inner();
         
         info!("Received validation request for user: {}", request.user_id);
         debug!("Attempting LDAP authentication...");
         
-        let result = self.ldap_client.authenticate_user(&request.user_id, &request.password).await;
+        let attempt = match &self.lockout {
+            Some(lockout) => Some(lockout.check(&request.user_id, address).await.map_err(|throttled| {
+                warn!("Refusing validation for {}: retry in {:?}", request.user_id, throttled.retry_after);
+                Status::from(throttled)
+            })?),
+            None => None,
+        };
+        let result = self.identity.authenticate(&request.user_id, &request.password).await;
+        if let Some(attempt) = attempt {
+            attempt.record(&result).await;
+        }
         
         match result {
-            Ok(phone_number) => {
-                info!("Authentication successful for user: {}", request.user_id);
+            Ok(user) => {
+                info!("Authentication successful for user: {} ({})", request.user_id, user.username);
+                let display_name = user
+                    .profile
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(DISPLAY_NAME_ATTRIBUTE))
+                    .map(|(_, value)| value.clone())
+                    .unwrap_or_default();
                 Ok(Response::new(ValidateCredentialsResponse {
-                    result: Some(ValidateCredentialsResult::PhoneNumber(phone_number)),
+                    result: Some(ValidateCredentialsResult::PhoneNumber(user.phone_number)),
+                    profile: Some(UserProfile {
+                        display_name,
+                        attributes: user.profile,
+                    }),
                 }))
             }
             Err(err) => {
                 let (error_type, message) = match err {
                     LdapError::UserNotFound(msg) => {
                         error!("User not found: {}", msg);
-                        (1, msg)
+                        (ValidateCredentialsErrorType::UserNotFound, msg)
                     }
                     LdapError::AuthenticationFailed => {
                         error!("Authentication failed");
-                        (2, "Invalid credentials".to_string())
+                        (ValidateCredentialsErrorType::InvalidCredentials, "Invalid credentials".to_string())
+                    }
+                    LdapError::PhoneNumberNotFound(_) | LdapError::PhoneNumberEmpty => {
+                        error!("Phone number not found: {}", err);
+                        (ValidateCredentialsErrorType::PhoneNumberNotFound, err.to_string())
+                    }
+                    LdapError::AccountLocked(_) => {
+                        error!("{}", err);
+                        (ValidateCredentialsErrorType::AccountLocked, "Account is locked".to_string())
+                    }
+                    LdapError::AccountDisabled(_) => {
+                        error!("{}", err);
+                        (ValidateCredentialsErrorType::AccountDisabled, "Account is disabled".to_string())
+                    }
+                    LdapError::AccountExpired(_) => {
+                        error!("{}", err);
+                        (ValidateCredentialsErrorType::AccountExpired, "Account has expired".to_string())
+                    }
+                    LdapError::PasswordMustChange(_) => {
+                        error!("{}", err);
+                        (ValidateCredentialsErrorType::PasswordMustChange, "Password must be changed".to_string())
                     }
                     _ => {
                         error!("Server error: {:?}", err);
-                        (3, format!("Server error: {}", err))
+                        (ValidateCredentialsErrorType::ServerError, format!("Server error: {}", err))
                     }
                 };
                 
                 Ok(Response::new(ValidateCredentialsResponse {
                     result: Some(ValidateCredentialsResult::Error(ValidateCredentialsError {
-                        error_type,
+                        error_type: error_type as i32,
                         message,
                     })),
+                    profile: None,
                }))
             }
         }
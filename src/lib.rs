@@ -1,46 +1,51 @@
-/// Signal Registration Service Library
-///
-/// This library provides the core functionality for the Signal Registration Service,
-/// including LDAP authentication, Twilio verification, and DynamoDB storage.
-///
-/// # Features
-/// - LDAP authentication and user management
-/// - Twilio SMS and voice verification
-/// - DynamoDB data persistence
-/// - gRPC service interface
-/// - Rate limiting and security
-///
-/// # Modules
-/// - `auth`: LDAP authentication and user management
-/// - `twilio`: Phone number verification via SMS and voice
-/// - `db`: DynamoDB storage and data management
-/// - `grpc`: gRPC service implementation
-/// - `config`: Configuration management
-/// - `ldap_validation`: LDAP validation service
-///
-/// # Example
-/// ```no_run
-/// use registration_service::{
-///     auth::ldap::LdapClient,
-///     twilio::TwilioClient,
-///     db::dynamodb::DynamoDbClient,
-///     config::Settings,
-/// };
-///
-/// async fn setup_service() {
-///     let settings = Settings::new().expect("Failed to load configuration");
-///     let ldap_client = LdapClient::new(settings.ldap).await.expect("Failed to create LDAP client");
-///     let twilio_client = TwilioClient::new(settings.twilio).expect("Failed to create Twilio client");
-///     let dynamodb_client = DynamoDbClient::new(settings.dynamodb).await.expect("Failed to create DynamoDB client");
-/// }
-/// ```
-///
-/// # Copyright
-/// Copyright (c) 2025 Signal Messenger, LLC
-/// All rights reserved.
-///
-/// # License
-/// Licensed under the AGPLv3 license.
+//! Signal Registration Service Library
+//!
+//! This library provides the core functionality for the Signal Registration Service,
+//! including LDAP authentication, Twilio verification, and DynamoDB storage.
+//!
+//! # Features
+//! - LDAP authentication and user management
+//! - Twilio SMS and voice verification
+//! - DynamoDB data persistence
+//! - gRPC service interface
+//! - Rate limiting and security
+//!
+//! # Modules
+//! - `auth`: LDAP authentication and user management
+//! - `twilio`: Phone number verification via SMS and voice
+//! - `db`: DynamoDB storage and data management
+//! - `grpc`: gRPC service implementation
+//! - `config`: Configuration management
+//! - `ldap_validation`: LDAP validation service
+//! - `reconcile`: Revocation of registrations that no longer match the directory
+//! - `watch`: Near-real-time revocation from directory change notifications
+//! - `writeback`: Writing user-supplied phone numbers back to the directory
+//! - `phone_change`: Self-service moves of a registration to a new phone number
+//! - `session`: Registration session storage, in memory or in DynamoDB
+//!
+//! # Example
+//! ```no_run
+//! use rust_ldap_registration::{
+//!     auth::ldap::{LdapClient, LdapConfig},
+//!     twilio::{TwilioClient, TwilioConfig},
+//!     db::dynamodb::DynamoDbClient,
+//! };
+//!
+//! async fn setup_service(ldap_config: LdapConfig, twilio_config: TwilioConfig) {
+//!     let ldap_client = LdapClient::new(ldap_config).await.expect("Failed to create LDAP client");
+//!     let twilio_client = TwilioClient::new(twilio_config).expect("Failed to create Twilio client");
+//!     let dynamodb_client = DynamoDbClient::new("signal_accounts".to_string(), "us-west-2".to_string())
+//!         .await
+//!         .expect("Failed to create DynamoDB client");
+//! }
+//! ```
+//!
+//! # Copyright
+//! Copyright (c) 2025 Signal Messenger, LLC
+//! All rights reserved.
+//!
+//! # License
+//! Licensed under the AGPLv3 license.
 
 pub mod auth;
 pub mod twilio;
@@ -48,6 +53,11 @@ pub mod db;
 pub mod grpc;
 pub mod config;
 pub mod ldap_validation;
+pub mod reconcile;
+pub mod watch;
+pub mod writeback;
+pub mod phone_change;
+pub mod session;
 
 /// Generated protocol buffer code
 pub mod proto {
@@ -19,20 +19,41 @@
 //! 5. User submits verification code
 //! 6. Service stores verified registration in DynamoDB
 //!
+//! With `use_ldap: false`, users come from the file configured under
+//! `identity` and the directory features (write-back, phone number changes,
+//! reconciliation and watching) are unavailable.
+//!
+//! # Commands
+//! - no arguments: run the gRPC server
+//! - `reconcile [--dry-run]`: check every registration against LDAP once and exit
+//! - `phone-approvals`: list the phone numbers waiting for write-back approval
+//! - `approve-phone <number> [--by <name>]`: write a pending number to LDAP
+//! - `reject-phone <number> [--by <name>]`: discard a pending number
+//!
 //! @author Joseph G Noonan
 //! @copyright 2025
 
 use tonic::transport::Server;
-use tracing::{info, Level};
+use tracing::{error, info, warn, Level};
 use tracing_subscriber::fmt;
 use rust_ldap_registration::proto::registration::registration_service_server::RegistrationServiceServer;
 use rust_ldap_registration::grpc::RegistrationServer;
 use rust_ldap_registration::ldap_validation::{LdapValidationServer, LdapValidationServiceServer};
+use rust_ldap_registration::auth::identity;
 use rust_ldap_registration::auth::ldap::{LdapClient, LdapConfig};
+use rust_ldap_registration::auth::lockout::LoginLockout;
 use rust_ldap_registration::db::dynamodb::DynamoDbClient;
-use rust_ldap_registration::twilio::{TwilioClient, TwilioConfig};
+use rust_ldap_registration::twilio::{ResendPolicy, TwilioClient, TwilioConfig};
 use rust_ldap_registration::config::Config;
 use rust_ldap_registration::twilio::rate_limit::{RateLimiter, RateLimitConfig};
+use rust_ldap_registration::reconcile::Reconciler;
+use rust_ldap_registration::watch::DirectoryWatcher;
+use rust_ldap_registration::writeback::PhoneWriteBack;
+use rust_ldap_registration::phone_change::PhoneNumberChanger;
+use rust_ldap_registration::config::{IdentityProviderKind, RegistrationConfig, SessionStoreKind, WriteBackBind};
+use rust_ldap_registration::session::{self, SessionMetrics};
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Initializes the logging system with appropriate configuration.
 ///
@@ -52,7 +73,6 @@ fn setup_logging() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .with_ansi(true)
         .with_writer(std::io::stdout)
         .try_init()
-        .map_err(|e| e.into())
 }
 
 /// Initializes and starts all service dependencies.
@@ -73,18 +93,28 @@ async fn setup_services(config: Config) -> Result<(), Box<dyn std::error::Error
     let registration_config = config.registration();
 
     // Initialize LDAP client
-    info!("Initializing LDAP client with URL: {}", registration_config.ldap.url);
-    let ldap_config = LdapConfig {
-        url: registration_config.ldap.url.clone(),
-        bind_dn: registration_config.ldap.bind_dn.clone(),
-        bind_password: registration_config.ldap.bind_password.clone(),
-        base_dn: registration_config.ldap.base_dn.clone(),
-        username_attribute: registration_config.ldap.username_attribute.clone(),
-        phone_number_attribute: registration_config.ldap.phone_number_attribute.clone(),
+    let ldap_client = if registration_config.use_ldap {
+        info!("Initializing LDAP client with URL: {}", registration_config.ldap.url);
+        if !registration_config.ldap.urls.is_empty() {
+            info!("LDAP servers: {}", registration_config.ldap.urls.join(", "));
+        }
+        if registration_config.ldap.trust_store.is_some() && registration_config.ldap.ca_bundle.is_none() {
+            warn!("LDAP trustStore is ignored; set ca_bundle to a PEM CA bundle instead");
+        }
+        let ldap_config = LdapConfig::from(registration_config.ldap.clone());
+        info!("Attempting to connect to LDAP server...");
+        let ldap_client = LdapClient::new(ldap_config).await?;
+        info!("LDAP client initialized successfully");
+        Some(ldap_client)
+    } else {
+        info!("LDAP is disabled");
+        None
     };
-    info!("Attempting to connect to LDAP server...");
-    let ldap_client = LdapClient::new(ldap_config).await?;
-    info!("LDAP client initialized successfully");
+
+    // Initialize identity provider
+    let identity = identity::from_config(&registration_config.identity, ldap_client.clone())?;
+    // The directory features need the directory to be the identity provider
+    let directory = ldap_client.filter(|_| registration_config.identity.provider(registration_config.use_ldap) == IdentityProviderKind::Ldap);
 
     // Initialize DynamoDB client
     info!("Initializing DynamoDB client with table: {}", registration_config.dynamodb.table_name);
@@ -94,6 +124,20 @@ async fn setup_services(config: Config) -> Result<(), Box<dyn std::error::Error
     ).await?;
     info!("DynamoDB client initialized successfully");
 
+    // Initialize session store
+    let session_config = &registration_config.sessions;
+    if session_config.store == SessionStoreKind::Dynamodb {
+        // Passwords for writing as the user are never persisted
+        let writes_as_user = (registration_config.phone_write_back.enabled
+            && registration_config.phone_write_back.bind_as == WriteBackBind::User)
+            || (registration_config.phone_change.enabled && registration_config.phone_change.bind_as == WriteBackBind::User);
+        if writes_as_user {
+            return Err("bind_as: user keeps the user's password in the session and cannot be used with the dynamodb session store".into());
+        }
+        info!("Keeping sessions in DynamoDB table: {}", session_config.table_name);
+    }
+    let session_store = session::from_config(session_config, &registration_config.dynamodb.region).await?;
+
     // Initialize Twilio client
     info!("Initializing Twilio client...");
     let twilio_config = TwilioConfig {
@@ -114,15 +158,107 @@ async fn setup_services(config: Config) -> Result<(), Box<dyn std::error::Error
     let addr = format!("{}:{}", config.registration().grpc.server.endpoint, config.registration().grpc.server.port).parse()?;
     info!("Starting server on {}", addr);
 
-    let ldap_service = LdapValidationServer::new(ldap_client.clone());
+    // Throttle repeated failed sign-ins on both password-checking services
+    let login_lockout = if registration_config.login_lockout.enabled {
+        info!("Failed sign-in throttling enabled");
+        Some(Arc::new(LoginLockout::new(registration_config.login_lockout.clone())))
+    } else {
+        warn!("Failed sign-in throttling is disabled");
+        None
+    };
+
+    let mut ldap_service = LdapValidationServer::new(identity.clone());
+    if let Some(lockout) = &login_lockout {
+        ldap_service = ldap_service.with_login_lockout(lockout.clone());
+    }
+
+    // Let users without a directory phone number supply one, if enabled
+    let write_back = match &directory {
+        Some(ldap_client) if registration_config.phone_write_back.enabled => {
+            info!("Phone number write-back enabled");
+            Some(Arc::new(phone_write_back(registration_config, ldap_client.clone()).await?))
+        }
+        None if registration_config.phone_write_back.enabled => {
+            warn!("Phone number write-back needs the LDAP identity provider, not enabling it");
+            None
+        }
+        _ => None,
+    };
+
+    // Revoke registrations of users who left, if enabled
+    let reconciliation = registration_config.reconciliation.clone();
+    if reconciliation.enabled && directory.is_none() {
+        warn!("Directory reconciliation needs the LDAP identity provider, not scheduling it");
+    }
+    if let Some(ldap_client) = directory.as_ref().filter(|_| reconciliation.enabled) {
+        info!("Scheduling directory reconciliation every {} seconds", reconciliation.interval_secs);
+        let reconciler_db = DynamoDbClient::new(
+            registration_config.dynamodb.table_name.clone(),
+            registration_config.dynamodb.region.clone(),
+        ).await?;
+        let mut reconciler = Reconciler::new(ldap_client.clone(), Arc::new(reconciler_db), reconciliation);
+        if let Some(write_back) = &write_back {
+            reconciler = reconciler.with_phone_write_back(write_back.clone());
+        }
+        tokio::spawn(reconciler.run_periodically());
+    }
 
-    let registration_server = RegistrationServer::new(
-        ldap_client,
+    let mut registration_server = RegistrationServer::new(
+        identity,
         twilio_client,
         dynamodb_client,
         rate_limiter,
         config.registration().grpc.timeout_secs,
     );
+    registration_server = registration_server
+        .with_session_store(session_store)
+        .with_max_check_attempts(registration_config.rate_limits.check_verification_code.max_attempts)
+        .with_resend_policy(ResendPolicy::from(&registration_config.rate_limits));
+    if let Some(write_back) = &write_back {
+        registration_server = registration_server.with_phone_write_back(write_back.clone());
+    }
+    if let Some(lockout) = login_lockout {
+        registration_server = registration_server.with_login_lockout(lockout);
+    }
+
+    // Let registered users move to a new phone number, if enabled
+    if registration_config.phone_change.enabled && directory.is_none() {
+        warn!("Phone number changes need the LDAP identity provider, not enabling them");
+    }
+    if let Some(ldap_client) = directory.as_ref().filter(|_| registration_config.phone_change.enabled) {
+        info!("Self-service phone number changes enabled");
+        let change_db = DynamoDbClient::new(
+            registration_config.dynamodb.table_name.clone(),
+            registration_config.dynamodb.region.clone(),
+        ).await?;
+        let changer = PhoneNumberChanger::new(ldap_client.clone(), Arc::new(change_db), &registration_config.phone_change);
+        registration_server = registration_server.with_phone_change(Arc::new(changer));
+    }
+
+    // Revoke sessions and registrations as soon as the directory changes, if enabled
+    if registration_config.watch.enabled && directory.is_none() {
+        warn!("Directory watching needs the LDAP identity provider, not starting it");
+    }
+    if let Some(ldap_client) = directory.filter(|_| registration_config.watch.enabled) {
+        let watcher_db = DynamoDbClient::new(
+            registration_config.dynamodb.table_name.clone(),
+            registration_config.dynamodb.region.clone(),
+        ).await?;
+        let mut reconciler = Reconciler::new(ldap_client.clone(), Arc::new(watcher_db), registration_config.reconciliation.clone());
+        if let Some(write_back) = &write_back {
+            reconciler = reconciler.with_phone_write_back(write_back.clone());
+        }
+        let watcher = DirectoryWatcher::new(ldap_client, registration_server.session_handle(), reconciler, &registration_config.watch);
+        tokio::spawn(watcher.run());
+    }
+
+    // Delete abandoned sessions in the background
+    tokio::spawn(registration_server.session_reaper(Duration::from_secs(session_config.reap_interval_secs)).run());
+
+    // Expose session metrics to Prometheus, if enabled
+    if config.metrics.enabled {
+        tokio::spawn(serve_metrics(config.metrics.port, registration_server.session_metrics()));
+    }
 
     Server::builder()
         .add_service(RegistrationServiceServer::new(registration_server))
@@ -133,12 +269,142 @@ async fn setup_services(config: Config) -> Result<(), Box<dyn std::error::Error
     Ok(())
 }
 
+/// Serves the session metrics in the Prometheus text format on `/metrics`.
+///
+/// # Arguments
+/// * `port` - Port to listen on, on all interfaces
+/// * `metrics` - Metrics of the registration server
+async fn serve_metrics(port: u16, metrics: Arc<SessionMetrics>) {
+    let app = axum::Router::new().route(
+        "/metrics",
+        axum::routing::get(move || {
+            let metrics = metrics.clone();
+            async move { ([(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics.render()) }
+        }),
+    );
+    let listener = match tokio::net::TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Could not listen for metrics scrapes on port {}: {}", port, e);
+            return;
+        }
+    };
+    info!("Serving metrics on port {}", port);
+    if let Err(e) = axum::serve(listener, app).await {
+        error!("Metrics endpoint failed: {}", e);
+    }
+}
+
+/// Creates the phone number write-back, with its approval table when approval
+/// is required.
+///
+/// # Arguments
+/// * `registration_config` - Registration configuration
+/// * `ldap_client` - Client used to write the numbers
+///
+/// # Returns
+/// * `Result<PhoneWriteBack>` - The write-back or error if the approval table client fails to start
+async fn phone_write_back(
+    registration_config: &RegistrationConfig,
+    ldap_client: LdapClient,
+) -> Result<PhoneWriteBack, Box<dyn std::error::Error + Send + Sync>> {
+    let write_back_config = &registration_config.phone_write_back;
+    let approvals = if write_back_config.require_approval {
+        info!("Phone numbers wait for approval in table: {}", write_back_config.approval_table);
+        Some(DynamoDbClient::new(
+            write_back_config.approval_table.clone(),
+            registration_config.dynamodb.region.clone(),
+        ).await?)
+    } else {
+        None
+    };
+    Ok(PhoneWriteBack::new(ldap_client, write_back_config, approvals))
+}
+
+/// Lists, approves or rejects phone numbers waiting for write-back approval.
+///
+/// # Arguments
+/// * `config` - Application configuration
+/// * `command` - `phone-approvals`, `approve-phone` or `reject-phone`
+/// * `args` - Arguments after the command: the phone number and an optional `--by <name>`
+///
+/// # Returns
+/// * `Result<()>` - Success or error if approval is not enabled or the number is not pending
+async fn run_phone_approval(config: Config, command: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let registration_config = config.registration();
+    if !registration_config.phone_write_back.require_approval {
+        return Err("phone_write_back.require_approval is not enabled".into());
+    }
+    if !registration_config.use_ldap {
+        return Err("Phone number approval requires use_ldap".into());
+    }
+
+    let ldap_client = LdapClient::new(LdapConfig::from(registration_config.ldap.clone())).await?;
+    let write_back = phone_write_back(registration_config, ldap_client).await?;
+
+    if command == "phone-approvals" {
+        for approval in write_back.pending().await? {
+            println!("{}\t{}\t{}\t{}", approval.phone_number, approval.username, approval.dn, approval.requested_at);
+        }
+        return Ok(());
+    }
+
+    let phone_number = args.first().ok_or_else(|| format!("Usage: {} <number> [--by <name>]", command))?;
+    let approver = args
+        .iter()
+        .position(|arg| arg == "--by")
+        .and_then(|pos| args.get(pos + 1))
+        .cloned()
+        .or_else(|| std::env::var("USER").ok())
+        .unwrap_or_else(|| "unknown".to_string());
+    let approval = if command == "approve-phone" {
+        write_back.approve(phone_number, &approver).await?
+    } else {
+        write_back.reject(phone_number, &approver).await?
+    };
+    println!("{} {} for {}", if command == "approve-phone" { "Approved" } else { "Rejected" }, approval.phone_number, approval.username);
+
+    Ok(())
+}
+
+/// Runs the directory reconciliation once and logs the summary.
+///
+/// # Arguments
+/// * `config` - Application configuration
+/// * `dry_run` - Report registrations that would be revoked without deleting them
+///
+/// # Returns
+/// * `Result<()>` - Success or error if the clients fail to start or the table cannot be read
+async fn run_reconciliation(config: Config, dry_run: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let registration_config = config.registration();
+    if !registration_config.use_ldap {
+        return Err("Reconciliation requires use_ldap".into());
+    }
+
+    let ldap_client = LdapClient::new(LdapConfig::from(registration_config.ldap.clone())).await?;
+    let dynamodb_client = DynamoDbClient::new(
+        registration_config.dynamodb.table_name.clone(),
+        registration_config.dynamodb.region.clone(),
+    ).await?;
+
+    let mut reconciliation = registration_config.reconciliation.clone();
+    reconciliation.dry_run |= dry_run;
+    let mut reconciler = Reconciler::new(ldap_client.clone(), Arc::new(dynamodb_client), reconciliation);
+    if registration_config.phone_write_back.enabled {
+        reconciler = reconciler.with_phone_write_back(Arc::new(phone_write_back(registration_config, ldap_client).await?));
+    }
+    let report = reconciler.run_once().await?;
+    println!("{}", report);
+
+    Ok(())
+}
+
 /// Main entry point for the registration service.
 ///
 /// # Flow
 /// 1. Initializes logging and configuration
 /// 2. Sets up service dependencies (LDAP, Twilio, DynamoDB)
-/// 3. Starts the gRPC server
+/// 3. Starts the gRPC server, or runs the `reconcile` or phone approval commands
 ///
 /// # Returns
 /// * `Result<()>` - Success or error if service fails to start
@@ -152,7 +418,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let config = Config::new().map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
     info!("Configuration loaded successfully");
 
-    setup_services(config).await?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => setup_services(config).await?,
+        Some("reconcile") => run_reconciliation(config, args[1..].iter().any(|arg| arg == "--dry-run")).await?,
+        Some(command @ ("phone-approvals" | "approve-phone" | "reject-phone")) => run_phone_approval(config, command, &args[1..]).await?,
+        Some(command) => return Err(format!("Unknown command: {}", command).into()),
+    }
 
     Ok(())
 }
@@ -0,0 +1,230 @@
+//! Self-service phone number changes.
+//!
+//! A user with a new phone used to need IT to edit the directory and delete the
+//! old registration by hand. This module moves a registration to a new number
+//! once the user has signed in with their password and verified the new number
+//! (and, if configured, confirmed the change on the old one). The registration is
+//! moved to the new `phone_number` key in one DynamoDB transaction that records
+//! the old number in `phone_history`, and the new number is written to the
+//! directory. If the directory write fails, the move is undone.
+//!
+//! Only registrations stored for the signed-in user's directory entry can be
+//! moved, so a user whose username collides with another entry's, e.g. in
+//! another domain, cannot take over that entry's registration.
+//!
+//! Every change is logged under the `audit` tracing target.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+use crate::auth::ldap::{AuthenticatedUser, Error as LdapError, LdapClient};
+use crate::auth::phone::mask_phone_number;
+use crate::config::{PhoneChangeConfig, WriteBackBind};
+use crate::db::dynamodb::{DynamoDbClient, Error as DbError, PhoneHistoryEntry, RegistrationRecord};
+
+/// Errors that can occur while changing a phone number
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("LDAP error: {0}")]
+    Ldap(#[from] LdapError),
+    #[error("DynamoDB error: {0}")]
+    Db(Box<DbError>),
+    #[error("User {0} has no registration")]
+    NoRegistration(String),
+    #[error("User {0} has {1} registrations")]
+    AmbiguousRegistration(String, usize),
+    #[error("Phone number is already registered")]
+    NumberInUse,
+    #[error("New phone number is the registered one")]
+    SameNumber,
+}
+
+impl From<DbError> for Error {
+    fn from(error: DbError) -> Self {
+        Error::Db(Box::new(error))
+    }
+}
+
+/// A change waiting for verification, kept with the session.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NumberChange {
+    /// DN of the user's directory entry
+    pub dn: String,
+    /// The registration as currently stored
+    pub registration: RegistrationRecord,
+    /// Number to move to, in E.164 format
+    pub new_phone_number: String,
+    /// Bind name and password of the user, kept only when writing as the user;
+    /// never serialized, so a stored session cannot leak the password
+    #[serde(skip)]
+    credentials: Option<(String, String)>,
+}
+
+impl fmt::Debug for NumberChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NumberChange")
+            .field("dn", &self.dn)
+            .field("username", &self.registration.username)
+            .field("old_phone_number", &mask_phone_number(&self.registration.phone_number))
+            .field("new_phone_number", &mask_phone_number(&self.new_phone_number))
+            .field("credentials", &self.credentials.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+/// Moves registrations and directory entries to new phone numbers.
+pub struct PhoneNumberChanger {
+    ldap_client: LdapClient,
+    dynamodb_client: Arc<DynamoDbClient>,
+    /// Attribute the new number is written to
+    attribute: String,
+    /// Whose bind writes the new number
+    bind_as: WriteBackBind,
+    /// Whether the change must be confirmed on the old number
+    confirm_old_number: bool,
+}
+
+impl PhoneNumberChanger {
+    /// Creates a changer.
+    ///
+    /// # Arguments
+    /// * `ldap_client` - Client used to write the new numbers
+    /// * `dynamodb_client` - Client for the registration table
+    /// * `config` - Phone number change settings
+    pub fn new(ldap_client: LdapClient, dynamodb_client: Arc<DynamoDbClient>, config: &PhoneChangeConfig) -> Self {
+        let attribute = config
+            .attribute
+            .clone()
+            .unwrap_or_else(|| ldap_client.phone_number_attribute().to_string());
+        Self {
+            ldap_client,
+            dynamodb_client,
+            attribute,
+            bind_as: config.bind_as,
+            confirm_old_number: config.confirm_old_number,
+        }
+    }
+
+    /// Returns whether a change must be confirmed on the old number.
+    pub fn confirms_old_number(&self) -> bool {
+        self.confirm_old_number
+    }
+
+    /// Looks up the registration to move and checks the new number is free.
+    /// Registrations stored for another directory entry, or before DNs were
+    /// stored, are not the user's to move.
+    ///
+    /// # Arguments
+    /// * `user` - The authenticated user
+    /// * `new_phone_number` - Number to move to, in E.164 format
+    /// * `password` - The user's password, kept only when writing as the user
+    ///
+    /// # Returns
+    /// * `Result<NumberChange>` - The change to apply once the numbers are verified;
+    ///   `NoRegistration` or `AmbiguousRegistration` unless the user has exactly one
+    ///   registration, `SameNumber` or `NumberInUse` if the new number cannot be used
+    pub async fn prepare(&self, user: &AuthenticatedUser, new_phone_number: &str, password: &str) -> Result<NumberChange, Error> {
+        let (mut registrations, others): (Vec<_>, Vec<_>) = self
+            .dynamodb_client
+            .find_registrations_by_username(&user.username)
+            .await?
+            .into_iter()
+            .partition(|record| record.dn.eq_ignore_ascii_case(&user.dn));
+        if !others.is_empty() {
+            warn!(
+                target: "audit",
+                "Ignoring {} registration(s) of {} not stored for dn={}",
+                others.len(),
+                user.username,
+                user.dn
+            );
+        }
+        let registration = match registrations.len() {
+            0 => return Err(Error::NoRegistration(user.username.clone())),
+            1 => registrations.remove(0),
+            count => return Err(Error::AmbiguousRegistration(user.username.clone(), count)),
+        };
+        if registration.phone_number == new_phone_number {
+            return Err(Error::SameNumber);
+        }
+        if self.dynamodb_client.get_registration(new_phone_number).await?.is_some() {
+            return Err(Error::NumberInUse);
+        }
+
+        info!(
+            target: "audit",
+            "Phone number change requested: user={} dn={} old={} new={}",
+            user.username,
+            user.dn,
+            mask_phone_number(&registration.phone_number),
+            mask_phone_number(new_phone_number)
+        );
+        Ok(NumberChange {
+            dn: user.dn.clone(),
+            registration,
+            new_phone_number: new_phone_number.to_string(),
+            credentials: (self.bind_as == WriteBackBind::User).then(|| (user.bind_name.clone(), password.to_string())),
+        })
+    }
+
+    /// Moves the registration to the new number and writes it to the directory.
+    ///
+    /// # Arguments
+    /// * `change` - The change prepared when the session started
+    ///
+    /// # Returns
+    /// * `Result<RegistrationRecord>` - The registration under its new number, or the
+    ///   error that stopped the change; a failed directory write undoes the move
+    pub async fn apply(&self, change: NumberChange) -> Result<RegistrationRecord, Error> {
+        let old = &change.registration;
+        let mut moved = old.clone();
+        moved.phone_number = change.new_phone_number.clone();
+        moved.phone_history.push(PhoneHistoryEntry {
+            phone_number: old.phone_number.clone(),
+            changed_at: chrono::Utc::now().to_rfc3339(),
+        });
+        let (old_phone, new_phone) = (mask_phone_number(&old.phone_number), mask_phone_number(&moved.phone_number));
+
+        self.dynamodb_client.move_registration(old, &moved).await.inspect_err(|e| {
+            error!(
+                target: "audit",
+                "Phone number change failed: user={} old={} new={} error={}",
+                old.username, old_phone, new_phone, e
+            );
+        })?;
+
+        let bind = if change.credentials.is_some() { "user" } else { "service" };
+        let credentials = change.credentials.as_ref().map(|(name, password)| (name.as_str(), password.as_str()));
+        if let Err(e) = self
+            .ldap_client
+            .write_phone_number(&change.dn, &self.attribute, &moved.phone_number, credentials)
+            .await
+        {
+            error!(
+                target: "audit",
+                "Phone number change failed: user={} dn={} old={} new={} bind={} error={}",
+                old.username, change.dn, old_phone, new_phone, bind, e
+            );
+            if let Err(undo) = self.dynamodb_client.move_registration(&moved, old).await {
+                error!(
+                    target: "audit",
+                    "Phone number change could not be undone: user={} old={} new={} error={}",
+                    old.username, old_phone, new_phone, undo
+                );
+            }
+            return Err(e.into());
+        }
+
+        info!(
+            target: "audit",
+            "Phone number changed: user={} dn={} attribute={} old={} new={} bind={}",
+            old.username, change.dn, self.attribute, old_phone, new_phone, bind
+        );
+        Ok(moved)
+    }
+}
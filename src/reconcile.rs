@@ -0,0 +1,324 @@
+//! Directory reconciliation.
+//!
+//! Registrations outlive the directory entries they were made for: nothing in the
+//! registration flow notices when an employee leaves. This module pages through
+//! every registration in DynamoDB, looks its username up again through the LDAP
+//! client and revokes the registration when the user is gone, disabled, no longer
+//! in an authorized group, or no longer holds the registered phone number. It runs
+//! periodically inside the server or once from the `reconcile` subcommand, and in
+//! dry-run mode only reports what it would revoke.
+//!
+//! A run checks every registration before deleting any. When more of them are
+//! flagged than `max_deletions` or `max_deletion_fraction` allow, something is
+//! more likely wrong with the directory or the configuration than with the
+//! registrations, and the run is aborted without deleting anything.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+use crate::auth::ldap::{Error as LdapError, LdapClient};
+use crate::auth::phone::mask_phone_number;
+use crate::auth::policy::AccountState;
+use crate::config::ReconciliationConfig;
+use crate::db::dynamodb::{DynamoDbClient, Error as DbError, RegistrationRecord};
+use crate::writeback::{Error as WriteBackError, PhoneWriteBack};
+
+/// Why a registration no longer matches the directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Finding {
+    /// The user's entry no longer exists
+    UserGone,
+    /// The account has been disabled
+    Disabled,
+    /// The user is no longer in an authorized group
+    NotAuthorized,
+    /// The entry no longer holds the registered phone number
+    PhoneChanged,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Finding::UserGone => "user no longer exists",
+            Finding::Disabled => "account is disabled",
+            Finding::NotAuthorized => "user is not in an authorized group",
+            Finding::PhoneChanged => "phone number changed",
+        })
+    }
+}
+
+/// Outcome of one reconciliation run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReconcileReport {
+    /// Whether registrations were only reported, not deleted
+    pub dry_run: bool,
+    /// Registrations checked
+    pub scanned: usize,
+    /// Registrations that still match the directory
+    pub kept: usize,
+    /// Registrations whose user no longer exists
+    pub user_gone: usize,
+    /// Registrations whose account is disabled
+    pub disabled: usize,
+    /// Registrations whose user left the authorized groups
+    pub not_authorized: usize,
+    /// Registrations whose phone number changed
+    pub phone_changed: usize,
+    /// Registrations deleted
+    pub deleted: usize,
+    /// Registrations that could not be checked or deleted and were left alone
+    pub errors: usize,
+}
+
+impl ReconcileReport {
+    /// Returns the number of registrations flagged for revocation.
+    pub fn flagged(&self) -> usize {
+        self.user_gone + self.disabled + self.not_authorized + self.phone_changed
+    }
+
+    fn record(&mut self, finding: Finding) {
+        match finding {
+            Finding::UserGone => self.user_gone += 1,
+            Finding::Disabled => self.disabled += 1,
+            Finding::NotAuthorized => self.not_authorized += 1,
+            Finding::PhoneChanged => self.phone_changed += 1,
+        }
+    }
+}
+
+impl fmt::Display for ReconcileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}scanned {}, kept {}, flagged {} (user gone {}, disabled {}, not authorized {}, phone changed {}), deleted {}, errors {}",
+            if self.dry_run { "[dry run] " } else { "" },
+            self.scanned,
+            self.kept,
+            self.flagged(),
+            self.user_gone,
+            self.disabled,
+            self.not_authorized,
+            self.phone_changed,
+            self.deleted,
+            self.errors,
+        )
+    }
+}
+
+/// Errors that stop a reconciliation run.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("DynamoDB error: {0}")]
+    Db(Box<DbError>),
+    #[error("Refusing to delete {} of {} registrations, more than one run may delete", .0.flagged(), .0.scanned)]
+    TooManyDeletions(ReconcileReport),
+}
+
+impl From<DbError> for Error {
+    fn from(error: DbError) -> Self {
+        Error::Db(Box::new(error))
+    }
+}
+
+/// Revokes registrations that no longer match the directory.
+pub struct Reconciler {
+    ldap_client: LdapClient,
+    dynamodb_client: Arc<DynamoDbClient>,
+    config: ReconciliationConfig,
+    /// Write-back whose numbers awaiting approval are not yet in the directory
+    write_back: Option<Arc<PhoneWriteBack>>,
+}
+
+impl Reconciler {
+    /// Creates a reconciler.
+    ///
+    /// # Arguments
+    /// * `ldap_client` - Client used to look the users up
+    /// * `dynamodb_client` - Client for the registration table
+    /// * `config` - Reconciliation settings
+    pub fn new(ldap_client: LdapClient, dynamodb_client: Arc<DynamoDbClient>, config: ReconciliationConfig) -> Self {
+        Self { ldap_client, dynamodb_client, config, write_back: None }
+    }
+
+    /// Keeps registrations whose number is waiting for write-back approval,
+    /// instead of revoking them because the directory does not hold it yet.
+    ///
+    /// # Arguments
+    /// * `write_back` - Write-back holding the pending approvals
+    pub fn with_phone_write_back(mut self, write_back: Arc<PhoneWriteBack>) -> Self {
+        self.write_back = Some(write_back);
+        self
+    }
+
+    /// Checks every registration once, then revokes the flagged ones.
+    ///
+    /// Registrations whose check fails, e.g. because the directory is
+    /// unreachable, are counted as errors and never deleted.
+    ///
+    /// # Returns
+    /// * `Result<ReconcileReport>` - Summary of the run, or an error if the table could not be
+    ///   read or more registrations were flagged than one run may delete
+    pub async fn run_once(&self) -> Result<ReconcileReport, Error> {
+        let mut report = ReconcileReport {
+            dry_run: self.config.dry_run,
+            ..Default::default()
+        };
+        info!("Starting directory reconciliation{}", if self.config.dry_run { " (dry run)" } else { "" });
+
+        let mut flagged = Vec::new();
+        let mut position = None;
+        loop {
+            let (records, next) = self
+                .dynamodb_client
+                .scan_registrations(self.config.page_size, position)
+                .await?;
+            for record in records {
+                if let Some(finding) = self.inspect(&record, &mut report).await {
+                    flagged.push((record, finding));
+                }
+            }
+            match next {
+                Some(next) => position = Some(next),
+                None => break,
+            }
+        }
+
+        if self.exceeds_deletion_limit(&report) {
+            if !self.config.dry_run {
+                error!("Directory reconciliation aborted, nothing deleted: {}", report);
+                return Err(Error::TooManyDeletions(report));
+            }
+            warn!("Flagged {} of {} registrations, more than one run may delete", report.flagged(), report.scanned);
+        }
+        for (record, finding) in &flagged {
+            self.revoke(record, *finding, &mut report).await;
+        }
+
+        info!("Directory reconciliation finished: {}", report);
+        Ok(report)
+    }
+
+    /// Checks the registrations of one user, e.g. after the directory reported a
+    /// change to the user's entry.
+    ///
+    /// # Arguments
+    /// * `username` - Canonical username
+    ///
+    /// # Returns
+    /// * `Result<ReconcileReport>` - Summary of the check, or an error if the table could not be read
+    pub async fn run_for_user(&self, username: &str) -> Result<ReconcileReport, DbError> {
+        let mut report = ReconcileReport {
+            dry_run: self.config.dry_run,
+            ..Default::default()
+        };
+        for record in self.dynamodb_client.find_registrations_by_username(username).await? {
+            if let Some(finding) = self.inspect(&record, &mut report).await {
+                self.revoke(&record, finding, &mut report).await;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Runs the reconciliation every `interval_secs` until the task is dropped.
+    pub async fn run_periodically(self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_secs.max(1)));
+        // The first tick fires immediately; the first run waits a full interval
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.run_once().await {
+                error!("Directory reconciliation failed: {}", e);
+            }
+        }
+    }
+
+    /// Returns whether a run flagged more registrations than it may delete.
+    fn exceeds_deletion_limit(&self, report: &ReconcileReport) -> bool {
+        let flagged = report.flagged();
+        let too_many = self.config.max_deletions.is_some_and(|max| flagged > max);
+        let too_large = report.scanned > 0 && flagged as f64 / report.scanned as f64 > self.config.max_deletion_fraction;
+        too_many || too_large
+    }
+
+    /// Checks one registration, counting the outcome in the report.
+    ///
+    /// # Returns
+    /// * `Option<Finding>` - Why the registration should be revoked, or `None` if it
+    ///   still matches or could not be checked
+    async fn inspect(&self, record: &RegistrationRecord, report: &mut ReconcileReport) -> Option<Finding> {
+        report.scanned += 1;
+        match self.check(record).await {
+            Ok(None) => {
+                report.kept += 1;
+                None
+            }
+            Ok(Some(finding)) => {
+                report.record(finding);
+                Some(finding)
+            }
+            Err(e) => {
+                error!("Could not check registration of {}: {}", record.username, e);
+                report.errors += 1;
+                None
+            }
+        }
+    }
+
+    /// Revokes a flagged registration, or only reports it in dry-run mode.
+    async fn revoke(&self, record: &RegistrationRecord, finding: Finding, report: &mut ReconcileReport) {
+        let phone = mask_phone_number(&record.phone_number);
+        if self.config.dry_run {
+            warn!("Would revoke registration of {} ({}): {}", record.username, phone, finding);
+            return;
+        }
+        match self.dynamodb_client.delete_registration(&record.phone_number).await {
+            Ok(()) => {
+                warn!("Revoked registration of {} ({}): {}", record.username, phone, finding);
+                report.deleted += 1;
+            }
+            Err(e) => {
+                error!("Failed to revoke registration of {} ({}): {}", record.username, phone, e);
+                report.errors += 1;
+            }
+        }
+    }
+
+    /// Compares a registration with the user's directory entry.
+    ///
+    /// # Returns
+    /// * `Result<Option<Finding>>` - Why the registration should be revoked, `None` if it
+    ///   still matches, or the error that prevented the check
+    async fn check(&self, record: &RegistrationRecord) -> Result<Option<Finding>, WriteBackError> {
+        let user = match self.ldap_client.lookup_user(&record.username).await {
+            Ok(user) => user,
+            Err(LdapError::UserNotFound(_)) => return Ok(Some(Finding::UserGone)),
+            Err(LdapError::NotAuthorized(_)) => return Ok(Some(Finding::NotAuthorized)),
+            Err(LdapError::PhoneNumberNotFound(_) | LdapError::PhoneNumberEmpty | LdapError::InvalidPhoneNumber(_)) => {
+                return self.phone_changed(record).await
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if user.account_state == Some(AccountState::Disabled) {
+            return Ok(Some(Finding::Disabled));
+        }
+        if !user.phone_numbers.contains(&record.phone_number) {
+            return self.phone_changed(record).await;
+        }
+        Ok(None)
+    }
+
+    /// Reports a registration whose number is not in the directory, unless the
+    /// number is waiting for write-back approval.
+    async fn phone_changed(&self, record: &RegistrationRecord) -> Result<Option<Finding>, WriteBackError> {
+        match &self.write_back {
+            Some(write_back) if write_back.is_pending(&record.username, &record.phone_number).await? => Ok(None),
+            _ => Ok(Some(Finding::PhoneChanged)),
+        }
+    }
+}
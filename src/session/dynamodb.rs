@@ -0,0 +1,153 @@
+//! DynamoDB session store.
+//!
+//! Sessions are kept in their own table, keyed by `session_id`, with the session
+//! serialized as JSON in `data`. `expires_at` holds the expiry in seconds since
+//! the Unix epoch; enable the table's TTL on that attribute so DynamoDB deletes
+//! abandoned sessions. TTL deletion can lag by hours, so expired sessions may
+//! still be read and callers must check `Session::is_expired`.
+//!
+//! Creates, updates and version-checked deletes are conditional writes, so a
+//! state transition is applied by exactly one of several racing replicas.
+//!
+//! With a maximum number of sessions, each replica estimates the live sessions
+//! from a count taken by every reaper run plus the sessions it created and
+//! deleted since, and recounts before refusing a session. Sessions created by
+//! other replicas are only seen at the next count, so the limit is approximate.
+//!
+//! Passwords kept for writing as the user are never serialized, so phone number
+//! write-back and changes with `bind_as: user` cannot use this store.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+use tracing::warn;
+
+use super::{epoch_secs, Error, Session, SessionStore};
+use crate::db::dynamodb::{DynamoDbClient, SessionItem};
+
+/// Keeps sessions in a DynamoDB table.
+pub struct DynamoDbSessionStore {
+    client: DynamoDbClient,
+    /// Estimated live sessions
+    live: AtomicUsize,
+    /// Most live sessions, if limited
+    max_sessions: Option<usize>,
+}
+
+impl std::fmt::Debug for DynamoDbSessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynamoDbSessionStore").finish_non_exhaustive()
+    }
+}
+
+impl DynamoDbSessionStore {
+    /// Creates a store.
+    ///
+    /// # Arguments
+    /// * `client` - Client for the sessions table
+    pub fn new(client: DynamoDbClient) -> Self {
+        Self {
+            client,
+            live: AtomicUsize::new(0),
+            max_sessions: None,
+        }
+    }
+
+    /// Limits the number of live sessions.
+    ///
+    /// # Arguments
+    /// * `max_sessions` - Most live sessions, or `None` for no limit
+    pub fn with_max_sessions(mut self, max_sessions: Option<usize>) -> Self {
+        self.max_sessions = max_sessions;
+        self
+    }
+
+    /// Builds the stored item of a session.
+    fn item(id: &str, session: &Session) -> Result<SessionItem, Error> {
+        Ok(SessionItem {
+            session_id: id.to_string(),
+            version: session.version,
+            expires_at: epoch_secs(session.expires_at),
+            data: serde_json::to_string(session)?,
+        })
+    }
+
+    /// Parses a session from its stored item, taking the version from the item.
+    fn parse(item: &SessionItem) -> Result<Session, Error> {
+        let mut session: Session = serde_json::from_str(&item.data)?;
+        session.version = item.version;
+        Ok(session)
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionStore for DynamoDbSessionStore {
+    async fn create(&self, id: &str, session: Session) -> Result<(), Error> {
+        if let Some(max) = self.max_sessions {
+            if self.live.load(Ordering::SeqCst) >= max && self.count(SystemTime::now()).await? >= max {
+                return Err(Error::Full(max));
+            }
+        }
+        if self.client.put_session(&Self::item(id, &session)?, None).await? {
+            self.live.fetch_add(1, Ordering::SeqCst);
+        } else {
+            // Session IDs are random UUIDs, so this should never happen
+            warn!("Session {} already exists, not replacing it", id);
+        }
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Session>, Error> {
+        self.client
+            .get_session(id)
+            .await?
+            .as_ref()
+            .map(Self::parse)
+            .transpose()
+    }
+
+    async fn compare_and_update(&self, id: &str, session: &mut Session) -> Result<bool, Error> {
+        let expected = session.version;
+        let mut updated = session.clone();
+        updated.version += 1;
+        if !self.client.put_session(&Self::item(id, &updated)?, Some(expected)).await? {
+            return Ok(false);
+        }
+        session.version = updated.version;
+        Ok(true)
+    }
+
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<bool, Error> {
+        let deleted = self.client.delete_session(id, version).await?;
+        if deleted {
+            let _ = self.live.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| live.checked_sub(1));
+        }
+        Ok(deleted)
+    }
+
+    async fn expire(&self, now: SystemTime) -> Result<usize, Error> {
+        let mut deleted = 0;
+        for item in self.client.scan_sessions(Some(epoch_secs(now))).await? {
+            if self.client.delete_session(&item.session_id, Some(item.version)).await? {
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    async fn list(&self) -> Result<Vec<(String, Session)>, Error> {
+        self.client
+            .scan_sessions(None)
+            .await?
+            .iter()
+            .map(|item| Ok((item.session_id.clone(), Self::parse(item)?)))
+            .collect()
+    }
+
+    async fn count(&self, now: SystemTime) -> Result<usize, Error> {
+        let live = self.client.count_sessions(epoch_secs(now)).await?;
+        self.live.store(live, Ordering::SeqCst);
+        Ok(live)
+    }
+}
@@ -0,0 +1,169 @@
+//! In-process session store.
+//!
+//! Sessions are spread over shards by a hash of their ID, each behind its own
+//! lock, so requests for different sessions rarely wait on each other. The locks
+//! are only held while a map is read or written, never across an await, and
+//! state transitions rely on the version check of `compare_and_update` rather
+//! than on holding a lock while Twilio or the directory is called.
+//!
+//! With a maximum number of sessions, a full store first deletes expired
+//! sessions and only then refuses new ones.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use super::{Error, Session, SessionStore};
+
+/// Shards used by `MemorySessionStore::new`.
+pub const DEFAULT_SHARDS: usize = 64;
+
+/// Keeps sessions in a sharded map in this process.
+#[derive(Debug)]
+pub struct MemorySessionStore {
+    shards: Box<[Mutex<HashMap<String, Session>>]>,
+    hasher: RandomState,
+    /// Sessions held, expired or not
+    len: AtomicUsize,
+    /// Most sessions held at once, if limited
+    max_sessions: Option<usize>,
+}
+
+impl Default for MemorySessionStore {
+    fn default() -> Self {
+        Self::with_shards(DEFAULT_SHARDS)
+    }
+}
+
+impl MemorySessionStore {
+    /// Creates an empty store with `DEFAULT_SHARDS` shards.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty store.
+    ///
+    /// # Arguments
+    /// * `shards` - Number of independently locked shards; at least one is used
+    pub fn with_shards(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1)).map(|_| Mutex::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+            len: AtomicUsize::new(0),
+            max_sessions: None,
+        }
+    }
+
+    /// Limits the number of sessions held at once.
+    ///
+    /// # Arguments
+    /// * `max_sessions` - Most sessions, or `None` for no limit
+    pub fn with_max_sessions(mut self, max_sessions: Option<usize>) -> Self {
+        self.max_sessions = max_sessions;
+        self
+    }
+
+    /// Counts a new session against the limit, failing if the store is full.
+    fn reserve(&self) -> bool {
+        match self.max_sessions {
+            Some(max) => self
+                .len
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |len| (len < max).then_some(len + 1))
+                .is_ok(),
+            None => {
+                self.len.fetch_add(1, Ordering::SeqCst);
+                true
+            }
+        }
+    }
+
+    /// Locks the shard holding a session ID.
+    fn shard(&self, id: &str) -> MutexGuard<'_, HashMap<String, Session>> {
+        let index = self.hasher.hash_one(id) as usize % self.shards.len();
+        Self::lock(&self.shards[index])
+    }
+
+    /// Locks a shard, recovering it if a thread panicked while holding it; every
+    /// write leaves the map consistent.
+    fn lock(shard: &Mutex<HashMap<String, Session>>) -> MutexGuard<'_, HashMap<String, Session>> {
+        shard.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn create(&self, id: &str, session: Session) -> Result<(), Error> {
+        if !self.reserve() {
+            self.expire(SystemTime::now()).await?;
+            if !self.reserve() {
+                return Err(Error::Full(self.len.load(Ordering::SeqCst)));
+            }
+        }
+        if self.shard(id).insert(id.to_string(), session).is_some() {
+            self.len.fetch_sub(1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Session>, Error> {
+        Ok(self.shard(id).get(id).cloned())
+    }
+
+    async fn compare_and_update(&self, id: &str, session: &mut Session) -> Result<bool, Error> {
+        let mut shard = self.shard(id);
+        match shard.get_mut(id) {
+            Some(stored) if stored.version == session.version => {
+                session.version += 1;
+                *stored = session.clone();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<bool, Error> {
+        let mut shard = self.shard(id);
+        match shard.get(id) {
+            Some(stored) if version.is_none_or(|version| stored.version == version) => {
+                shard.remove(id);
+                self.len.fetch_sub(1, Ordering::SeqCst);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn expire(&self, now: SystemTime) -> Result<usize, Error> {
+        let mut removed = 0;
+        for shard in self.shards.iter() {
+            let mut shard = Self::lock(shard);
+            let before = shard.len();
+            shard.retain(|_, session| !session.is_expired(now));
+            removed += before - shard.len();
+        }
+        self.len.fetch_sub(removed, Ordering::SeqCst);
+        Ok(removed)
+    }
+
+    async fn list(&self) -> Result<Vec<(String, Session)>, Error> {
+        let mut sessions = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = Self::lock(shard);
+            sessions.extend(shard.iter().map(|(id, session)| (id.clone(), session.clone())));
+        }
+        Ok(sessions)
+    }
+
+    async fn count(&self, now: SystemTime) -> Result<usize, Error> {
+        Ok(self
+            .shards
+            .iter()
+            .map(|shard| Self::lock(shard).values().filter(|session| !session.is_expired(now)).count())
+            .sum())
+    }
+}
@@ -0,0 +1,84 @@
+//! Session metrics.
+//!
+//! Counters of the session lifecycle, rendered in the Prometheus text format
+//! for the `/metrics` endpoint.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Live, expired, reaped and refused session counts.
+#[derive(Debug, Default)]
+pub struct SessionMetrics {
+    /// Live sessions at the last reaper run
+    live: AtomicU64,
+    /// Sessions found expired by a request
+    expired: AtomicU64,
+    /// Expired sessions deleted by the reaper
+    reaped: AtomicU64,
+    /// Sessions refused because the store was full
+    rejected: AtomicU64,
+}
+
+impl SessionMetrics {
+    /// Creates metrics with every count at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of live sessions.
+    pub fn set_live(&self, live: usize) {
+        self.live.store(live as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a session a request found expired.
+    pub fn record_expired(&self) {
+        self.expired.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts sessions deleted by the reaper.
+    pub fn record_reaped(&self, reaped: usize) {
+        self.reaped.fetch_add(reaped as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a session refused because the store was full.
+    pub fn record_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of live sessions at the last reaper run.
+    pub fn live(&self) -> u64 {
+        self.live.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of sessions requests found expired.
+    pub fn expired(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of sessions deleted by the reaper.
+    pub fn reaped(&self) -> u64 {
+        self.reaped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of sessions refused because the store was full.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let metrics = [
+            ("registration_sessions_live", "gauge", "Live registration sessions at the last reaper run", self.live()),
+            ("registration_sessions_expired_total", "counter", "Registration sessions found expired by a request", self.expired()),
+            ("registration_sessions_reaped_total", "counter", "Expired registration sessions deleted by the reaper", self.reaped()),
+            ("registration_sessions_rejected_total", "counter", "Registration sessions refused because the store was full", self.rejected()),
+        ];
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(text, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+        }
+        text
+    }
+}
@@ -0,0 +1,223 @@
+//! Registration session storage.
+//!
+//! A registration spans several RPCs, from StartRegistration to
+//! CompleteRegistration, and the state in between lives in a session. The
+//! `SessionStore` trait abstracts where sessions are kept: `MemorySessionStore`
+//! keeps them in this process, which loses them on restart and cannot be shared
+//! between replicas, while `DynamoDbSessionStore` keeps them in a DynamoDB table
+//! with native TTL so that any replica can continue a registration.
+//!
+//! Both stores can be capped at a number of live sessions, and `SessionReaper`
+//! deletes expired sessions in the background, keeping `SessionMetrics` up to date.
+//!
+//! Every session carries a version. Updates and deletes may be made conditional
+//! on the version the caller read, so two requests racing on one session cannot
+//! both apply a state transition.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+pub mod dynamodb;
+pub mod memory;
+pub mod metrics;
+pub mod reaper;
+
+pub use dynamodb::DynamoDbSessionStore;
+pub use memory::MemorySessionStore;
+pub use metrics::SessionMetrics;
+pub use reaper::SessionReaper;
+
+use crate::config::{SessionConfig, SessionStoreKind};
+use crate::db::dynamodb::{DynamoDbClient, Error as DbError};
+use crate::phone_change::NumberChange;
+use crate::twilio::CodeSends;
+use crate::writeback::PendingWrite;
+
+/// Errors that can occur while storing sessions
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("DynamoDB error: {0}")]
+    Db(Box<DbError>),
+    #[error("Failed to serialize session: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Session store is full ({0} live sessions)")]
+    Full(usize),
+}
+
+impl From<DbError> for Error {
+    fn from(error: DbError) -> Self {
+        Error::Db(Box::new(error))
+    }
+}
+
+/// A user registration session with associated state and timing information.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    /// Username associated with the session
+    pub username: String,
+    /// DN of the user's directory entry, stored with the registration
+    #[serde(default)]
+    pub dn: String,
+    /// Phone number being verified; empty until the user picks a candidate
+    pub phone_number: String,
+    /// Directory phone numbers the user may choose from
+    pub phone_candidates: Vec<String>,
+    /// Profile attributes fetched from LDAP, stored with the registration
+    pub profile: HashMap<String, String>,
+    /// User-supplied number to write to the directory once verified
+    pub write_back: Option<PendingWrite>,
+    /// Registration to move to `phone_number`, for a phone number change
+    pub change: Option<NumberChange>,
+    /// Whether the change was confirmed with a code sent to the old number
+    pub old_number_verified: bool,
+    /// Timestamp when the session was created
+    pub created_at: SystemTime,
+    /// Timestamp after which the session is no longer valid
+    pub expires_at: SystemTime,
+    /// Whether the session has been verified
+    pub verified: bool,
+    /// Codes sent to `phone_number`
+    #[serde(default)]
+    pub sends: CodeSends,
+    /// Codes checked for `phone_number`
+    #[serde(default)]
+    pub check_attempts: u32,
+    /// Codes checked for the old number of a phone number change
+    #[serde(default)]
+    pub old_number_check_attempts: u32,
+    /// Codes sent to the old number of a phone number change
+    #[serde(default)]
+    pub old_number_sends: CodeSends,
+    /// Version of the stored session, incremented by every update
+    #[serde(default)]
+    pub version: u64,
+}
+
+impl Session {
+    /// Creates an unverified session.
+    ///
+    /// # Arguments
+    /// * `username` - Canonical username
+    /// * `phone_number` - Phone number being verified, or empty until the user picks a candidate
+    /// * `timeout` - How long the session stays valid
+    pub fn new(username: &str, phone_number: String, timeout: Duration) -> Self {
+        let created_at = SystemTime::now();
+        Self {
+            username: username.to_string(),
+            dn: String::new(),
+            phone_number,
+            phone_candidates: Vec::new(),
+            profile: HashMap::new(),
+            write_back: None,
+            change: None,
+            old_number_verified: false,
+            created_at,
+            expires_at: created_at + timeout,
+            verified: false,
+            sends: CodeSends::default(),
+            check_attempts: 0,
+            old_number_check_attempts: 0,
+            old_number_sends: CodeSends::default(),
+            version: 0,
+        }
+    }
+
+    /// Returns whether the session has expired.
+    ///
+    /// # Arguments
+    /// * `now` - The current time
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        now > self.expires_at
+    }
+}
+
+/// Storage of registration sessions, keyed by session ID.
+#[async_trait::async_trait]
+pub trait SessionStore: std::fmt::Debug + Send + Sync {
+    /// Stores a new session under an unused ID, failing with `Error::Full` when
+    /// the store holds its maximum number of live sessions.
+    ///
+    /// # Arguments
+    /// * `id` - Session ID
+    /// * `session` - The session, with version 0
+    async fn create(&self, id: &str, session: Session) -> Result<(), Error>;
+
+    /// Retrieves a session, expired or not.
+    ///
+    /// # Arguments
+    /// * `id` - Session ID
+    ///
+    /// # Returns
+    /// * `Result<Option<Session>>` - The session if found
+    async fn get(&self, id: &str) -> Result<Option<Session>, Error>;
+
+    /// Replaces a session if it has not changed since it was read. On success
+    /// the session's version is incremented to match the stored one.
+    ///
+    /// # Arguments
+    /// * `id` - Session ID
+    /// * `session` - The modified session, with the version it was read with
+    ///
+    /// # Returns
+    /// * `Result<bool>` - Whether the session was replaced; `false` if it changed
+    ///   or was deleted in the meantime
+    async fn compare_and_update(&self, id: &str, session: &mut Session) -> Result<bool, Error>;
+
+    /// Deletes a session.
+    ///
+    /// # Arguments
+    /// * `id` - Session ID
+    /// * `version` - Version the session must still have, or `None` to delete it regardless
+    ///
+    /// # Returns
+    /// * `Result<bool>` - Whether a session was deleted
+    async fn delete(&self, id: &str, version: Option<u64>) -> Result<bool, Error>;
+
+    /// Deletes the sessions that expired before a time.
+    ///
+    /// # Arguments
+    /// * `now` - The current time
+    ///
+    /// # Returns
+    /// * `Result<usize>` - Number of sessions deleted
+    async fn expire(&self, now: SystemTime) -> Result<usize, Error>;
+
+    /// Retrieves every session with its ID.
+    async fn list(&self) -> Result<Vec<(String, Session)>, Error>;
+
+    /// Counts the sessions that have not expired.
+    ///
+    /// # Arguments
+    /// * `now` - The current time
+    async fn count(&self, now: SystemTime) -> Result<usize, Error>;
+}
+
+/// Returns a time in whole seconds since the Unix epoch, rounded up.
+pub(crate) fn epoch_secs(time: SystemTime) -> u64 {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    elapsed.as_secs() + u64::from(elapsed.subsec_nanos() > 0)
+}
+
+/// Creates the configured session store.
+///
+/// # Arguments
+/// * `config` - Session storage configuration
+/// * `region` - AWS region of the DynamoDB table
+///
+/// # Returns
+/// * `Result<Arc<dyn SessionStore>>` - The store or error if the DynamoDB client fails to start
+pub async fn from_config(config: &SessionConfig, region: &str) -> Result<Arc<dyn SessionStore>, Error> {
+    let max_sessions = (config.max_sessions > 0).then_some(config.max_sessions);
+    Ok(match config.store {
+        SessionStoreKind::Memory => Arc::new(MemorySessionStore::new().with_max_sessions(max_sessions)),
+        SessionStoreKind::Dynamodb => {
+            let client = DynamoDbClient::new(config.table_name.clone(), region.to_string()).await?;
+            Arc::new(DynamoDbSessionStore::new(client).with_max_sessions(max_sessions))
+        }
+    })
+}
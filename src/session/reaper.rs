@@ -0,0 +1,72 @@
+//! Background deletion of expired sessions.
+//!
+//! Abandoned sessions would otherwise stay in the store until a request for
+//! them happened to notice they had expired. The reaper deletes them on an
+//! interval and refreshes the live session count. Each run is a task of its
+//! own, so a run that panics is logged and the next run goes ahead.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, error, info};
+
+use super::{Error, SessionMetrics, SessionStore};
+
+/// Deletes expired sessions periodically.
+#[derive(Debug, Clone)]
+pub struct SessionReaper {
+    sessions: Arc<dyn SessionStore>,
+    metrics: Arc<SessionMetrics>,
+    interval: Duration,
+}
+
+impl SessionReaper {
+    /// Creates a reaper.
+    ///
+    /// # Arguments
+    /// * `sessions` - Store to delete expired sessions from
+    /// * `metrics` - Metrics updated by every run
+    /// * `interval` - Time between runs
+    pub fn new(sessions: Arc<dyn SessionStore>, metrics: Arc<SessionMetrics>, interval: Duration) -> Self {
+        Self {
+            sessions,
+            metrics,
+            interval: interval.max(Duration::from_secs(1)),
+        }
+    }
+
+    /// Deletes the expired sessions once and refreshes the live session count.
+    ///
+    /// # Returns
+    /// * `Result<usize>` - Number of sessions deleted
+    pub async fn reap_once(&self) -> Result<usize, Error> {
+        let now = SystemTime::now();
+        let reaped = self.sessions.expire(now).await?;
+        self.metrics.record_reaped(reaped);
+        self.metrics.set_live(self.sessions.count(now).await?);
+        if reaped > 0 {
+            info!("Reaped {} expired session(s), {} live", reaped, self.metrics.live());
+        } else {
+            debug!("No expired sessions, {} live", self.metrics.live());
+        }
+        Ok(reaped)
+    }
+
+    /// Runs until the task is dropped, starting a run every interval.
+    pub async fn run(self) {
+        info!("Reaping expired sessions every {:?}", self.interval);
+        let mut interval = tokio::time::interval(self.interval);
+        // The first tick fires immediately; the first run waits a full interval
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let reaper = self.clone();
+            match tokio::spawn(async move { reaper.reap_once().await }).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!("Session reaping failed: {}", e),
+                Err(e) => error!("Session reaper run panicked, continuing: {}", e),
+            }
+        }
+    }
+}
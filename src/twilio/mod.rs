@@ -14,7 +14,9 @@ use tracing::{error, info};
 use serde::Deserialize;
 
 pub mod rate_limit;
+pub mod resend;
 pub use rate_limit::RateLimiter;
+pub use resend::{CodeSends, ResendPolicy};
 
 /// Configuration for Twilio API connection.
 #[derive(Debug, Clone)]
@@ -40,15 +42,45 @@ pub enum VerificationChannel {
     Voice,
 }
 
-impl ToString for VerificationChannel {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for VerificationChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Sms => "sms".to_string(),
-            Self::Voice => "voice".to_string(),
+            Self::Sms => write!(f, "sms"),
+            Self::Voice => write!(f, "voice"),
         }
     }
 }
 
+impl std::str::FromStr for VerificationChannel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sms" => Ok(Self::Sms),
+            "voice" => Ok(Self::Voice),
+            _ => anyhow::bail!("Invalid channel. Must be 'sms' or 'voice'"),
+        }
+    }
+}
+
+/// Result of checking a verification code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckOutcome {
+    /// The code is correct
+    Approved,
+    /// The code is wrong; the verification remains pending
+    Rejected,
+    /// Twilio no longer accepts codes for the number: its check attempts ran out
+    /// (error 60202) or the verification expired or was already approved (error 20404)
+    Exhausted,
+}
+
+/// Twilio error code for a verification whose check attempts ran out.
+const MAX_CHECK_ATTEMPTS_REACHED: u32 = 60202;
+
+/// Twilio error code for a resource that does not exist.
+const RESOURCE_NOT_FOUND: u32 = 20404;
+
 /// Client for Twilio Verify API operations.
 ///
 /// Provides methods for sending verification codes and checking responses
@@ -62,8 +94,13 @@ pub struct TwilioClient {
     http_client: HttpClient,
     test_mode: bool,
     test_ldap_phone: Option<String>,
+    /// Base URL of the Verify API
+    api_base: String,
 }
 
+/// Base URL of Twilio's Verify API.
+const VERIFY_API_BASE: &str = "https://verify.twilio.com";
+
 impl TwilioClient {
     /// Creates a new Twilio client instance.
     ///
@@ -84,9 +121,19 @@ impl TwilioClient {
             http_client,
             test_mode: config.test_mode,
             test_ldap_phone: None,
+            api_base: VERIFY_API_BASE.to_string(),
         })
     }
 
+    /// Sends Verify API requests to another base URL, e.g. a local stand-in for tests.
+    ///
+    /// # Arguments
+    /// * `api_base` - Base URL without a trailing slash, e.g. "http://127.0.0.1:8080"
+    pub fn with_api_base(mut self, api_base: &str) -> Self {
+        self.api_base = api_base.trim_end_matches('/').to_string();
+        self
+    }
+
     /// Sends a verification code to a phone number.
     ///
     /// # Arguments
@@ -106,8 +153,8 @@ impl TwilioClient {
         }
 
         let url = format!(
-            "https://verify.twilio.com/v2/Services/{}/Verifications",
-            self.verification_service_sid
+            "{}/v2/Services/{}/Verifications",
+            self.api_base, self.verification_service_sid
         );
         
         let params = [
@@ -141,6 +188,19 @@ impl TwilioClient {
     /// # Returns
     /// * `Result<bool>` - True if code is valid
     pub async fn verify_code(&self, phone_number: &str, code: &str) -> Result<bool> {
+        Ok(self.check_code(phone_number, code).await? == CheckOutcome::Approved)
+    }
+
+    /// Checks a code submitted by a user, telling wrong codes apart from
+    /// verifications that no longer accept codes.
+    ///
+    /// # Arguments
+    /// * `phone_number` - Phone number being verified
+    /// * `code` - Verification code submitted by user
+    ///
+    /// # Returns
+    /// * `Result<CheckOutcome>` - Outcome of the check, or error if Twilio fails
+    pub async fn check_code(&self, phone_number: &str, code: &str) -> Result<CheckOutcome> {
         if self.test_mode {
             let ldap_phone = self.test_ldap_phone.as_ref()
                 .ok_or_else(|| anyhow::anyhow!("Test mode requires LDAP phone number to be set"))?;
@@ -160,12 +220,12 @@ impl TwilioClient {
             
             info!("Test mode: Comparing code {} with expected {} (from LDAP phone: {})", 
                   code, expected_code, ldap_phone);
-            return Ok(code == expected_code);
+            return Ok(if code == expected_code { CheckOutcome::Approved } else { CheckOutcome::Rejected });
         }
 
         let url = format!(
-            "https://verify.twilio.com/v2/Services/{}/VerificationCheck",
-            self.verification_service_sid
+            "{}/v2/Services/{}/VerificationCheck",
+            self.api_base, self.verification_service_sid
         );
         
         let params = [
@@ -181,9 +241,22 @@ impl TwilioClient {
             .await?;
             
         if !response.status().is_success() {
+            #[derive(Deserialize)]
+            struct ErrorResponse {
+                code: u32,
+            }
+            
             let error_text = response.text().await?;
-            error!("Twilio verification check failed: {}", error_text);
-            anyhow::bail!("Failed to verify code: {}", error_text);
+            match serde_json::from_str::<ErrorResponse>(&error_text) {
+                Ok(e) if e.code == MAX_CHECK_ATTEMPTS_REACHED || e.code == RESOURCE_NOT_FOUND => {
+                    info!("Twilio no longer accepts codes for {} (error {})", phone_number, e.code);
+                    return Ok(CheckOutcome::Exhausted);
+                }
+                _ => {
+                    error!("Twilio verification check failed: {}", error_text);
+                    anyhow::bail!("Failed to verify code: {}", error_text);
+                }
+            }
         }
         
         #[derive(Deserialize)]
@@ -192,7 +265,7 @@ impl TwilioClient {
         }
         
         let check: VerificationCheck = response.json().await?;
-        Ok(check.status == "approved")
+        Ok(if check.status == "approved" { CheckOutcome::Approved } else { CheckOutcome::Rejected })
     }
 
     /// Stores a phone number for test mode verification.
@@ -1,21 +1,21 @@
-/// Rate Limiting Module for Twilio Verification
-///
-/// Implements rate limiting for Twilio verification requests to prevent abuse.
-/// Uses a combination of fixed window and leaky bucket algorithms for different
-/// verification channels.
-///
-/// # Features
-/// - Channel-specific rate limits
-/// - Configurable time windows
-/// - Leaky bucket implementation
-/// - Separate limits for SMS and voice
-///
-/// # Copyright
-/// Copyright (c) 2025 Signal Messenger, LLC
-/// All rights reserved.
-///
-/// # License
-/// Licensed under the AGPLv3 license.
+//! Rate Limiting Module for Twilio Verification
+//!
+//! Implements rate limiting for Twilio verification requests to prevent abuse.
+//! Uses a combination of fixed window and leaky bucket algorithms for different
+//! verification channels.
+//!
+//! # Features
+//! - Channel-specific rate limits
+//! - Configurable time windows
+//! - Leaky bucket implementation
+//! - Separate limits for SMS and voice
+//!
+//! # Copyright
+//! Copyright (c) 2025 Signal Messenger, LLC
+//! All rights reserved.
+//!
+//! # License
+//! Licensed under the AGPLv3 license.
 
 use std::time::SystemTime;
 use std::collections::HashMap;
@@ -61,7 +61,7 @@ impl RateLimiter {
     ///
     /// # Examples
     /// ```
-    /// use registration_service::twilio::rate_limit::{RateLimiter, RateLimitConfig};
+    /// use rust_ldap_registration::twilio::rate_limit::{RateLimiter, RateLimitConfig};
     ///
     /// let config = RateLimitConfig {
     ///     max_attempts: 3,
@@ -87,14 +87,14 @@ impl RateLimiter {
     ///
     /// # Examples
     /// ```no_run
-    /// # use registration_service::twilio::rate_limit::RateLimiter;
-    /// # let rate_limiter = get_rate_limiter();
+    /// # use rust_ldap_registration::twilio::rate_limit::RateLimiter;
+    /// # async fn example(rate_limiter: RateLimiter) {
     /// if rate_limiter.check_rate_limit("+1234567890").await {
     ///     println!("Attempt allowed");
     /// } else {
     ///     println!("Rate limited");
     /// }
-    /// # async fn get_rate_limiter() -> RateLimiter { unimplemented!() }
+    /// # }
     /// ```
     pub async fn check_rate_limit(&self, key: &str) -> bool {
         let mut attempts = self.attempts.lock().await;
@@ -142,10 +142,10 @@ impl RateLimiter {
     ///
     /// # Examples
     /// ```no_run
-    /// # use registration_service::twilio::rate_limit::RateLimiter;
-    /// # let rate_limiter = get_rate_limiter();
+    /// # use rust_ldap_registration::twilio::rate_limit::RateLimiter;
+    /// # async fn example(rate_limiter: RateLimiter) {
     /// rate_limiter.reset_rate_limit("+1234567890").await;
-    /// # async fn get_rate_limiter() -> RateLimiter { unimplemented!() }
+    /// # }
     /// ```
     pub async fn reset_rate_limit(&self, key: &str) {
         let mut attempts = self.attempts.lock().await;
@@ -0,0 +1,110 @@
+//! Resend cooldowns for verification codes.
+//!
+//! A session records when codes were sent to its number. `ResendPolicy` decides
+//! from that record when the next code may go out on each channel: SMS codes
+//! wait `send_sms_verification_code.delays` after the last SMS, and voice calls
+//! wait `send_voice_verification_code.delays` after the last call and
+//! `delay_after_first_sms` after the first SMS, up to `max_attempts` calls.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+use super::VerificationChannel;
+use crate::config::RateLimits;
+
+/// Codes sent to a session's phone number.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CodeSends {
+    /// When the first SMS code was sent
+    pub first_sms: Option<SystemTime>,
+    /// When the last SMS code was sent
+    pub last_sms: Option<SystemTime>,
+    /// When the last voice call was made
+    pub last_voice: Option<SystemTime>,
+    /// Voice calls made
+    pub voice_calls: u32,
+}
+
+impl CodeSends {
+    /// Records a code sent on a channel.
+    ///
+    /// # Arguments
+    /// * `channel` - Channel the code was sent on
+    /// * `at` - When it was sent
+    pub fn record(&mut self, channel: VerificationChannel, at: SystemTime) {
+        match channel {
+            VerificationChannel::Sms => {
+                self.first_sms.get_or_insert(at);
+                self.last_sms = Some(at);
+            }
+            VerificationChannel::Voice => {
+                self.last_voice = Some(at);
+                self.voice_calls += 1;
+            }
+        }
+    }
+}
+
+/// When codes may be sent again.
+#[derive(Debug, Clone)]
+pub struct ResendPolicy {
+    /// Time between SMS codes
+    pub sms_delay: Duration,
+    /// Time between voice calls
+    pub voice_delay: Duration,
+    /// Most voice calls per session
+    pub max_voice_calls: u32,
+    /// Time after the first SMS code before a voice call may be made
+    pub voice_delay_after_first_sms: Duration,
+}
+
+impl Default for ResendPolicy {
+    /// No cooldowns and no limit on voice calls.
+    fn default() -> Self {
+        Self {
+            sms_delay: Duration::ZERO,
+            voice_delay: Duration::ZERO,
+            max_voice_calls: u32::MAX,
+            voice_delay_after_first_sms: Duration::ZERO,
+        }
+    }
+}
+
+impl ResendPolicy {
+    /// Returns when a code may next be sent on a channel.
+    ///
+    /// # Arguments
+    /// * `sends` - Codes already sent to the number
+    /// * `channel` - Channel to send on
+    ///
+    /// # Returns
+    /// * `Option<SystemTime>` - Earliest time of the next send, possibly in the
+    ///   past, or `None` if no more codes may be sent on the channel
+    pub fn next_send(&self, sends: &CodeSends, channel: VerificationChannel) -> Option<SystemTime> {
+        match channel {
+            VerificationChannel::Sms => Some(sends.last_sms.map_or(SystemTime::UNIX_EPOCH, |last| last + self.sms_delay)),
+            VerificationChannel::Voice => {
+                if sends.voice_calls >= self.max_voice_calls {
+                    return None;
+                }
+                let after_voice = sends.last_voice.map(|last| last + self.voice_delay);
+                let after_sms = sends.first_sms.map(|first| first + self.voice_delay_after_first_sms);
+                Some(after_voice.max(after_sms).unwrap_or(SystemTime::UNIX_EPOCH))
+            }
+        }
+    }
+}
+
+impl From<&RateLimits> for ResendPolicy {
+    fn from(rate_limits: &RateLimits) -> Self {
+        let voice = &rate_limits.send_voice_verification_code;
+        Self {
+            sms_delay: Duration::from_secs(rate_limits.send_sms_verification_code.delays),
+            voice_delay: Duration::from_secs(voice.delays),
+            max_voice_calls: voice.max_attempts,
+            voice_delay_after_first_sms: Duration::from_secs(voice.delay_after_first_sms),
+        }
+    }
+}
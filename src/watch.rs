@@ -0,0 +1,219 @@
+//! Directory change watcher.
+//!
+//! The reconciler catches stale registrations eventually; this module catches
+//! them as they happen. It follows the directory's change feed (syncrepl or
+//! DirSync, see [`crate::auth::sync`]) and, for every changed user, looks the user
+//! up again. Sessions in progress are dropped when the user is gone, disabled or
+//! no longer authorized, or when the number being verified is no longer on the
+//! entry, and the user's stored registrations go through the same checks as in a
+//! reconciliation run. The feed's resume cookies are written to `cookie_file`
+//! after every batch, so a restart picks up where the watcher stopped.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+use crate::auth::ldap::{Error as LdapError, LdapClient};
+use crate::auth::policy::AccountState;
+use crate::auth::sync::{ChangeFeed, DirectoryChange, SyncEvent};
+use crate::config::WatchConfig;
+use crate::grpc::SessionHandle;
+use crate::reconcile::Reconciler;
+
+/// LDAP result code telling a syncrepl client that its cookie is no longer
+/// usable (e-syncRefreshRequired).
+const SYNC_REFRESH_REQUIRED: u32 = 4096;
+
+/// Events buffered between the change feed and the watcher.
+const EVENT_BUFFER: usize = 256;
+
+/// Follows directory changes and revokes sessions and registrations they invalidate.
+pub struct DirectoryWatcher {
+    ldap_client: LdapClient,
+    feed: ChangeFeed,
+    sessions: SessionHandle,
+    reconciler: Reconciler,
+    /// File the resume cookies are persisted to
+    cookie_file: PathBuf,
+    /// Time to wait before reconnecting after an error
+    reconnect_delay: Duration,
+}
+
+impl DirectoryWatcher {
+    /// Creates a watcher.
+    ///
+    /// # Arguments
+    /// * `ldap_client` - Client used to follow changes and look users up
+    /// * `sessions` - Handle to the registration server's sessions
+    /// * `reconciler` - Reconciler that checks and revokes registrations
+    /// * `config` - Change tracking settings
+    pub fn new(ldap_client: LdapClient, sessions: SessionHandle, reconciler: Reconciler, config: &WatchConfig) -> Self {
+        Self {
+            feed: ldap_client.change_feed(config),
+            ldap_client,
+            sessions,
+            reconciler,
+            cookie_file: PathBuf::from(&config.cookie_file),
+            reconnect_delay: Duration::from_secs(config.reconnect_delay_secs.max(1)),
+        }
+    }
+
+    /// Follows the change feed until the task is dropped, reconnecting after errors.
+    pub async fn run(self) {
+        info!("Watching the directory for changes with {:?}", self.feed.mode());
+        let mut cookies = load_cookies(&self.cookie_file).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable sync cookie file {}: {}", self.cookie_file.display(), e);
+            HashMap::new()
+        });
+
+        loop {
+            let (events, mut received) = mpsc::channel(EVENT_BUFFER);
+            let feed = self.feed.clone();
+            let start = cookies.clone();
+            // The feed runs in its own task so a malformed control cannot take
+            // the watcher down with it
+            let task = tokio::spawn(async move { feed.run(&start, events).await });
+
+            while let Some(event) = received.recv().await {
+                match event {
+                    SyncEvent::Changed(change) => self.handle_change(change).await,
+                    SyncEvent::Resync => self.resync().await,
+                    SyncEvent::Cookie { base, cookie } => {
+                        cookies.insert(base, cookie);
+                        if let Err(e) = save_cookies(&self.cookie_file, &cookies) {
+                            error!("Failed to persist sync cookies to {}: {}", self.cookie_file.display(), e);
+                        }
+                    }
+                }
+            }
+
+            match task.await {
+                Ok(Err(LdapError::Ldap(ldap3::LdapError::LdapResult { result }))) if result.rc == SYNC_REFRESH_REQUIRED => {
+                    warn!("Directory rejected the sync cookies, starting over");
+                    cookies.clear();
+                    if let Err(e) = save_cookies(&self.cookie_file, &cookies) {
+                        error!("Failed to persist sync cookies to {}: {}", self.cookie_file.display(), e);
+                    }
+                    self.resync().await;
+                    continue;
+                }
+                Ok(Err(e)) => warn!("Directory change feed stopped: {}", e),
+                Ok(Ok(())) => debug!("Directory change feed ended"),
+                Err(e) => error!("Directory change feed failed: {}", e),
+            }
+            tokio::time::sleep(self.reconnect_delay).await;
+        }
+    }
+
+    /// Re-checks the user behind one change.
+    async fn handle_change(&self, change: DirectoryChange) {
+        let username = match change.username {
+            Some(username) => username,
+            // A deleted entry can no longer be read
+            None if change.deleted => {
+                debug!("Deleted entry {} carries no username", change.dn);
+                return self.resync().await;
+            }
+            None => match self.ldap_client.username_for_dn(&change.dn).await {
+                Ok(Some(username)) => username,
+                Ok(None) => {
+                    debug!("Changed entry {} has no username, ignoring", change.dn);
+                    return;
+                }
+                Err(e) => {
+                    error!("Could not read the username of {}: {}", change.dn, e);
+                    return;
+                }
+            },
+        };
+        debug!("Directory change for {} ({})", username, change.dn);
+        self.check_user(&username).await;
+    }
+
+    /// Looks a user up and drops the sessions and registrations that no longer match.
+    async fn check_user(&self, username: &str) {
+        let phone_numbers = match self.ldap_client.lookup_user(username).await {
+            Ok(user) if user.account_state == Some(AccountState::Disabled) => None,
+            Ok(user) => Some(user.phone_numbers),
+            // Sessions verifying a number to write back stay valid
+            Err(LdapError::PhoneNumberNotFound(_) | LdapError::PhoneNumberEmpty | LdapError::InvalidPhoneNumber(_)) => {
+                Some(Vec::new())
+            }
+            Err(LdapError::UserNotFound(_) | LdapError::NotAuthorized(_)) => None,
+            Err(e) => {
+                error!("Could not check {} after a directory change: {}", username, e);
+                return;
+            }
+        };
+
+        match self.sessions.invalidate_user(username, phone_numbers.as_deref()).await {
+            Ok(0) => {}
+            Ok(removed) => warn!("Invalidated {} session(s) of {} after a directory change", removed, username),
+            Err(e) => error!("Could not invalidate the sessions of {}: {}", username, e),
+        }
+        match self.reconciler.run_for_user(username).await {
+            Ok(report) if report.flagged() > 0 => info!("Registrations of {} after a directory change: {}", username, report),
+            Ok(_) => {}
+            Err(e) => error!("Could not check the registrations of {}: {}", username, e),
+        }
+    }
+
+    /// Checks every user with a session and every registration, after changes
+    /// were missed.
+    async fn resync(&self) {
+        info!("Re-checking all sessions and registrations");
+        match self.sessions.usernames().await {
+            Ok(usernames) => {
+                for username in usernames {
+                    self.check_user(&username).await;
+                }
+            }
+            Err(e) => error!("Could not list sessions: {}", e),
+        }
+        if let Err(e) = self.reconciler.run_once().await {
+            error!("Directory reconciliation failed: {}", e);
+        }
+    }
+}
+
+/// Reads the persisted cookies; a missing file means no cookies yet.
+fn load_cookies(path: &Path) -> Result<HashMap<String, Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let encoded: HashMap<String, String> = serde_json::from_str(&contents)?;
+    encoded
+        .into_iter()
+        .map(|(base, cookie)| Ok((base, decode_hex(&cookie).ok_or("invalid cookie encoding")?)))
+        .collect()
+}
+
+/// Writes the cookies to a temporary file and renames it over the old one, so a
+/// crash never leaves a truncated file behind.
+fn save_cookies(path: &Path, cookies: &HashMap<String, Vec<u8>>) -> std::io::Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let encoded: HashMap<&String, String> = cookies.iter().map(|(base, cookie)| (base, encode_hex(cookie))).collect();
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, serde_json::to_vec_pretty(&encoded)?)?;
+    std::fs::rename(&temporary, path)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    // An odd trailing digit fails the slice
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
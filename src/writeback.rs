@@ -0,0 +1,274 @@
+//! Phone number write-back.
+//!
+//! Users whose directory entry holds no phone number cannot register: there is
+//! nothing to send a code to. With write-back enabled, StartRegistration accepts
+//! a number from such a user instead, and once Twilio has verified it the number
+//! is written to the user's entry, bound either as the service account or as the
+//! user. When approval is required, the verified number waits in a DynamoDB table
+//! until an operator approves or rejects it with the `approve-phone` or
+//! `reject-phone` subcommand; approved numbers are written by the service account.
+//!
+//! Every request, write, approval and rejection is logged under the `audit`
+//! tracing target.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::{error, info};
+
+pub use crate::config::WriteBackBind;
+
+use crate::auth::ldap::{AuthenticatedUser, Error as LdapError, LdapClient};
+use crate::auth::phone::mask_phone_number;
+use crate::config::PhoneWriteBackConfig;
+use crate::db::dynamodb::{DynamoDbClient, Error as DbError, PhoneApproval};
+
+/// Errors that can occur while writing a phone number back
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("LDAP error: {0}")]
+    Ldap(#[from] LdapError),
+    #[error("DynamoDB error: {0}")]
+    Db(Box<DbError>),
+    #[error("No approval pending for {0}")]
+    NotPending(String),
+    #[error("Approval is not enabled")]
+    ApprovalDisabled,
+}
+
+impl From<DbError> for Error {
+    fn from(error: DbError) -> Self {
+        Error::Db(Box::new(error))
+    }
+}
+
+/// A user-supplied number waiting for verification, kept with the session.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PendingWrite {
+    /// Canonical username
+    pub username: String,
+    /// DN of the user's directory entry
+    pub dn: String,
+    /// Number to write, in E.164 format
+    pub phone_number: String,
+    /// Bind name and password of the user, kept only when writing as the user;
+    /// never serialized, so a stored session cannot leak the password
+    #[serde(skip)]
+    credentials: Option<(String, String)>,
+}
+
+impl fmt::Debug for PendingWrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingWrite")
+            .field("username", &self.username)
+            .field("dn", &self.dn)
+            .field("phone_number", &mask_phone_number(&self.phone_number))
+            .field("credentials", &self.credentials.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+/// What happened to a verified number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteBackOutcome {
+    /// The number was written to the directory
+    Written,
+    /// The number is waiting for an operator's approval
+    AwaitingApproval,
+}
+
+/// Writes verified, user-supplied phone numbers to the directory.
+pub struct PhoneWriteBack {
+    ldap_client: LdapClient,
+    /// Attribute the number is written to
+    attribute: String,
+    /// Whose bind writes numbers that need no approval
+    bind_as: WriteBackBind,
+    /// Table of numbers waiting for approval, when approval is required
+    approvals: Option<Arc<DynamoDbClient>>,
+}
+
+impl PhoneWriteBack {
+    /// Creates a write-back.
+    ///
+    /// # Arguments
+    /// * `ldap_client` - Client used to write the numbers
+    /// * `config` - Write-back settings
+    /// * `approvals` - Client for the approval table; required when `require_approval` is set
+    pub fn new(ldap_client: LdapClient, config: &PhoneWriteBackConfig, approvals: Option<DynamoDbClient>) -> Self {
+        let attribute = config
+            .attribute
+            .clone()
+            .unwrap_or_else(|| ldap_client.phone_number_attribute().to_string());
+        Self {
+            ldap_client,
+            attribute,
+            bind_as: config.bind_as,
+            approvals: approvals.filter(|_| config.require_approval).map(Arc::new),
+        }
+    }
+
+    /// Records a user-supplied number that is about to be verified.
+    ///
+    /// # Arguments
+    /// * `user` - The authenticated user, whose entry has no phone number
+    /// * `phone_number` - Number supplied by the user, in E.164 format
+    /// * `password` - The user's password, kept only when writing as the user
+    ///
+    /// # Returns
+    /// * `PendingWrite` - The write to submit once the number is verified
+    pub fn prepare(&self, user: &AuthenticatedUser, phone_number: &str, password: &str) -> PendingWrite {
+        info!(
+            target: "audit",
+            "Phone write-back requested: user={} dn={} attribute={} phone={}",
+            user.username, user.dn, self.attribute, mask_phone_number(phone_number)
+        );
+        let writes_as_user = self.bind_as == WriteBackBind::User && self.approvals.is_none();
+        PendingWrite {
+            username: user.username.clone(),
+            dn: user.dn.clone(),
+            phone_number: phone_number.to_string(),
+            credentials: writes_as_user.then(|| (user.bind_name.clone(), password.to_string())),
+        }
+    }
+
+    /// Writes a verified number, or queues it for approval.
+    ///
+    /// # Arguments
+    /// * `write` - The write prepared when the session started
+    ///
+    /// # Returns
+    /// * `Result<WriteBackOutcome>` - Whether the number was written or queued
+    pub async fn submit(&self, write: PendingWrite) -> Result<WriteBackOutcome, Error> {
+        let phone = mask_phone_number(&write.phone_number);
+        if let Some(approvals) = &self.approvals {
+            let approval = PhoneApproval {
+                phone_number: write.phone_number.clone(),
+                username: write.username.clone(),
+                dn: write.dn.clone(),
+                attribute: self.attribute.clone(),
+                requested_at: chrono::Utc::now().to_rfc3339(),
+            };
+            approvals.save_phone_approval(&approval).await.inspect_err(|e| {
+                error!(target: "audit", "Phone write-back not queued: user={} phone={} error={}", write.username, phone, e);
+            })?;
+            info!(target: "audit", "Phone write-back awaiting approval: user={} dn={} phone={}", write.username, write.dn, phone);
+            return Ok(WriteBackOutcome::AwaitingApproval);
+        }
+
+        let bind = if write.credentials.is_some() { "user" } else { "service" };
+        let credentials = write.credentials.as_ref().map(|(name, password)| (name.as_str(), password.as_str()));
+        match self
+            .ldap_client
+            .write_phone_number(&write.dn, &self.attribute, &write.phone_number, credentials)
+            .await
+        {
+            Ok(()) => {
+                info!(
+                    target: "audit",
+                    "Phone write-back written: user={} dn={} attribute={} phone={} bind={}",
+                    write.username, write.dn, self.attribute, phone, bind
+                );
+                Ok(WriteBackOutcome::Written)
+            }
+            Err(e) => {
+                error!(
+                    target: "audit",
+                    "Phone write-back failed: user={} dn={} attribute={} phone={} bind={} error={}",
+                    write.username, write.dn, self.attribute, phone, bind, e
+                );
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Returns the numbers waiting for approval.
+    ///
+    /// # Returns
+    /// * `Result<Vec<PhoneApproval>>` - The pending approvals, oldest first
+    pub async fn pending(&self) -> Result<Vec<PhoneApproval>, Error> {
+        let approvals = self.approvals.as_ref().ok_or(Error::ApprovalDisabled)?;
+        Ok(approvals.list_phone_approvals().await?)
+    }
+
+    /// Approves a pending number and writes it as the service account.
+    ///
+    /// # Arguments
+    /// * `phone_number` - The pending number, in E.164 format
+    /// * `approver` - Who approved it, for the audit log
+    ///
+    /// # Returns
+    /// * `Result<PhoneApproval>` - The approval that was applied, or `NotPending`
+    pub async fn approve(&self, phone_number: &str, approver: &str) -> Result<PhoneApproval, Error> {
+        let approvals = self.approvals.as_ref().ok_or(Error::ApprovalDisabled)?;
+        let approval = approvals
+            .get_phone_approval(phone_number)
+            .await?
+            .ok_or_else(|| Error::NotPending(mask_phone_number(phone_number)))?;
+        let phone = mask_phone_number(&approval.phone_number);
+
+        if let Err(e) = self
+            .ldap_client
+            .write_phone_number(&approval.dn, &approval.attribute, &approval.phone_number, None)
+            .await
+        {
+            error!(
+                target: "audit",
+                "Approved phone write-back failed: user={} dn={} phone={} approver={} error={}",
+                approval.username, approval.dn, phone, approver, e
+            );
+            return Err(e.into());
+        }
+        approvals.delete_phone_approval(&approval.phone_number).await?;
+        info!(
+            target: "audit",
+            "Phone write-back approved and written: user={} dn={} attribute={} phone={} approver={}",
+            approval.username, approval.dn, approval.attribute, phone, approver
+        );
+        Ok(approval)
+    }
+
+    /// Rejects a pending number without writing it.
+    ///
+    /// # Arguments
+    /// * `phone_number` - The pending number, in E.164 format
+    /// * `approver` - Who rejected it, for the audit log
+    ///
+    /// # Returns
+    /// * `Result<PhoneApproval>` - The approval that was rejected, or `NotPending`
+    pub async fn reject(&self, phone_number: &str, approver: &str) -> Result<PhoneApproval, Error> {
+        let approvals = self.approvals.as_ref().ok_or(Error::ApprovalDisabled)?;
+        let approval = approvals
+            .get_phone_approval(phone_number)
+            .await?
+            .ok_or_else(|| Error::NotPending(mask_phone_number(phone_number)))?;
+        approvals.delete_phone_approval(&approval.phone_number).await?;
+        info!(
+            target: "audit",
+            "Phone write-back rejected: user={} dn={} phone={} approver={}",
+            approval.username, approval.dn, mask_phone_number(&approval.phone_number), approver
+        );
+        Ok(approval)
+    }
+
+    /// Returns whether a number is waiting for approval for the given user.
+    ///
+    /// # Arguments
+    /// * `username` - Canonical username
+    /// * `phone_number` - Number in E.164 format
+    ///
+    /// # Returns
+    /// * `Result<bool>` - `false` when approval is not required
+    pub async fn is_pending(&self, username: &str, phone_number: &str) -> Result<bool, Error> {
+        let Some(approvals) = &self.approvals else {
+            return Ok(false);
+        };
+        Ok(approvals
+            .get_phone_approval(phone_number)
+            .await?
+            .is_some_and(|approval| approval.username.eq_ignore_ascii_case(username)))
+    }
+}
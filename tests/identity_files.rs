@@ -0,0 +1,116 @@
+//! Tests of the users-file and htpasswd identity providers.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use rust_ldap_registration::auth::identity::IdentityProvider;
+use rust_ldap_registration::auth::ldap::Error;
+use rust_ldap_registration::auth::password::{verify_password, HashFormat};
+use rust_ldap_registration::auth::{FileIdentityProvider, HtpasswdIdentityProvider, PhoneNormalizer};
+use rust_ldap_registration::config::PhoneSelection;
+use std::path::PathBuf;
+
+/// `htpasswd -nbB myName myPassword`, from the Apache documentation.
+const BCRYPT: &str = "$2y$05$c4WoMPo3SXsafkva.HHa6uXQZWr7oboPiC2bT/r7q1BB8I2s0BRqC";
+
+/// `htpasswd -nbm myName myPassword`, from the Apache documentation.
+const APR1: &str = "$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/";
+
+/// `htpasswd -nbs myName myPassword`, from the Apache documentation.
+const SHA: &str = "{SHA}VBPuJHI7uixaa6LQGWx4s+5GKNE=";
+
+/// "password" salted with "somesalt", from the argon2 reference implementation.
+const ARGON2: &str = "$argon2i$v=19$m=65536,t=2,p=4$c29tZXNhbHQ$RdescudvJCsgt3ub+b+dWRWJTmaaJObG";
+
+/// Writes a file under a name unique to this test run.
+fn write(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("identity-files-{}-{}", std::process::id(), name));
+    std::fs::write(&path, contents).expect("write test file");
+    path
+}
+
+fn normalizer() -> PhoneNormalizer {
+    PhoneNormalizer::new(Some("US")).unwrap()
+}
+
+#[tokio::test]
+async fn checks_the_password_before_reporting_a_disabled_account() {
+    let path = write(
+        "disabled.yml",
+        &format!(
+            "users:\n  - username: alice\n    password_hash: \"{}\"\n    phone_numbers: [\"+14155550101\"]\n    disabled: true\n",
+            BCRYPT
+        ),
+    );
+    let provider = FileIdentityProvider::load(&path, normalizer(), PhoneSelection::First).unwrap();
+
+    let result = provider.authenticate("alice", "wrong").await;
+    assert!(matches!(result, Err(Error::AuthenticationFailed)), "{:?}", result);
+    let result = provider.authenticate("alice", "myPassword").await;
+    assert!(matches!(result, Err(Error::AccountDisabled(_))), "{:?}", result);
+    let result = provider.authenticate("bob", "myPassword").await;
+    assert!(matches!(result, Err(Error::UserNotFound(_))), "{:?}", result);
+}
+
+#[test]
+fn verifies_known_hashes_of_each_format() {
+    for (hash, format) in [(BCRYPT, HashFormat::Bcrypt), (APR1, HashFormat::Apr1), (SHA, HashFormat::Sha1)] {
+        assert_eq!(HashFormat::detect(hash), Some(format));
+        assert!(verify_password("myPassword", hash), "{}", hash);
+        assert!(!verify_password("mypassword", hash), "{}", hash);
+    }
+    assert_eq!(HashFormat::detect(ARGON2), Some(HashFormat::Argon2));
+    assert!(verify_password("password", ARGON2));
+    assert!(!verify_password("Password", ARGON2));
+}
+
+#[test]
+fn rejects_unsupported_and_malformed_hashes() {
+    // Plain text and crypt(3) DES, which htpasswd -p and -d write
+    for hash in ["myPassword", "rOc2nV0eTHE2E"] {
+        assert_eq!(HashFormat::detect(hash), None);
+        assert!(!verify_password("myPassword", hash));
+    }
+    for hash in ["$2y$05$truncated", "$argon2i$v=19$garbage", "$apr1$r31.....$", "{SHA}"] {
+        assert!(!verify_password("myPassword", hash), "{}", hash);
+    }
+}
+
+#[tokio::test]
+async fn signs_in_with_each_htpasswd_format() {
+    let htpasswd = write(
+        "formats.htpasswd",
+        &format!("# htpasswd -B, -m and -s\nalice:{}\nbob:{}\n\ncarol:{}\n", BCRYPT, APR1, SHA),
+    );
+    let phones = write("formats.phones", "alice:+14155550101\nbob:4155550102\ncarol:+14155550103,+14155550104\n");
+    let provider = HtpasswdIdentityProvider::load(&htpasswd, &phones, normalizer(), PhoneSelection::First).unwrap();
+
+    for (username, phone) in [("alice", "+14155550101"), ("bob", "+14155550102"), ("carol", "+14155550103")] {
+        let user = provider.authenticate(username, "myPassword").await.unwrap();
+        assert_eq!((user.username.as_str(), user.phone_number.as_str()), (username, phone));
+        let result = provider.authenticate(username, "wrong").await;
+        assert!(matches!(result, Err(Error::AuthenticationFailed)), "{:?}", result);
+    }
+}
+
+#[test]
+fn refuses_an_htpasswd_file_with_a_malformed_line() {
+    let phones = write("malformed.phones", "alice:+14155550101\n");
+    for (name, contents) in [("no-colon", "alice\n"), ("no-user", &format!(":{}\n", BCRYPT))] {
+        let htpasswd = write(name, &format!("bob:{}\n{}", APR1, contents));
+        let result = HtpasswdIdentityProvider::load(&htpasswd, &phones, normalizer(), PhoneSelection::First);
+        assert!(matches!(&result, Err(Error::ServerError(message)) if message.starts_with("Invalid line 2")), "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn reads_a_csv_users_file() {
+    let path = write(
+        "users.csv",
+        &format!("username,password_hash,phone_numbers,department\nalice,{},+14155550101;+14155550102,Sales\n", BCRYPT),
+    );
+    let provider = FileIdentityProvider::load(&path, normalizer(), PhoneSelection::First).unwrap();
+
+    let user = provider.authenticate("alice", "myPassword").await.unwrap();
+    assert_eq!(user.phone_number, "+14155550101");
+    assert_eq!(user.profile.get("department").map(String::as_str), Some("Sales"));
+}
@@ -0,0 +1,348 @@
+//! End-to-end tests of `LdapClient` against the fake LDAP server.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+mod support;
+
+use rust_ldap_registration::auth::ldap::Error;
+use rust_ldap_registration::auth::{AccountState, DirectoryType};
+use std::time::Duration;
+use support::{ldap_client, ldap_config, rc, FakeLdapServer, ALICE_DN, ALICE_PASSWORD, BASE_DN};
+
+#[tokio::test]
+async fn authenticates_user_and_returns_normalized_phone_number() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let client = ldap_client(ldap_config(server.url())).await;
+
+    let user = client.authenticate_user("alice", ALICE_PASSWORD).await.unwrap();
+
+    assert_eq!(user.username, "alice");
+    assert_eq!(user.dn, ALICE_DN);
+    assert_eq!(user.phone_number, "+14155550101");
+    assert_eq!(user.profile.get("displayName").map(String::as_str), Some("Alice Example"));
+    assert!(server.update(|d| d.binds().iter().any(|dn| dn == ALICE_DN)));
+}
+
+#[tokio::test]
+async fn rejects_bad_password() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let client = ldap_client(ldap_config(server.url())).await;
+
+    let result = client.authenticate_user("alice", "wrong").await;
+
+    assert!(matches!(result, Err(Error::AuthenticationFailed)), "{:?}", result);
+}
+
+#[tokio::test]
+async fn rejects_empty_password_without_binding() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let client = ldap_client(ldap_config(server.url())).await;
+
+    let result = client.authenticate_user("alice", "").await;
+
+    assert!(matches!(result, Err(Error::AuthenticationFailed)), "{:?}", result);
+    assert!(server.update(|d| !d.binds().iter().any(|dn| dn == ALICE_DN)));
+}
+
+#[tokio::test]
+async fn reports_unknown_user() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let client = ldap_client(ldap_config(server.url())).await;
+
+    let result = client.authenticate_user("mallory", "whatever").await;
+
+    assert!(matches!(result, Err(Error::UserNotFound(_))), "{:?}", result);
+}
+
+#[tokio::test]
+async fn rejects_ambiguous_user_filter() {
+    let directory = support::directory().entry(
+        "uid=alice,ou=contractors,dc=example,dc=com",
+        &[("uid", &["alice"]), ("mobile", &["+14155550199"]), ("userPassword", &["other"])],
+    );
+    let server = FakeLdapServer::start(directory).await;
+    let client = ldap_client(ldap_config(server.url())).await;
+
+    let result = client.authenticate_user("alice", ALICE_PASSWORD).await;
+
+    assert!(matches!(result, Err(Error::AmbiguousUser(_))), "{:?}", result);
+}
+
+#[tokio::test]
+async fn honors_user_filter_template() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let mut config = ldap_config(server.url());
+    config.user_filter = Some("(&(objectClass=person)(|(uid={0})(mail={0})))".to_string());
+    let client = ldap_client(config).await;
+
+    let user = client.authenticate_user("alice@example.com", ALICE_PASSWORD).await.unwrap();
+
+    assert_eq!(user.username, "alice");
+}
+
+#[tokio::test]
+async fn keys_email_and_username_sign_ins_on_the_same_username() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    server.update(|d| {
+        let entry = d.get_mut(ALICE_DN).unwrap();
+        entry.attrs.iter_mut().find(|(name, _)| name == "mail").unwrap().1 = vec!["a.example@example.com".to_string()];
+    });
+    let client = ldap_client(ldap_config(server.url())).await;
+
+    let by_email = client.authenticate_user("a.example@example.com", ALICE_PASSWORD).await.unwrap();
+    let by_username = client.authenticate_user("alice", ALICE_PASSWORD).await.unwrap();
+
+    assert_eq!(by_email.username, "alice");
+    assert_eq!(by_email.username, by_username.username);
+}
+
+#[tokio::test]
+async fn refuses_an_entry_without_the_username_attribute() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let mut config = ldap_config(server.url());
+    config.user_filter = Some("(mail={0})".to_string());
+    config.username_attribute = Some("employeeNumber".to_string());
+    let client = ldap_client(config).await;
+
+    let result = client.authenticate_user("alice@example.com", ALICE_PASSWORD).await;
+
+    assert!(matches!(result, Err(Error::UsernameMissing { .. })), "{:?}", result);
+}
+
+#[tokio::test]
+async fn reports_locked_active_directory_account() {
+    let directory = support::directory().fail_bind(
+        ALICE_DN,
+        rc::INVALID_CREDENTIALS,
+        "80090308: LdapErr: DSID-0C09044E, comment: AcceptSecurityContext error, data 775, v2580",
+    );
+    let server = FakeLdapServer::start(directory).await;
+    let client = ldap_client(ldap_config(server.url())).await;
+
+    let result = client.authenticate_user("alice", ALICE_PASSWORD).await;
+
+    assert!(matches!(result, Err(Error::AccountLocked(_))), "{:?}", result);
+}
+
+#[tokio::test]
+async fn does_not_reveal_a_disabled_account_to_a_wrong_password() {
+    let server = FakeLdapServer::start(support::ad_directory()).await;
+    // ACCOUNTDISABLE | NORMAL_ACCOUNT
+    server.update(|d| {
+        let entry = d.get_mut(ALICE_DN).unwrap();
+        entry.attrs.iter_mut().find(|(name, _)| name == "userAccountControl").unwrap().1 = vec!["514".to_string()];
+    });
+    let mut config = ldap_config(server.url());
+    config.directory_type = DirectoryType::ActiveDirectory;
+    let client = ldap_client(config).await;
+
+    let result = client.authenticate_user("alice", "wrong").await;
+
+    assert!(matches!(result, Err(Error::AuthenticationFailed)), "{:?}", result);
+}
+
+#[tokio::test]
+async fn reports_a_disabled_account_whatever_the_case_of_the_attribute_name() {
+    let server = FakeLdapServer::start(support::ad_directory()).await;
+    // ACCOUNTDISABLE | NORMAL_ACCOUNT, under the name some servers return
+    server.update(|d| {
+        let entry = d.get_mut(ALICE_DN).unwrap();
+        entry.attrs.retain(|(name, _)| name != "userAccountControl");
+        entry.attrs.push(("useraccountcontrol".to_string(), vec!["514".to_string()]));
+    });
+    let mut config = ldap_config(server.url());
+    config.directory_type = DirectoryType::ActiveDirectory;
+    let client = ldap_client(config).await;
+
+    let user = client.lookup_user("alice").await.unwrap();
+
+    assert_eq!(user.account_state, Some(AccountState::Disabled));
+}
+
+#[tokio::test]
+async fn keys_active_directory_sign_ins_on_the_user_principal_name() {
+    let server = FakeLdapServer::start(support::ad_directory()).await;
+    let mut config = ldap_config(server.url());
+    config.directory_type = DirectoryType::ActiveDirectory;
+    let client = ldap_client(config).await;
+
+    for name in ["alice", "alice@example.com", "EXAMPLE\\alice", "alice.example@example.com"] {
+        let user = client.authenticate_user(name, ALICE_PASSWORD).await.unwrap();
+        assert_eq!(user.username, "alice@example.com", "signed in as {}", name);
+    }
+
+    // No userPrincipalName: refused rather than keyed on the typed name
+    server.update(|d| d.get_mut(ALICE_DN).unwrap().attrs.retain(|(name, _)| name != "userPrincipalName"));
+    let result = client.authenticate_user("alice", ALICE_PASSWORD).await;
+    assert!(matches!(result, Err(Error::UsernameMissing { .. })), "{:?}", result);
+}
+
+#[tokio::test]
+async fn gates_on_group_membership() {
+    let group = "cn=signal,ou=groups,dc=example,dc=com";
+    let directory = support::directory()
+        .entry(
+            "uid=bob,ou=people,dc=example,dc=com",
+            &[("uid", &["bob"]), ("mobile", &["+14155550102"]), ("memberOf", &[group]), ("userPassword", &["bob-secret"])],
+        )
+        // Attribute names are case-insensitive, and some servers return them in lower case
+        .entry(
+            "uid=carol,ou=people,dc=example,dc=com",
+            &[("uid", &["carol"]), ("mobile", &["+14155550103"]), ("memberof", &[group]), ("userPassword", &["carol-secret"])],
+        );
+    let server = FakeLdapServer::start(directory).await;
+    let mut config = ldap_config(server.url());
+    config.authorized_groups = vec![group.to_string()];
+    let client = ldap_client(config).await;
+
+    assert!(client.authenticate_user("bob", "bob-secret").await.is_ok());
+    assert!(client.authenticate_user("carol", "carol-secret").await.is_ok());
+    assert!(client.lookup_user("carol").await.is_ok());
+    let result = client.authenticate_user("alice", ALICE_PASSWORD).await;
+    assert!(matches!(result, Err(Error::NotAuthorized(_))), "{:?}", result);
+}
+
+#[tokio::test]
+async fn reports_missing_phone_number() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    server.update(|d| d.get_mut(ALICE_DN).unwrap().attrs.retain(|(name, _)| name != "mobile"));
+    let client = ldap_client(ldap_config(server.url())).await;
+
+    let result = client.authenticate_user("alice", ALICE_PASSWORD).await;
+    assert!(matches!(result, Err(Error::PhoneNumberNotFound(_))), "{:?}", result);
+
+    let user = client.authenticate_user_without_phone("alice", ALICE_PASSWORD).await.unwrap();
+    assert!(user.phone_number.is_empty());
+}
+
+#[tokio::test]
+async fn follows_referrals_to_another_server() {
+    let partners = "ou=partners,dc=example,dc=com";
+    let remote = FakeLdapServer::start(support::directory().entry(
+        "uid=carol,ou=partners,dc=example,dc=com",
+        &[("uid", &["carol"]), ("mobile", &["+14155550103"]), ("userPassword", &["carol-secret"])],
+    ))
+    .await;
+    let local = FakeLdapServer::start(support::directory().referral(partners, &format!("{}/{}", remote.url(), partners))).await;
+
+    let mut config = ldap_config(local.url());
+    config.follow_referrals = true;
+    let client = ldap_client(config).await;
+    let user = client.lookup_user("carol").await.unwrap();
+    assert_eq!(user.phone_numbers, vec!["+14155550103".to_string()]);
+    // Without TLS the referral is followed anonymously
+    assert!(remote.update(|d| d.binds().is_empty()));
+
+    let client = ldap_client(ldap_config(local.url())).await;
+    let result = client.lookup_user("carol").await;
+    assert!(matches!(result, Err(Error::UserNotFound(_))), "{:?}", result);
+}
+
+#[tokio::test]
+async fn follows_referrals_only_to_trusted_hosts() {
+    let partners = "ou=partners,dc=example,dc=com";
+    let remote = FakeLdapServer::start(support::directory().entry(
+        "uid=carol,ou=partners,dc=example,dc=com",
+        &[("uid", &["carol"]), ("mobile", &["+14155550103"]), ("userPassword", &["carol-secret"])],
+    ))
+    .await;
+    let elsewhere = remote.url().replace("127.0.0.1", "localhost");
+    let local = FakeLdapServer::start(support::directory().referral(partners, &format!("{}/{}", elsewhere, partners))).await;
+
+    let mut config = ldap_config(local.url());
+    config.follow_referrals = true;
+    let client = ldap_client(config.clone()).await;
+    let result = client.lookup_user("carol").await;
+    assert!(matches!(result, Err(Error::UserNotFound(_))), "{:?}", result);
+    assert!(remote.update(|d| d.binds().is_empty()));
+
+    let port = elsewhere.rsplit(':').next().unwrap();
+    config.referral_hosts = vec![format!("localhost:{}", port)];
+    let client = ldap_client(config).await;
+    let user = client.lookup_user("carol").await.unwrap();
+    assert_eq!(user.phone_numbers, vec!["+14155550103".to_string()]);
+}
+
+#[tokio::test]
+async fn stops_following_referrals_at_hop_limit() {
+    let looping = "ou=loop,dc=example,dc=com";
+    let server = FakeLdapServer::start(support::directory()).await;
+    let url = format!("{}/{}", server.url(), looping);
+    server.update(|d| *d = std::mem::take(d).referral(looping, &url));
+
+    let mut config = ldap_config(server.url());
+    config.follow_referrals = true;
+    config.referral_hop_limit = 2;
+    let client = ldap_client(config).await;
+
+    let user = client.authenticate_user("alice", ALICE_PASSWORD).await.unwrap();
+    assert_eq!(user.username, "alice");
+}
+
+#[tokio::test]
+async fn times_out_slow_searches() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let mut config = ldap_config(server.url());
+    config.read_timeout = 200;
+    let client = ldap_client(config).await;
+    server.update(|d| d.set_search_delay(Duration::from_secs(2)));
+
+    let result = client.authenticate_user("alice", ALICE_PASSWORD).await;
+
+    assert!(matches!(&result, Err(e) if e.is_connection_error()), "{:?}", result);
+}
+
+#[tokio::test]
+async fn fails_over_to_the_next_server() {
+    let down = FakeLdapServer::start(support::directory().fail_searches(rc::UNAVAILABLE, "Unavailable")).await;
+    let server = FakeLdapServer::start(support::directory()).await;
+    let mut config = ldap_config(server.url());
+    config.urls = vec![down.url().to_string(), server.url().to_string()];
+    config.max_retries = 2;
+    config.min_pool_size = 0;
+    let client = ldap_client(config).await;
+
+    let user = client.authenticate_user("alice", ALICE_PASSWORD).await.unwrap();
+
+    assert_eq!(user.username, "alice");
+}
+
+#[tokio::test]
+async fn fails_over_to_every_server_without_retries() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let mut config = ldap_config(server.url());
+    config.urls = vec![support::closed_url().await, support::closed_url().await, server.url().to_string()];
+    config.max_retries = 0;
+    config.min_pool_size = 0;
+    let client = ldap_client(config).await;
+
+    let user = client.authenticate_user("alice", ALICE_PASSWORD).await.unwrap();
+
+    assert_eq!(user.username, "alice");
+}
+
+#[tokio::test]
+async fn looks_up_users_and_writes_phone_numbers() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let client = ldap_client(ldap_config(server.url())).await;
+
+    client.write_phone_number(ALICE_DN, "mobile", "+14155550111", None).await.unwrap();
+    let user = client.lookup_user("alice").await.unwrap();
+    assert_eq!(user.phone_numbers, vec!["+14155550111".to_string()]);
+
+    assert_eq!(client.username_for_dn(ALICE_DN).await.unwrap().as_deref(), Some("alice"));
+    let missing = format!("uid=nobody,{}", BASE_DN);
+    assert_eq!(client.username_for_dn(&missing).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn matches_phone_number_attributes_case_insensitively() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let mut config = ldap_config(server.url());
+    config.phone_number_attribute = "MOBILE".to_string();
+    let client = ldap_client(config).await;
+
+    let user = client.authenticate_user("alice", ALICE_PASSWORD).await.unwrap();
+
+    assert_eq!(user.phone_number, "+14155550101");
+}
@@ -0,0 +1,79 @@
+//! Tests of `LdapPool` against the fake LDAP server.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+mod support;
+
+use rust_ldap_registration::auth::ldap::{Error, LdapConfig};
+use rust_ldap_registration::auth::LdapPool;
+use std::time::Duration;
+use support::{ldap_config, rc, FakeLdapServer, SERVICE_DN};
+
+async fn pool(server: &FakeLdapServer, min_pool_size: u32, max_pool_size: u32) -> LdapPool {
+    let mut config = ldap_config(server.url());
+    config.min_pool_size = min_pool_size;
+    config.max_pool_size = max_pool_size;
+    config.pool_timeout = 200;
+    LdapPool::new(LdapConfig::from(config)).await.expect("create pool")
+}
+
+fn service_binds(server: &FakeLdapServer) -> usize {
+    server.update(|d| d.binds().iter().filter(|dn| *dn == SERVICE_DN).count())
+}
+
+#[tokio::test]
+async fn waits_for_a_free_connection_and_times_out_when_exhausted() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let pool = pool(&server, 1, 2).await;
+
+    let first = pool.get().await.unwrap();
+    let second = pool.get().await.unwrap();
+    let result = pool.get().await;
+    assert!(matches!(result, Err(Error::PoolTimeout)), "{:?}", result.map(|_| ()));
+
+    // A returned connection is handed out again instead of opening another
+    drop(first);
+    let _third = pool.get().await.unwrap();
+    drop(second);
+    assert_eq!(pool.idle_count(), 1);
+    assert_eq!(service_binds(&server), 2);
+}
+
+#[tokio::test]
+async fn replaces_a_connection_that_broke_while_idle() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let pool = pool(&server, 1, 1).await;
+    assert_eq!(service_binds(&server), 1);
+
+    let conn = pool.get().await.unwrap();
+    let mut handle = (*conn).clone();
+    drop(conn);
+    handle.unbind().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let mut conn = pool.get().await.unwrap();
+    let (entries, _) = conn.op().search("", ldap3::Scope::Base, "(objectClass=*)", vec!["1.1"]).await.unwrap().success().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(service_binds(&server), 2);
+}
+
+#[tokio::test]
+async fn keeps_the_server_of_an_idle_connection_that_failed_its_health_check() {
+    let primary = FakeLdapServer::start(support::directory()).await;
+    let secondary = FakeLdapServer::start(support::directory()).await;
+    let mut config = ldap_config(primary.url());
+    config.urls = vec![primary.url().to_string(), secondary.url().to_string()];
+    config.min_pool_size = 1;
+    config.max_pool_size = 1;
+    config.idle_timeout = 1;
+    let pool = LdapPool::new(LdapConfig::from(config)).await.expect("create pool");
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    // Searches fail, so the probe does, but binds still work
+    primary.update(|d| d.set_search_failure(Some((rc::UNAVAILABLE, "Idle connection reset"))));
+
+    let conn = pool.get().await.unwrap();
+    assert_eq!(conn.server_url(), primary.url());
+    assert_eq!(service_binds(&primary), 2);
+    assert_eq!(service_binds(&secondary), 0);
+}
@@ -0,0 +1,75 @@
+//! End-to-end tests of `LdapValidationServer` against the fake LDAP server.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+mod support;
+
+use rust_ldap_registration::ldap_validation::{LdapValidationServer, LdapValidationService};
+use rust_ldap_registration::proto::org::signal::registration::ldap::rpc::{
+    validate_credentials_response::Result as ValidateCredentialsResult, ValidateCredentialsErrorType,
+    ValidateCredentialsRequest,
+};
+use std::sync::Arc;
+use support::{ldap_client, ldap_config, FakeLdapServer, ALICE_PASSWORD};
+use tonic::Request;
+
+async fn validate(server: &LdapValidationServer, user_id: &str, password: &str) -> ValidateCredentialsResult {
+    let request = Request::new(ValidateCredentialsRequest {
+        user_id: user_id.to_string(),
+        password: password.to_string(),
+    });
+    server.validate_credentials(request).await.unwrap().into_inner().result.unwrap()
+}
+
+fn error_type(result: ValidateCredentialsResult) -> ValidateCredentialsErrorType {
+    match result {
+        ValidateCredentialsResult::Error(error) => error.error_type(),
+        other => panic!("expected an error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn returns_phone_number_and_profile() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let server = LdapValidationServer::new(Arc::new(ldap_client(ldap_config(ldap.url())).await));
+
+    let request = Request::new(ValidateCredentialsRequest {
+        user_id: "alice".to_string(),
+        password: ALICE_PASSWORD.to_string(),
+    });
+    let response = server.validate_credentials(request).await.unwrap().into_inner();
+
+    assert_eq!(response.result, Some(ValidateCredentialsResult::PhoneNumber("+14155550101".to_string())));
+    assert_eq!(response.profile.unwrap().display_name, "Alice Example");
+}
+
+#[tokio::test]
+async fn reports_invalid_credentials() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let server = LdapValidationServer::new(Arc::new(ldap_client(ldap_config(ldap.url())).await));
+
+    let result = validate(&server, "alice", "wrong").await;
+
+    assert_eq!(error_type(result), ValidateCredentialsErrorType::InvalidCredentials);
+}
+
+#[tokio::test]
+async fn reports_unknown_user() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let server = LdapValidationServer::new(Arc::new(ldap_client(ldap_config(ldap.url())).await));
+
+    let result = validate(&server, "mallory", "whatever").await;
+
+    assert_eq!(error_type(result), ValidateCredentialsErrorType::UserNotFound);
+}
+
+#[tokio::test]
+async fn reports_server_errors_when_the_directory_is_unavailable() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let server = LdapValidationServer::new(Arc::new(ldap_client(ldap_config(ldap.url())).await));
+    ldap.update(|d| d.set_search_failure(Some((support::rc::UNAVAILABLE, "Unavailable"))));
+
+    let result = validate(&server, "alice", ALICE_PASSWORD).await;
+
+    assert_eq!(error_type(result), ValidateCredentialsErrorType::ServerError);
+}
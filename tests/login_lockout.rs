@@ -0,0 +1,129 @@
+//! Tests of the failed sign-in tracker.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use rust_ldap_registration::auth::ldap::{AuthenticatedUser, Error};
+use rust_ldap_registration::auth::lockout::{LoginLockout, LoginLockoutConfig};
+use std::net::IpAddr;
+use std::time::Duration;
+
+fn config() -> LoginLockoutConfig {
+    LoginLockoutConfig {
+        free_attempts: 2,
+        backoff_base_secs: 4,
+        backoff_max_secs: 10,
+        lockout_threshold: 5,
+        address_lockout_threshold: 8,
+        lockout_secs: 600,
+        ..LoginLockoutConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn backs_off_exponentially_after_free_attempts() {
+    let lockout = LoginLockout::new(config());
+
+    lockout.record_failure("alice", None).await;
+    lockout.record_failure("alice", None).await;
+    assert!(lockout.check("alice", None).await.is_ok());
+
+    lockout.record_failure("alice", None).await;
+    let throttled = lockout.check("alice", None).await.unwrap_err();
+    assert!(!throttled.locked);
+    assert!(throttled.retry_after <= Duration::from_secs(4) && throttled.retry_after > Duration::from_secs(3));
+
+    lockout.record_failure("alice", None).await;
+    let throttled = lockout.check("ALICE ", None).await.unwrap_err();
+    assert!(throttled.retry_after > Duration::from_secs(7));
+
+    lockout.record_failure("alice", None).await;
+    let throttled = lockout.check("alice", None).await.unwrap_err();
+    assert!(throttled.locked);
+    assert!(throttled.retry_after > Duration::from_secs(590));
+}
+
+#[tokio::test]
+async fn success_clears_the_username_but_not_the_address() {
+    let lockout = LoginLockout::new(config());
+    let address: IpAddr = "192.0.2.10".parse().unwrap();
+
+    for _ in 0..8 {
+        lockout.record_failure("alice", Some(address)).await;
+    }
+    lockout.record_success("alice").await;
+
+    assert!(lockout.check("alice", None).await.is_ok());
+    let throttled = lockout.check("bob", Some(address)).await.unwrap_err();
+    assert!(throttled.locked);
+}
+
+#[tokio::test]
+async fn keeps_usernames_apart() {
+    let lockout = LoginLockout::new(config());
+
+    for _ in 0..5 {
+        lockout.record_failure("alice", None).await;
+    }
+
+    assert!(lockout.check("alice", None).await.is_err());
+    assert!(lockout.check("bob", None).await.is_ok());
+}
+
+#[tokio::test]
+async fn counts_account_state_errors_as_failures() {
+    let lockout = LoginLockout::new(config());
+
+    for error in [
+        Error::AccountLocked("alice".to_string()),
+        Error::AccountDisabled("alice".to_string()),
+        Error::AccountExpired("alice".to_string()),
+    ] {
+        lockout.record("alice", None, &Err(error)).await;
+    }
+
+    assert!(lockout.check("alice", None).await.is_err());
+}
+
+#[tokio::test]
+async fn counts_every_form_of_a_username_together() {
+    let lockout = LoginLockout::new(config());
+
+    for username in ["alice", "alice@corp.example", "CORP\\alice", "Alice@example.com", " ALICE "] {
+        lockout.record_failure(username, None).await;
+    }
+
+    assert!(lockout.check("alice", None).await.unwrap_err().locked);
+    assert!(lockout.check("corp\\alice", None).await.is_err());
+    assert!(lockout.check("alice.example@example.com", None).await.is_ok());
+}
+
+#[tokio::test]
+async fn admits_one_attempt_at_a_time_past_the_free_attempts() {
+    let lockout = LoginLockout::new(config());
+    let failed = Err(Error::AuthenticationFailed);
+
+    // The free attempts may run in parallel
+    let first = lockout.check("alice", None).await.unwrap();
+    let second = lockout.check("alice@corp.example", None).await.unwrap();
+    let throttled = lockout.check("alice", None).await.unwrap_err();
+    assert!(!throttled.locked);
+    assert!(throttled.retry_after <= Duration::from_secs(1));
+    first.record(&failed).await;
+    second.record(&failed).await;
+
+    // After them, the next attempt waits for the one in flight
+    let third = lockout.check("alice", None).await.unwrap();
+    assert!(lockout.check("alice", None).await.is_err());
+    // A cancelled attempt is no longer in flight
+    drop(third);
+    let third = lockout.check("alice", None).await.unwrap();
+    let user = AuthenticatedUser {
+        username: "alice".to_string(),
+        dn: "uid=alice,ou=people,dc=example,dc=com".to_string(),
+        bind_name: "uid=alice,ou=people,dc=example,dc=com".to_string(),
+        phone_number: "+14155550101".to_string(),
+        profile: Default::default(),
+    };
+    third.record(&Ok(user)).await;
+    assert!(lockout.check("alice", None).await.is_ok());
+}
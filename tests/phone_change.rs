@@ -0,0 +1,146 @@
+//! End-to-end tests of the phone number change RPCs against the fake LDAP
+//! server and an in-memory registration table.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+mod support;
+
+use rust_ldap_registration::config::PhoneChangeConfig;
+use rust_ldap_registration::grpc::RegistrationServer;
+use rust_ldap_registration::phone_change::PhoneNumberChanger;
+use rust_ldap_registration::proto::registration::registration_service_server::RegistrationService;
+use rust_ldap_registration::proto::registration::{
+    CompletePhoneNumberChangeRequest, StartPhoneNumberChangeRequest, StartRegistrationRequest, VerifyCodeRequest,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use support::{
+    ldap_client, ldap_config, registration_server_with_dynamodb, FakeDynamoDb, FakeLdapServer, ALICE_DN, ALICE_PASSWORD,
+};
+use tonic::{Code, Request};
+
+const OLD_PHONE: &str = "+14155550101";
+const NEW_PHONE: &str = "+14155550199";
+
+/// Code the test-mode Twilio client accepts: the last six digits of `NEW_PHONE`.
+const CODE: &str = "550199";
+
+/// Returns a table holding alice's registration, stored for the entry `dn`.
+async fn registrations(dn: &str) -> FakeDynamoDb {
+    let table = FakeDynamoDb::new("phone_number");
+    table.client().save_registration("alice", dn, OLD_PHONE, "1", &HashMap::new()).await.unwrap();
+    table
+}
+
+async fn server(ldap: &FakeLdapServer, table: &FakeDynamoDb) -> RegistrationServer {
+    let client = ldap_client(ldap_config(ldap.url())).await;
+    let config = PhoneChangeConfig { enabled: true, confirm_old_number: true, ..Default::default() };
+    let changer = PhoneNumberChanger::new(client.clone(), Arc::new(table.client()), &config);
+    registration_server_with_dynamodb(Arc::new(client), NEW_PHONE, table.client()).with_phone_change(Arc::new(changer))
+}
+
+fn start_request() -> Request<StartPhoneNumberChangeRequest> {
+    Request::new(StartPhoneNumberChangeRequest {
+        username: "alice".to_string(),
+        password: ALICE_PASSWORD.to_string(),
+        new_phone_number: NEW_PHONE.to_string(),
+        channel: "sms".to_string(),
+    })
+}
+
+fn code(session_id: &str, code: &str) -> Request<VerifyCodeRequest> {
+    Request::new(VerifyCodeRequest { session_id: session_id.to_string(), code: code.to_string() })
+}
+
+fn complete(session_id: &str) -> Request<CompletePhoneNumberChangeRequest> {
+    Request::new(CompletePhoneNumberChangeRequest { session_id: session_id.to_string() })
+}
+
+#[tokio::test]
+async fn moves_the_registration_once_both_numbers_are_verified() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let table = registrations(ALICE_DN).await;
+    let server = server(&ldap, &table).await;
+
+    let started = server.start_phone_number_change(start_request()).await.unwrap().into_inner();
+    assert!(started.old_number_confirmation_required);
+    assert_eq!(started.old_phone_number, "+*******0101");
+    let session_id = started.session_id;
+
+    assert!(server.verify_code(code(&session_id, CODE)).await.unwrap().into_inner().success);
+    let refused = server.complete_phone_number_change(complete(&session_id)).await.unwrap().into_inner();
+    assert!(!refused.success);
+    assert_eq!(refused.message, "Old phone number not confirmed");
+
+    let wrong = server.verify_old_phone_number(code(&session_id, "000000")).await.unwrap().into_inner();
+    assert!(!wrong.success);
+    assert!(server.verify_old_phone_number(code(&session_id, CODE)).await.unwrap().into_inner().success);
+    // Retries of either verification are answered without checking again
+    assert!(server.verify_code(code(&session_id, CODE)).await.unwrap().into_inner().success);
+    assert!(server.verify_old_phone_number(code(&session_id, CODE)).await.unwrap().into_inner().success);
+    let completed = server.complete_phone_number_change(complete(&session_id)).await.unwrap().into_inner();
+    assert!(completed.success, "{}", completed.message);
+
+    assert_eq!(table.keys(), vec![NEW_PHONE]);
+    let moved = table.client().get_registration(NEW_PHONE).await.unwrap().unwrap();
+    assert_eq!(moved.dn, ALICE_DN);
+    assert_eq!(moved.phone_history[0].phone_number, OLD_PHONE);
+    let user = ldap_client(ldap_config(ldap.url())).await.lookup_user("alice").await.unwrap();
+    assert_eq!(user.phone_numbers, vec![NEW_PHONE.to_string()]);
+}
+
+#[tokio::test]
+async fn refuses_a_registration_stored_for_another_entry_with_the_same_username() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let table = registrations("uid=alice,ou=contractors,dc=example,dc=com").await;
+    let server = server(&ldap, &table).await;
+
+    let status = server.start_phone_number_change(start_request()).await.unwrap_err();
+
+    assert_eq!(status.code(), Code::NotFound);
+    assert_eq!(table.keys(), vec![OLD_PHONE]);
+}
+
+#[tokio::test]
+async fn does_not_move_a_registration_that_changed_hands() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let table = registrations(ALICE_DN).await;
+    let server = server(&ldap, &table).await;
+    let session_id = server.start_phone_number_change(start_request()).await.unwrap().into_inner().session_id;
+    assert!(server.verify_code(code(&session_id, CODE)).await.unwrap().into_inner().success);
+    assert!(server.verify_old_phone_number(code(&session_id, CODE)).await.unwrap().into_inner().success);
+
+    // The number is re-registered for another entry while the change is pending
+    let other = "uid=alice,ou=contractors,dc=example,dc=com";
+    table.client().save_registration("alice", other, OLD_PHONE, "2", &HashMap::new()).await.unwrap();
+    let completed = server.complete_phone_number_change(complete(&session_id)).await.unwrap().into_inner();
+
+    assert!(!completed.success);
+    assert_eq!(table.keys(), vec![OLD_PHONE]);
+    assert_eq!(table.client().get_registration(OLD_PHONE).await.unwrap().unwrap().dn, other);
+}
+
+#[tokio::test]
+async fn verifies_the_old_number_only_for_a_phone_number_change() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let table = registrations(ALICE_DN).await;
+    let server = server(&ldap, &table).await;
+
+    let registration = server
+        .start_registration(Request::new(StartRegistrationRequest {
+            username: "alice".to_string(),
+            password: ALICE_PASSWORD.to_string(),
+            channel: "sms".to_string(),
+            phone_number: String::new(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let status = server.verify_old_phone_number(code(&registration.session_id, CODE)).await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+    let status = server.complete_phone_number_change(complete(&registration.session_id)).await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+    let status = server.verify_old_phone_number(code("missing", CODE)).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
@@ -0,0 +1,112 @@
+//! Tests of the directory reconciler against the fake LDAP server and an
+//! in-memory registration table.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+mod support;
+
+use rust_ldap_registration::config::ReconciliationConfig;
+use rust_ldap_registration::reconcile::{Error, Reconciler};
+use std::collections::HashMap;
+use std::sync::Arc;
+use support::{ldap_client, ldap_config, FakeDynamoDb, FakeLdapServer, ALICE_DN};
+
+const ALICE_PHONE: &str = "+14155550101";
+const BOB_PHONE: &str = "+14155550102";
+
+/// Returns a table with registrations for alice, who is in the directory, and
+/// bob, who is not.
+async fn registrations() -> FakeDynamoDb {
+    let table = FakeDynamoDb::new("phone_number");
+    let client = table.client();
+    client.save_registration("alice", ALICE_DN, ALICE_PHONE, "1", &HashMap::new()).await.unwrap();
+    client.save_registration("bob", "uid=bob,ou=people,dc=example,dc=com", BOB_PHONE, "2", &HashMap::new()).await.unwrap();
+    table
+}
+
+async fn reconciler(server: &FakeLdapServer, table: &FakeDynamoDb, config: ReconciliationConfig) -> Reconciler {
+    Reconciler::new(ldap_client(ldap_config(server.url())).await, Arc::new(table.client()), config)
+}
+
+/// Settings without a limit on deletions.
+fn unlimited(dry_run: bool) -> ReconciliationConfig {
+    ReconciliationConfig { dry_run, max_deletion_fraction: 1.0, ..Default::default() }
+}
+
+#[tokio::test]
+async fn only_reports_in_dry_run() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let table = registrations().await;
+
+    let report = reconciler(&server, &table, unlimited(true)).await.run_once().await.unwrap();
+
+    assert_eq!((report.scanned, report.kept, report.user_gone, report.deleted), (2, 1, 1, 0));
+    assert_eq!(table.keys(), vec![ALICE_PHONE, BOB_PHONE]);
+}
+
+#[tokio::test]
+async fn deletes_the_registration_of_a_user_who_left() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let table = registrations().await;
+
+    let report = reconciler(&server, &table, unlimited(false)).await.run_once().await.unwrap();
+
+    assert_eq!((report.kept, report.user_gone, report.deleted, report.errors), (1, 1, 1, 0));
+    assert_eq!(table.keys(), vec![ALICE_PHONE]);
+}
+
+#[tokio::test]
+async fn aborts_a_run_that_would_delete_too_many() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let table = registrations().await;
+
+    // One of two is more than the default fraction
+    let result = reconciler(&server, &table, ReconciliationConfig::default()).await.run_once().await;
+    assert!(matches!(&result, Err(Error::TooManyDeletions(report)) if report.flagged() == 1), "{:?}", result);
+
+    let config = ReconciliationConfig { max_deletions: Some(0), ..unlimited(false) };
+    let result = reconciler(&server, &table, config).await.run_once().await;
+    assert!(matches!(result, Err(Error::TooManyDeletions(_))), "{:?}", result);
+    assert_eq!(table.keys(), vec![ALICE_PHONE, BOB_PHONE]);
+
+    // A dry run still reports what it would revoke
+    let config = ReconciliationConfig { dry_run: true, ..Default::default() };
+    let report = reconciler(&server, &table, config).await.run_once().await.unwrap();
+    assert_eq!(report.user_gone, 1);
+}
+
+#[tokio::test]
+async fn keeps_every_registration_when_the_search_base_is_missing() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let table = registrations().await;
+    let mut config = ldap_config(server.url());
+    config.base_dn = "ou=gone,dc=example,dc=com".to_string();
+    let reconciler = Reconciler::new(ldap_client(config).await, Arc::new(table.client()), unlimited(false));
+
+    let report = reconciler.run_once().await.unwrap();
+
+    assert_eq!((report.errors, report.flagged(), report.deleted), (2, 0, 0));
+    assert_eq!(table.keys(), vec![ALICE_PHONE, BOB_PHONE]);
+}
+
+#[tokio::test]
+async fn deletes_the_registration_of_an_account_locked_by_an_administrator() {
+    let server = FakeLdapServer::start(support::directory()).await;
+    let table = registrations().await;
+    let lock = |time: &str| {
+        let time = time.to_string();
+        server.update(move |d| d.get_mut(ALICE_DN).unwrap().attrs.push(("pwdAccountLockedTime".to_string(), vec![time])))
+    };
+
+    // A lockout after failed attempts expires on its own
+    lock("20261017093000Z");
+    let report = reconciler(&server, &table, unlimited(false)).await.run_once().await.unwrap();
+    assert_eq!((report.kept, report.disabled), (1, 0));
+    assert_eq!(table.keys(), vec![ALICE_PHONE]);
+
+    server.update(|d| d.get_mut(ALICE_DN).unwrap().attrs.retain(|(name, _)| name != "pwdAccountLockedTime"));
+    lock("000001010000Z");
+    let report = reconciler(&server, &table, unlimited(false)).await.run_once().await.unwrap();
+    assert_eq!((report.kept, report.disabled, report.deleted), (0, 1, 1));
+    assert!(table.keys().is_empty());
+}
@@ -0,0 +1,223 @@
+//! End-to-end tests of `RegistrationServer` against the fake LDAP server.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+mod support;
+
+use rust_ldap_registration::auth::lockout::{LoginLockout, LoginLockoutConfig};
+use rust_ldap_registration::config::PhoneSelection;
+use rust_ldap_registration::proto::registration::registration_service_server::RegistrationService;
+use rust_ldap_registration::proto::registration::{
+    SelectPhoneNumberRequest, StartRegistrationRequest, VerifyCodeRequest,
+};
+use rust_ldap_registration::session::{MemorySessionStore, Session, SessionStore};
+use std::sync::Arc;
+use std::time::Duration;
+use support::{
+    ldap_client, ldap_config, registration_server, registration_server_with_twilio, twilio_server, FakeLdapServer,
+    FakeTwilioServer, ALICE_DN, ALICE_PASSWORD,
+};
+use tonic::{Code, Request};
+
+fn start_request(username: &str, password: &str) -> Request<StartRegistrationRequest> {
+    Request::new(StartRegistrationRequest {
+        username: username.to_string(),
+        password: password.to_string(),
+        channel: "sms".to_string(),
+        phone_number: String::new(),
+    })
+}
+
+#[tokio::test]
+async fn rejects_bad_password() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let server = registration_server(Arc::new(ldap_client(ldap_config(ldap.url())).await), "+14155550101").await;
+
+    let status = server.start_registration(start_request("alice", "wrong")).await.unwrap_err();
+
+    assert_eq!(status.code(), Code::Unauthenticated);
+}
+
+#[tokio::test]
+async fn reports_unknown_user() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let server = registration_server(Arc::new(ldap_client(ldap_config(ldap.url())).await), "+14155550101").await;
+
+    let status = server.start_registration(start_request("mallory", "whatever")).await.unwrap_err();
+
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn verifies_the_directory_phone_number() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let server = registration_server(Arc::new(ldap_client(ldap_config(ldap.url())).await), "+14155550101").await;
+
+    let started = server.start_registration(start_request("alice", ALICE_PASSWORD)).await.unwrap().into_inner();
+    assert_eq!(started.phone_number, "+14155550101");
+
+    let wrong = server
+        .verify_code(Request::new(VerifyCodeRequest { session_id: started.session_id.clone(), code: "000000".to_string() }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!wrong.success);
+
+    let verified = server
+        .verify_code(Request::new(VerifyCodeRequest { session_id: started.session_id, code: "550101".to_string() }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(verified.success);
+}
+
+#[tokio::test]
+async fn lets_users_choose_between_several_numbers() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    ldap.update(|d| {
+        let alice = d.get_mut(ALICE_DN).unwrap();
+        alice.attrs.retain(|(name, _)| name != "mobile");
+        alice.attrs.push(("mobile".to_string(), vec!["+14155550101".to_string(), "+14155550199".to_string()]));
+    });
+    let mut config = ldap_config(ldap.url());
+    config.phone_selection = PhoneSelection::Reject;
+    let server = registration_server(Arc::new(ldap_client(config).await), "+14155550199").await;
+
+    let started = server.start_registration(start_request("alice", ALICE_PASSWORD)).await.unwrap().into_inner();
+    assert!(started.phone_number.is_empty());
+    assert_eq!(started.phone_number_candidates.len(), 2);
+    assert!(started.phone_number_candidates.iter().all(|n| !n.contains("555")));
+
+    let selected = server
+        .select_phone_number(Request::new(SelectPhoneNumberRequest {
+            session_id: started.session_id,
+            candidate_index: 1,
+            channel: "sms".to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(selected.phone_number, "+14155550199");
+}
+
+#[tokio::test]
+async fn throttles_repeated_bad_passwords_before_they_reach_the_directory() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let lockout = LoginLockout::new(LoginLockoutConfig {
+        free_attempts: 2,
+        backoff_base_secs: 30,
+        ..LoginLockoutConfig::default()
+    });
+    let server = registration_server(Arc::new(ldap_client(ldap_config(ldap.url())).await), "+14155550101")
+        .await
+        .with_login_lockout(Arc::new(lockout));
+
+    for _ in 0..3 {
+        let status = server.start_registration(start_request("alice", "wrong")).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+    let binds = ldap.update(|d| d.binds().len());
+
+    let status = server.start_registration(start_request("Alice", ALICE_PASSWORD)).await.unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    assert_eq!(status.metadata().get("retry-after").unwrap(), "30");
+    assert_eq!(ldap.update(|d| d.binds().len()), binds);
+}
+
+#[tokio::test]
+async fn continues_a_registration_on_another_replica_sharing_the_session_store() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let first = registration_server(Arc::new(ldap_client(ldap_config(ldap.url())).await), "+14155550101")
+        .await
+        .with_session_store(sessions.clone());
+    let second = registration_server(Arc::new(ldap_client(ldap_config(ldap.url())).await), "+14155550101")
+        .await
+        .with_session_store(sessions.clone());
+
+    let started = first.start_registration(start_request("alice", ALICE_PASSWORD)).await.unwrap().into_inner();
+    let verified = second
+        .verify_code(Request::new(VerifyCodeRequest { session_id: started.session_id.clone(), code: "550101".to_string() }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert!(verified.success);
+    assert!(sessions.get(&started.session_id).await.unwrap().unwrap().verified);
+}
+
+#[tokio::test]
+async fn refuses_new_registrations_when_the_session_store_is_full() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let server = registration_server(Arc::new(ldap_client(ldap_config(ldap.url())).await), "+14155550101")
+        .await
+        .with_session_store(Arc::new(MemorySessionStore::new().with_max_sessions(Some(1))));
+
+    server.start_registration(start_request("alice", ALICE_PASSWORD)).await.unwrap();
+    let status = server.start_registration(start_request("alice", ALICE_PASSWORD)).await.unwrap_err();
+
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    assert_eq!(server.session_metrics().rejected(), 1);
+}
+
+#[tokio::test]
+async fn counts_down_the_remaining_attempts_and_drops_the_session_when_they_run_out() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let server = registration_server(Arc::new(ldap_client(ldap_config(ldap.url())).await), "+14155550101")
+        .await
+        .with_session_store(sessions.clone())
+        .with_max_check_attempts(3);
+    let started = server.start_registration(start_request("alice", ALICE_PASSWORD)).await.unwrap().into_inner();
+    let verify = |code: &str| {
+        server.verify_code(Request::new(VerifyCodeRequest { session_id: started.session_id.clone(), code: code.to_string() }))
+    };
+
+    for remaining in [2, 1, 0] {
+        let wrong = verify("000000").await.unwrap().into_inner();
+        assert!(!wrong.success);
+        assert_eq!(wrong.remaining_attempts, remaining);
+    }
+
+    assert!(sessions.get(&started.session_id).await.unwrap().is_none());
+    assert_eq!(verify("550101").await.unwrap_err().code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn drops_the_session_when_twilio_reports_the_attempts_exhausted() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let twilio = FakeTwilioServer::start(Duration::ZERO).await;
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let server = registration_server_with_twilio(Arc::new(ldap_client(ldap_config(ldap.url())).await), &twilio)
+        .await
+        .with_session_store(sessions.clone());
+    sessions.create("session", Session::new("alice", "+14155550101".to_string(), Duration::from_secs(300))).await.unwrap();
+
+    let request = VerifyCodeRequest { session_id: "session".to_string(), code: twilio_server::EXHAUSTED_CODE.to_string() };
+    let response = server.verify_code(Request::new(request)).await.unwrap().into_inner();
+
+    assert!(!response.success);
+    assert_eq!(response.remaining_attempts, 0);
+    assert!(sessions.get("session").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn answers_a_retried_verification_without_checking_the_code_again() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let twilio = FakeTwilioServer::start(Duration::ZERO).await;
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let server = registration_server_with_twilio(Arc::new(ldap_client(ldap_config(ldap.url())).await), &twilio)
+        .await
+        .with_session_store(sessions.clone());
+    sessions.create("session", Session::new("alice", "+14155550101".to_string(), Duration::from_secs(300))).await.unwrap();
+    let request = || Request::new(VerifyCodeRequest { session_id: "session".to_string(), code: twilio_server::CODE.to_string() });
+
+    assert!(server.verify_code(request()).await.unwrap().into_inner().success);
+    let retried = server.verify_code(request()).await.unwrap().into_inner();
+
+    assert!(retried.success, "{}", retried.message);
+    assert_eq!(twilio.requests(), 1);
+    let session = sessions.get("session").await.unwrap().unwrap();
+    assert!(session.verified);
+    assert_eq!(session.check_attempts, 1);
+}
@@ -0,0 +1,204 @@
+//! Tests of the resend cooldowns and the ResendCode RPC.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+mod support;
+
+use rust_ldap_registration::config::PhoneChangeConfig;
+use rust_ldap_registration::phone_change::PhoneNumberChanger;
+use rust_ldap_registration::proto::registration::registration_service_server::RegistrationService;
+use rust_ldap_registration::proto::registration::{
+    ResendCodeRequest, StartPhoneNumberChangeRequest, StartRegistrationRequest, VerifyCodeRequest,
+};
+use rust_ldap_registration::session::{MemorySessionStore, Session, SessionStore};
+use rust_ldap_registration::twilio::{CodeSends, ResendPolicy, VerificationChannel};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use support::{
+    ldap_client, ldap_config, registration_server, registration_server_with_twilio, twilio_server, FakeDynamoDb,
+    FakeLdapServer, FakeTwilioServer, ALICE_DN, ALICE_PASSWORD,
+};
+use tonic::{Code, Request};
+
+fn policy() -> ResendPolicy {
+    ResendPolicy {
+        sms_delay: Duration::from_secs(10),
+        voice_delay: Duration::from_secs(60),
+        max_voice_calls: 2,
+        voice_delay_after_first_sms: Duration::from_secs(120),
+    }
+}
+
+fn resend(session_id: &str, channel: &str) -> Request<ResendCodeRequest> {
+    Request::new(ResendCodeRequest {
+        session_id: session_id.to_string(),
+        channel: channel.to_string(),
+        old_phone_number: false,
+    })
+}
+
+fn resend_to_old(session_id: &str) -> Request<ResendCodeRequest> {
+    Request::new(ResendCodeRequest {
+        session_id: session_id.to_string(),
+        channel: "sms".to_string(),
+        old_phone_number: true,
+    })
+}
+
+fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[test]
+fn voice_calls_wait_for_the_first_sms_and_run_out() {
+    let policy = policy();
+    let start = SystemTime::now();
+    let mut sends = CodeSends::default();
+    assert!(policy.next_send(&sends, VerificationChannel::Voice).unwrap() <= start);
+
+    sends.record(VerificationChannel::Sms, start);
+    sends.record(VerificationChannel::Sms, start + Duration::from_secs(30));
+    assert_eq!(policy.next_send(&sends, VerificationChannel::Sms), Some(start + Duration::from_secs(40)));
+    assert_eq!(policy.next_send(&sends, VerificationChannel::Voice), Some(start + Duration::from_secs(120)));
+
+    sends.record(VerificationChannel::Voice, start + Duration::from_secs(120));
+    assert_eq!(policy.next_send(&sends, VerificationChannel::Voice), Some(start + Duration::from_secs(180)));
+    sends.record(VerificationChannel::Voice, start + Duration::from_secs(180));
+    assert_eq!(policy.next_send(&sends, VerificationChannel::Voice), None);
+}
+
+#[tokio::test]
+async fn refuses_a_resend_while_the_channel_cools_down() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let server = registration_server(Arc::new(ldap_client(ldap_config(ldap.url())).await), "+14155550101")
+        .await
+        .with_session_store(sessions.clone())
+        .with_resend_policy(policy());
+    let started = server
+        .start_registration(Request::new(StartRegistrationRequest {
+            username: "alice".to_string(),
+            password: ALICE_PASSWORD.to_string(),
+            channel: "sms".to_string(),
+            phone_number: String::new(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let first_sms = sessions.get(&started.session_id).await.unwrap().unwrap().sends.first_sms.unwrap();
+
+    let refused = server.resend_code(resend(&started.session_id, "voice")).await.unwrap().into_inner();
+
+    assert!(!refused.success);
+    assert_eq!(refused.next_sms_at, epoch_secs(first_sms + Duration::from_secs(10)) + 1);
+    assert_eq!(refused.next_voice_at, epoch_secs(first_sms + Duration::from_secs(120)) + 1);
+    assert_eq!(sessions.get(&started.session_id).await.unwrap().unwrap().sends.voice_calls, 0);
+}
+
+#[tokio::test]
+async fn switches_to_voice_once_allowed_and_reports_when_calls_run_out() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let server = registration_server(Arc::new(ldap_client(ldap_config(ldap.url())).await), "+14155550101")
+        .await
+        .with_session_store(sessions.clone())
+        .with_resend_policy(ResendPolicy { voice_delay: Duration::ZERO, ..policy() });
+    let mut session = Session::new("alice", "+14155550101".to_string(), Duration::from_secs(300));
+    session.sends.record(VerificationChannel::Sms, SystemTime::now() - Duration::from_secs(150));
+    sessions.create("session", session).await.unwrap();
+
+    let first = server.resend_code(resend("session", "voice")).await.unwrap().into_inner();
+    assert!(first.success);
+    assert!(first.next_voice_at > 0);
+    let second = server.resend_code(resend("session", "voice")).await.unwrap().into_inner();
+    assert!(second.success);
+    assert_eq!(second.next_voice_at, 0);
+
+    let refused = server.resend_code(resend("session", "voice")).await.unwrap().into_inner();
+    assert!(!refused.success);
+    assert_eq!(refused.next_voice_at, 0);
+    assert!(refused.next_sms_at <= epoch_secs(SystemTime::now()) + 1);
+    assert_eq!(sessions.get("session").await.unwrap().unwrap().sends.voice_calls, 2);
+}
+
+#[tokio::test]
+async fn needs_a_phone_number_to_resend_to() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let server = registration_server(Arc::new(ldap_client(ldap_config(ldap.url())).await), "+14155550101")
+        .await
+        .with_session_store(sessions.clone());
+    sessions.create("session", Session::new("alice", String::new(), Duration::from_secs(300))).await.unwrap();
+
+    let status = server.resend_code(resend("session", "sms")).await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+    let status = server.resend_code(resend("session", "fax")).await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    let status = server.resend_code(resend_to_old("session")).await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+    let status = server.resend_code(resend("missing", "sms")).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn a_failed_send_does_not_start_the_cooldown() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let twilio = FakeTwilioServer::start(Duration::ZERO).await;
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let server = registration_server_with_twilio(Arc::new(ldap_client(ldap_config(ldap.url())).await), &twilio)
+        .await
+        .with_session_store(sessions.clone())
+        .with_resend_policy(policy());
+    let mut session = Session::new("alice", twilio_server::LANDLINE.to_string(), Duration::from_secs(300));
+    session.sends.record(VerificationChannel::Sms, SystemTime::now() - Duration::from_secs(150));
+    sessions.create("session", session).await.unwrap();
+    let before = sessions.get("session").await.unwrap().unwrap().sends;
+
+    for _ in 0..2 {
+        let status = server.resend_code(resend("session", "sms")).await.unwrap_err();
+        assert_eq!(status.code(), Code::Internal);
+    }
+
+    assert_eq!(twilio.sent_to(), vec![twilio_server::LANDLINE; 2]);
+    let after = sessions.get("session").await.unwrap().unwrap().sends;
+    assert_eq!((after.first_sms, after.last_sms), (before.first_sms, before.last_sms));
+}
+
+#[tokio::test]
+async fn resends_to_the_old_number_of_a_phone_number_change() {
+    const OLD_PHONE: &str = "+14155550101";
+    const NEW_PHONE: &str = "+14155550199";
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let twilio = FakeTwilioServer::start(Duration::ZERO).await;
+    let table = FakeDynamoDb::new("phone_number");
+    table.client().save_registration("alice", ALICE_DN, OLD_PHONE, "1", &HashMap::new()).await.unwrap();
+    let client = ldap_client(ldap_config(ldap.url())).await;
+    let config = PhoneChangeConfig { enabled: true, confirm_old_number: true, ..Default::default() };
+    let changer = PhoneNumberChanger::new(client.clone(), Arc::new(table.client()), &config);
+    let server = registration_server_with_twilio(Arc::new(client), &twilio)
+        .await
+        .with_resend_policy(ResendPolicy { sms_delay: Duration::ZERO, ..policy() })
+        .with_phone_change(Arc::new(changer));
+    let session_id = server
+        .start_phone_number_change(Request::new(StartPhoneNumberChangeRequest {
+            username: "alice".to_string(),
+            password: ALICE_PASSWORD.to_string(),
+            new_phone_number: NEW_PHONE.to_string(),
+            channel: "sms".to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id;
+    assert_eq!(twilio.sent_to(), vec![NEW_PHONE, OLD_PHONE]);
+
+    assert!(server.resend_code(resend_to_old(&session_id)).await.unwrap().into_inner().success);
+    assert!(server.resend_code(resend(&session_id, "sms")).await.unwrap().into_inner().success);
+    assert_eq!(twilio.sent_to(), vec![NEW_PHONE, OLD_PHONE, OLD_PHONE, NEW_PHONE]);
+
+    let code = VerifyCodeRequest { session_id: session_id.clone(), code: twilio_server::CODE.to_string() };
+    assert!(server.verify_old_phone_number(Request::new(code)).await.unwrap().into_inner().success);
+    let status = server.resend_code(resend_to_old(&session_id)).await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+}
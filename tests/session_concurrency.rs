@@ -0,0 +1,101 @@
+//! Load test of concurrent verifications against a slow Verify API.
+//!
+//! Each check takes `DELAY`. If the server held one lock across the Twilio call,
+//! `SESSIONS` concurrent checks would take `SESSIONS * DELAY` and never overlap at
+//! the fake server; without it they overlap and finish in about `DELAY`.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+mod support;
+
+use rust_ldap_registration::proto::registration::registration_service_server::RegistrationService;
+use rust_ldap_registration::proto::registration::VerifyCodeRequest;
+use rust_ldap_registration::session::{MemorySessionStore, Session, SessionStore};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use support::{ldap_client, ldap_config, registration_server_with_twilio, twilio_server, FakeLdapServer, FakeTwilioServer};
+use tonic::Request;
+
+/// Latency of every fake Twilio response.
+const DELAY: Duration = Duration::from_millis(200);
+
+/// Registrations verified at once.
+const SESSIONS: usize = 32;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn verifications_of_different_sessions_do_not_wait_on_each_other() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let twilio = FakeTwilioServer::start(DELAY).await;
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let server = Arc::new(
+        registration_server_with_twilio(Arc::new(ldap_client(ldap_config(ldap.url())).await), &twilio)
+            .await
+            .with_session_store(sessions.clone()),
+    );
+    for i in 0..SESSIONS {
+        let session = Session::new("alice", format!("+1415555{:04}", i), Duration::from_secs(300));
+        sessions.create(&format!("session-{}", i), session).await.unwrap();
+    }
+
+    let started = Instant::now();
+    let verifications: Vec<_> = (0..SESSIONS)
+        .map(|i| {
+            let server = server.clone();
+            tokio::spawn(async move {
+                let request = VerifyCodeRequest { session_id: format!("session-{}", i), code: twilio_server::CODE.to_string() };
+                server.verify_code(Request::new(request)).await.unwrap().into_inner()
+            })
+        })
+        .collect();
+    for verification in verifications {
+        assert!(verification.await.unwrap().success);
+    }
+    let elapsed = started.elapsed();
+
+    println!(
+        "{} verifications with {:?} Twilio latency took {:?} (serialized: at least {:?}); at most {} in flight",
+        SESSIONS,
+        DELAY,
+        elapsed,
+        DELAY * SESSIONS as u32,
+        twilio.max_in_flight()
+    );
+    assert_eq!(twilio.requests(), SESSIONS);
+    assert!(twilio.max_in_flight() > SESSIONS / 2, "checks were serialized: {}", twilio.max_in_flight());
+    assert!(elapsed < DELAY * SESSIONS as u32 / 4, "took {:?}", elapsed);
+    for i in 0..SESSIONS {
+        assert!(sessions.get(&format!("session-{}", i)).await.unwrap().unwrap().verified);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn only_one_of_two_racing_verifications_of_a_session_applies() {
+    let ldap = FakeLdapServer::start(support::directory()).await;
+    let twilio = FakeTwilioServer::start(DELAY).await;
+    let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let server = Arc::new(
+        registration_server_with_twilio(Arc::new(ldap_client(ldap_config(ldap.url())).await), &twilio)
+            .await
+            .with_session_store(sessions.clone()),
+    );
+    sessions.create("session", Session::new("alice", "+14155550101".to_string(), Duration::from_secs(300))).await.unwrap();
+
+    let verify = || {
+        let server = server.clone();
+        tokio::spawn(async move {
+            let request = VerifyCodeRequest { session_id: "session".to_string(), code: twilio_server::CODE.to_string() };
+            server.verify_code(Request::new(request)).await
+        })
+    };
+    let (first, second) = (verify(), verify());
+    let results = [first.await.unwrap(), second.await.unwrap()];
+
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    let status = results.iter().find_map(|result| result.as_ref().err()).unwrap();
+    assert_eq!(status.code(), tonic::Code::Aborted);
+    // Both attempts count if the loser lost the race after asking Twilio
+    let session = sessions.get("session").await.unwrap().unwrap();
+    assert!(session.verified);
+    assert_eq!(session.check_attempts as usize, twilio.requests());
+    assert_eq!(session.version, u64::from(session.check_attempts) + 1);
+}
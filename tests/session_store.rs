@@ -0,0 +1,85 @@
+//! Tests of the in-memory session store and the session reaper.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use rust_ldap_registration::session::{Error, MemorySessionStore, Session, SessionMetrics, SessionReaper, SessionStore};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+#[tokio::test]
+async fn updates_only_the_version_that_was_read() {
+    let store = MemorySessionStore::new();
+    store.create("s1", Session::new("alice", "+14155550101".to_string(), Duration::from_secs(300))).await.unwrap();
+
+    let mut first = store.get("s1").await.unwrap().unwrap();
+    let mut second = first.clone();
+    first.verified = true;
+    assert!(store.compare_and_update("s1", &mut first).await.unwrap());
+    assert_eq!(first.version, 1);
+
+    second.phone_number = "+14155550199".to_string();
+    assert!(!store.compare_and_update("s1", &mut second).await.unwrap());
+    assert_eq!(second.version, 0);
+
+    let stored = store.get("s1").await.unwrap().unwrap();
+    assert!(stored.verified);
+    assert_eq!(stored.phone_number, "+14155550101");
+}
+
+#[tokio::test]
+async fn deletes_conditionally_on_the_version() {
+    let store = MemorySessionStore::new();
+    store.create("s1", Session::new("alice", String::new(), Duration::from_secs(300))).await.unwrap();
+
+    assert!(!store.delete("s1", Some(1)).await.unwrap());
+    assert!(store.delete("s1", Some(0)).await.unwrap());
+    assert!(!store.delete("s1", None).await.unwrap());
+    assert!(store.get("s1").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn expires_only_sessions_past_their_timeout() {
+    let store = MemorySessionStore::new();
+    store.create("short", Session::new("alice", String::new(), Duration::from_secs(1))).await.unwrap();
+    store.create("long", Session::new("bob", String::new(), Duration::from_secs(300))).await.unwrap();
+
+    let removed = store.expire(SystemTime::now() + Duration::from_secs(60)).await.unwrap();
+
+    assert_eq!(removed, 1);
+    let ids: Vec<String> = store.list().await.unwrap().into_iter().map(|(id, _)| id).collect();
+    assert_eq!(ids, vec!["long".to_string()]);
+}
+
+#[tokio::test]
+async fn refuses_sessions_when_full_after_dropping_expired_ones() {
+    let store = MemorySessionStore::new().with_max_sessions(Some(2));
+    store.create("expired", Session { expires_at: SystemTime::now() - Duration::from_secs(1), ..Session::new("alice", String::new(), Duration::ZERO) }).await.unwrap();
+    store.create("s1", Session::new("bob", String::new(), Duration::from_secs(300))).await.unwrap();
+
+    store.create("s2", Session::new("carol", String::new(), Duration::from_secs(300))).await.unwrap();
+    let result = store.create("s3", Session::new("dave", String::new(), Duration::from_secs(300))).await;
+
+    assert!(matches!(result, Err(Error::Full(2))), "{:?}", result);
+    assert!(store.get("expired").await.unwrap().is_none());
+    store.delete("s1", None).await.unwrap();
+    store.create("s3", Session::new("dave", String::new(), Duration::from_secs(300))).await.unwrap();
+}
+
+#[tokio::test]
+async fn reaper_deletes_expired_sessions_and_updates_metrics() {
+    let store: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let expired = SystemTime::now() - Duration::from_secs(1);
+    for id in ["a", "b"] {
+        store.create(id, Session { expires_at: expired, ..Session::new("alice", String::new(), Duration::ZERO) }).await.unwrap();
+    }
+    store.create("live", Session::new("bob", String::new(), Duration::from_secs(300))).await.unwrap();
+    let metrics = Arc::new(SessionMetrics::new());
+    let reaper = SessionReaper::new(store.clone(), metrics.clone(), Duration::from_secs(60));
+
+    assert_eq!(reaper.reap_once().await.unwrap(), 2);
+    assert_eq!(reaper.reap_once().await.unwrap(), 0);
+
+    assert_eq!(metrics.reaped(), 2);
+    assert_eq!(metrics.live(), 1);
+    assert!(metrics.render().contains("registration_sessions_reaped_total 2\n"));
+}
@@ -0,0 +1,220 @@
+//! An in-memory stand-in for a DynamoDB table.
+//!
+//! Implements the operations `DynamoDbClient` uses on one table with a string
+//! hash key, including the condition and filter expressions it sends:
+//! `attribute_not_exists(...)` and comparisons joined with `AND`. Failed
+//! conditions are answered with the same service errors DynamoDB returns.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use aws_sdk_dynamodb::config::http::HttpResponse;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::operation::delete_item::{DeleteItemError, DeleteItemInput, DeleteItemOutput};
+use aws_sdk_dynamodb::operation::get_item::{GetItemError, GetItemInput, GetItemOutput};
+use aws_sdk_dynamodb::operation::put_item::{PutItemError, PutItemInput, PutItemOutput};
+use aws_sdk_dynamodb::operation::scan::{ScanError, ScanInput, ScanOutput};
+use aws_sdk_dynamodb::operation::transact_write_items::{
+    TransactWriteItemsError, TransactWriteItemsInput, TransactWriteItemsOutput,
+};
+use aws_sdk_dynamodb::types::error::{ConditionalCheckFailedException, TransactionCanceledException};
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue, Select};
+use aws_smithy_runtime_api::http::StatusCode;
+use aws_smithy_types::body::SdkBody;
+use rust_ldap_registration::db::dynamodb::{DynamoDbClient, DynamoDbConfig, DynamoDbOps};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// A DynamoDB item.
+pub type Item = HashMap<String, AttributeValue>;
+
+/// A table shared by every client made from it.
+#[derive(Debug, Clone)]
+pub struct FakeDynamoDb {
+    key: String,
+    items: Arc<Mutex<BTreeMap<String, Item>>>,
+}
+
+impl FakeDynamoDb {
+    /// Creates an empty table.
+    ///
+    /// # Arguments
+    /// * `key` - Name of the hash key attribute, e.g. `phone_number`
+    pub fn new(key: &str) -> Self {
+        Self { key: key.to_string(), items: Arc::default() }
+    }
+
+    /// Returns a client for the table.
+    pub fn client(&self) -> DynamoDbClient {
+        DynamoDbClient::with_ops(
+            Box::new(self.clone()),
+            DynamoDbConfig { region: "us-east-1".to_string(), table_name: "registrations".to_string() },
+        )
+    }
+
+    /// Returns the keys of the items in the table, in order.
+    pub fn keys(&self) -> Vec<String> {
+        self.items.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Returns an item.
+    pub fn get(&self, key: &str) -> Option<Item> {
+        self.items.lock().unwrap().get(key).cloned()
+    }
+
+    fn key_of(&self, item: &Item) -> String {
+        item.get(&self.key).and_then(|value| value.as_s().ok()).cloned().expect("item without a string key")
+    }
+}
+
+/// Names and values an expression refers to.
+struct Expression<'a> {
+    text: Option<&'a str>,
+    names: Option<&'a HashMap<String, String>>,
+    values: Option<&'a HashMap<String, AttributeValue>>,
+}
+
+impl Expression<'_> {
+    /// Returns whether an item, or no item, satisfies the expression.
+    fn matches(&self, item: Option<&Item>) -> bool {
+        let Some(text) = self.text else { return true };
+        text.split(" AND ").all(|clause| self.clause(clause.trim(), item))
+    }
+
+    fn clause(&self, clause: &str, item: Option<&Item>) -> bool {
+        if let Some(name) = clause.strip_prefix("attribute_not_exists(").and_then(|rest| rest.strip_suffix(')')) {
+            return item.is_none_or(|item| !item.contains_key(self.name(name)));
+        }
+        let [name, operator, value] = clause.split_whitespace().collect::<Vec<_>>()[..] else {
+            panic!("unsupported expression: {}", clause);
+        };
+        let expected = self.values.and_then(|values| values.get(value)).expect("expression value");
+        let Some(actual) = item.and_then(|item| item.get(self.name(name))) else { return false };
+        let ordering = match (actual, expected) {
+            (AttributeValue::S(a), AttributeValue::S(b)) => a.cmp(b),
+            (AttributeValue::N(a), AttributeValue::N(b)) => {
+                a.parse::<f64>().unwrap().total_cmp(&b.parse::<f64>().unwrap())
+            }
+            _ => return false,
+        };
+        match operator {
+            "=" => ordering.is_eq(),
+            "<" => ordering.is_lt(),
+            "<=" => ordering.is_le(),
+            ">" => ordering.is_gt(),
+            ">=" => ordering.is_ge(),
+            _ => panic!("unsupported operator: {}", operator),
+        }
+    }
+
+    fn name<'n>(&'n self, name: &'n str) -> &'n str {
+        self.names.and_then(|names| names.get(name)).map_or(name, String::as_str)
+    }
+}
+
+fn service_error<E>(error: E) -> SdkError<E, HttpResponse> {
+    SdkError::service_error(error, HttpResponse::new(StatusCode::try_from(400).unwrap(), SdkBody::empty()))
+}
+
+#[async_trait::async_trait]
+impl DynamoDbOps for FakeDynamoDb {
+    async fn put_item(&self, input: PutItemInput) -> Result<PutItemOutput, SdkError<PutItemError>> {
+        let item = input.item().cloned().unwrap_or_default();
+        let key = self.key_of(&item);
+        let condition = Expression {
+            text: input.condition_expression(),
+            names: input.expression_attribute_names(),
+            values: input.expression_attribute_values(),
+        };
+        let mut items = self.items.lock().unwrap();
+        if !condition.matches(items.get(&key)) {
+            let failed = ConditionalCheckFailedException::builder().message("The conditional request failed").build();
+            return Err(service_error(PutItemError::ConditionalCheckFailedException(failed)));
+        }
+        items.insert(key, item);
+        Ok(PutItemOutput::builder().build())
+    }
+
+    async fn get_item(&self, input: GetItemInput) -> Result<GetItemOutput, SdkError<GetItemError>> {
+        let key = self.key_of(input.key().expect("key"));
+        Ok(GetItemOutput::builder().set_item(self.get(&key)).build())
+    }
+
+    async fn delete_item(&self, input: DeleteItemInput) -> Result<DeleteItemOutput, SdkError<DeleteItemError>> {
+        let key = self.key_of(input.key().expect("key"));
+        let condition = Expression {
+            text: input.condition_expression(),
+            names: input.expression_attribute_names(),
+            values: input.expression_attribute_values(),
+        };
+        let mut items = self.items.lock().unwrap();
+        if !condition.matches(items.get(&key)) {
+            let failed = ConditionalCheckFailedException::builder().message("The conditional request failed").build();
+            return Err(service_error(DeleteItemError::ConditionalCheckFailedException(failed)));
+        }
+        let old = items.remove(&key);
+        let attributes = old.filter(|_| input.return_values() == Some(&ReturnValue::AllOld));
+        Ok(DeleteItemOutput::builder().set_attributes(attributes).build())
+    }
+
+    async fn scan(&self, input: ScanInput) -> Result<ScanOutput, SdkError<ScanError>> {
+        let start = input.exclusive_start_key().map(|key| self.key_of(key));
+        let limit = input.limit().map_or(usize::MAX, |limit| limit as usize);
+        let filter = Expression {
+            text: input.filter_expression(),
+            names: input.expression_attribute_names(),
+            values: input.expression_attribute_values(),
+        };
+        let items = self.items.lock().unwrap();
+        let remaining: Vec<(&String, &Item)> =
+            items.iter().filter(|(key, _)| start.as_ref().is_none_or(|start| *key > start)).collect();
+        let page = &remaining[..limit.min(remaining.len())];
+        let last_key = (page.len() < remaining.len())
+            .then(|| page.last().map(|(key, _)| HashMap::from([(self.key.clone(), AttributeValue::S((*key).clone()))])))
+            .flatten();
+        let matching: Vec<Item> = page.iter().filter(|(_, item)| filter.matches(Some(item))).map(|(_, item)| (*item).clone()).collect();
+
+        let builder = ScanOutput::builder().count(matching.len() as i32).set_last_evaluated_key(last_key);
+        let builder = if input.select() == Some(&Select::Count) { builder } else { builder.set_items(Some(matching)) };
+        Ok(builder.build())
+    }
+
+    async fn transact_write_items(
+        &self,
+        input: TransactWriteItemsInput,
+    ) -> Result<TransactWriteItemsOutput, SdkError<TransactWriteItemsError>> {
+        let mut items = self.items.lock().unwrap();
+        let mut writes = Vec::new();
+        for write in input.transact_items() {
+            let (key, condition, item) = if let Some(put) = write.put() {
+                let item = put.item().clone();
+                let condition = Expression {
+                    text: put.condition_expression(),
+                    names: put.expression_attribute_names(),
+                    values: put.expression_attribute_values(),
+                };
+                (self.key_of(&item), condition, Some(item))
+            } else if let Some(delete) = write.delete() {
+                let condition = Expression {
+                    text: delete.condition_expression(),
+                    names: delete.expression_attribute_names(),
+                    values: delete.expression_attribute_values(),
+                };
+                (self.key_of(delete.key()), condition, None)
+            } else {
+                panic!("unsupported transaction item: {:?}", write);
+            };
+            if !condition.matches(items.get(&key)) {
+                let cancelled = TransactionCanceledException::builder().message("Transaction cancelled").build();
+                return Err(service_error(TransactWriteItemsError::TransactionCanceledException(cancelled)));
+            }
+            writes.push((key, item));
+        }
+        for (key, item) in writes {
+            match item {
+                Some(item) => items.insert(key, item),
+                None => items.remove(&key),
+            };
+        }
+        Ok(TransactWriteItemsOutput::builder().build())
+    }
+}
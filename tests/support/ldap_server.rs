@@ -0,0 +1,549 @@
+//! In-process fake LDAP server for integration tests.
+//!
+//! The server speaks just enough LDAPv3, built on ldap3's ASN.1 types, to
+//! exercise [`LdapClient`](rust_ldap_registration::auth::LdapClient) without a
+//! real directory: simple bind against each entry's `userPassword`, by DN or,
+//! as Active Directory allows, by `userPrincipalName`, base,
+//! one-level and subtree searches with equality, presence, substring and
+//! AND/OR/NOT filters, and modify. Extended operations such as StartTLS are
+//! refused. Tests can also plant referrals, make binds or searches fail with a
+//! given result code, and delay search responses to provoke timeouts.
+//!
+//! Entries can be changed while the server runs through
+//! [`FakeLdapServer::update`].
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use bytes::BytesMut;
+use ldap3::asn1::{parse_tag, write, StructureTag, TagClass, PL};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// Result codes the server sends.
+pub mod rc {
+    pub const SUCCESS: u32 = 0;
+    pub const PROTOCOL_ERROR: u32 = 2;
+    pub const AUTH_METHOD_NOT_SUPPORTED: u32 = 7;
+    pub const REFERRAL: u32 = 10;
+    pub const NO_SUCH_OBJECT: u32 = 32;
+    pub const INVALID_CREDENTIALS: u32 = 49;
+    pub const UNAVAILABLE: u32 = 52;
+}
+
+/// Attribute holding an entry's bind password; never returned by searches.
+const PASSWORD_ATTRIBUTE: &str = "userPassword";
+
+/// A directory entry.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// DN as given
+    pub dn: String,
+    /// Attributes and their values, in insertion order
+    pub attrs: Vec<(String, Vec<String>)>,
+}
+
+impl Entry {
+    /// Returns the values of an attribute, matched case-insensitively.
+    pub fn get(&self, attribute: &str) -> Option<&Vec<String>> {
+        self.attrs
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+            .map(|(_, values)| values)
+    }
+
+    /// Replaces the values of an attribute; no values removes it.
+    fn replace(&mut self, attribute: &str, values: Vec<String>) {
+        self.attrs.retain(|(name, _)| !name.eq_ignore_ascii_case(attribute));
+        if !values.is_empty() {
+            self.attrs.push((attribute.to_string(), values));
+        }
+    }
+}
+
+/// Contents and behavior of a fake server.
+#[derive(Debug, Clone, Default)]
+pub struct Directory {
+    entries: Vec<Entry>,
+    /// Subtrees held by another server: normalized DN and referral URL
+    referrals: Vec<(String, String)>,
+    /// Bind results forced for normalized DNs: result code and diagnostic message
+    bind_failures: Vec<(String, u32, String)>,
+    /// Result code and diagnostic message forced for every search
+    search_failure: Option<(u32, String)>,
+    /// How long every search waits before answering
+    search_delay: Duration,
+    /// DNs of successful binds, in order
+    binds: Vec<String>,
+}
+
+impl Directory {
+    /// Creates an empty directory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an entry.
+    ///
+    /// # Arguments
+    /// * `dn` - DN of the entry
+    /// * `attrs` - Attributes and their values; `userPassword` is checked on bind
+    pub fn entry(mut self, dn: &str, attrs: &[(&str, &[&str])]) -> Self {
+        self.add(dn, attrs);
+        self
+    }
+
+    /// Hands out a referral for the subtree under `dn` instead of answering.
+    ///
+    /// # Arguments
+    /// * `dn` - Root of the referred subtree
+    /// * `url` - LDAP URL of the server holding it, e.g. `ldap://127.0.0.1:3890/ou=x,dc=y`
+    pub fn referral(mut self, dn: &str, url: &str) -> Self {
+        self.referrals.push((normalize(dn), url.to_string()));
+        self
+    }
+
+    /// Answers binds as `dn` with the given result, whatever the password.
+    ///
+    /// # Arguments
+    /// * `dn` - DN whose binds fail
+    /// * `code` - LDAP result code, e.g. [`rc::INVALID_CREDENTIALS`]
+    /// * `message` - Diagnostic message, e.g. an Active Directory `data 775` text
+    pub fn fail_bind(mut self, dn: &str, code: u32, message: &str) -> Self {
+        self.bind_failures.push((normalize(dn), code, message.to_string()));
+        self
+    }
+
+    /// Answers every search with the given result code.
+    pub fn fail_searches(mut self, code: u32, message: &str) -> Self {
+        self.search_failure = Some((code, message.to_string()));
+        self
+    }
+
+    /// Waits before answering every search.
+    pub fn delay_searches(mut self, delay: Duration) -> Self {
+        self.search_delay = delay;
+        self
+    }
+
+    /// Adds an entry to a running directory.
+    pub fn add(&mut self, dn: &str, attrs: &[(&str, &[&str])]) {
+        self.entries.push(Entry {
+            dn: dn.to_string(),
+            attrs: attrs
+                .iter()
+                .map(|(name, values)| (name.to_string(), values.iter().map(|v| v.to_string()).collect()))
+                .collect(),
+        });
+    }
+
+    /// Removes an entry.
+    pub fn remove(&mut self, dn: &str) {
+        let dn = normalize(dn);
+        self.entries.retain(|entry| normalize(&entry.dn) != dn);
+    }
+
+    /// Returns an entry by DN.
+    pub fn get(&self, dn: &str) -> Option<&Entry> {
+        let dn = normalize(dn);
+        self.entries.iter().find(|entry| normalize(&entry.dn) == dn)
+    }
+
+    /// Returns a mutable entry by DN.
+    pub fn get_mut(&mut self, dn: &str) -> Option<&mut Entry> {
+        let dn = normalize(dn);
+        self.entries.iter_mut().find(|entry| normalize(&entry.dn) == dn)
+    }
+
+    /// Sets or clears the search delay of a running directory.
+    pub fn set_search_delay(&mut self, delay: Duration) {
+        self.search_delay = delay;
+    }
+
+    /// Sets or clears the search failure of a running directory.
+    pub fn set_search_failure(&mut self, failure: Option<(u32, &str)>) {
+        self.search_failure = failure.map(|(code, message)| (code, message.to_string()));
+    }
+
+    /// Returns the DNs of the successful binds so far.
+    pub fn binds(&self) -> &[String] {
+        &self.binds
+    }
+}
+
+/// A fake LDAP server listening on a local port.
+#[derive(Debug)]
+pub struct FakeLdapServer {
+    url: String,
+    directory: Arc<Mutex<Directory>>,
+    task: JoinHandle<()>,
+}
+
+impl FakeLdapServer {
+    /// Starts a server for the directory on a free local port.
+    pub async fn start(directory: Directory) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind fake LDAP server");
+        let url = format!("ldap://{}", listener.local_addr().expect("local address"));
+        let directory = Arc::new(Mutex::new(directory));
+
+        let shared = directory.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, shared.clone()));
+            }
+        });
+        Self { url, directory, task }
+    }
+
+    /// Returns the server's `ldap://host:port` URL.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Reads or changes the directory while the server runs.
+    pub fn update<T>(&self, f: impl FnOnce(&mut Directory) -> T) -> T {
+        f(&mut self.directory.lock().unwrap())
+    }
+}
+
+impl Drop for FakeLdapServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Answers the requests on one connection until the client unbinds or leaves.
+async fn serve(mut stream: TcpStream, directory: Arc<Mutex<Directory>>) {
+    let mut buf = Vec::new();
+    loop {
+        let frame = loop {
+            if let Some(len) = frame_length(&buf) {
+                if buf.len() >= len {
+                    break buf.drain(..len).collect::<Vec<u8>>();
+                }
+            }
+            let mut chunk = [0u8; 4096];
+            match stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            }
+        };
+        let Ok((_, message)) = parse_tag(&frame) else {
+            return;
+        };
+        let Some(mut parts) = message.expect_constructed() else {
+            return;
+        };
+        if parts.len() < 2 {
+            return;
+        }
+        let op = parts.remove(1);
+        let id = int(&parts[0]);
+
+        let responses = match (op.class, op.id) {
+            (TagClass::Application, 0) => vec![bind(&directory, op)],
+            (TagClass::Application, 2) => return,
+            (TagClass::Application, 3) => {
+                let delay = directory.lock().unwrap().search_delay;
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                search(&directory, op)
+            }
+            (TagClass::Application, 6) => vec![modify(&directory, op)],
+            (TagClass::Application, 16) => vec![],
+            (TagClass::Application, 23) => {
+                vec![result(24, rc::PROTOCOL_ERROR, "Extended operations are not supported", &[])]
+            }
+            _ => return,
+        };
+
+        let mut out = BytesMut::new();
+        for response in responses {
+            let message = constructed(TagClass::Universal, 16, vec![integer(2, id), response]);
+            write::encode_into(&mut out, message).expect("encode LDAP message");
+        }
+        if stream.write_all(&out).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Returns the total length of the BER element at the start of `buf`, once its
+/// header is complete.
+fn frame_length(buf: &[u8]) -> Option<usize> {
+    let first = *buf.get(1)?;
+    if first & 0x80 == 0 {
+        return Some(2 + first as usize);
+    }
+    let count = (first & 0x7f) as usize;
+    let bytes = buf.get(2..2 + count)?;
+    Some(2 + count + bytes.iter().fold(0usize, |len, &b| (len << 8) | b as usize))
+}
+
+/// Handles a BindRequest.
+fn bind(directory: &Mutex<Directory>, op: StructureTag) -> StructureTag {
+    let parts = op.expect_constructed().unwrap_or_default();
+    let name = parts.get(1).map(string).unwrap_or_default();
+    let Some(auth) = parts.get(2).filter(|auth| auth.class == TagClass::Context && auth.id == 0) else {
+        return result(1, rc::AUTH_METHOD_NOT_SUPPORTED, "Only simple binds are supported", &[]);
+    };
+    let password = string(auth);
+
+    let mut directory = directory.lock().unwrap();
+    let normalized = normalize(&name);
+    if let Some((_, code, message)) = directory.bind_failures.iter().find(|(dn, _, _)| *dn == normalized) {
+        return result(1, *code, message, &[]);
+    }
+    let valid = name.is_empty()
+        || directory
+            .get(&name)
+            .or_else(|| {
+                directory.entries.iter().find(|entry| {
+                    entry.get("userPrincipalName").is_some_and(|upns| upns.iter().any(|upn| upn.eq_ignore_ascii_case(&name)))
+                })
+            })
+            .and_then(|entry| entry.get(PASSWORD_ATTRIBUTE))
+            .is_some_and(|passwords| !password.is_empty() && passwords.contains(&password));
+    if !valid {
+        return result(1, rc::INVALID_CREDENTIALS, "Invalid credentials", &[]);
+    }
+    directory.binds.push(name);
+    result(1, rc::SUCCESS, "", &[])
+}
+
+/// Handles a SearchRequest.
+fn search(directory: &Mutex<Directory>, op: StructureTag) -> Vec<StructureTag> {
+    let mut parts = op.expect_constructed().unwrap_or_default();
+    if parts.len() < 8 {
+        return vec![result(5, rc::PROTOCOL_ERROR, "Malformed search request", &[])];
+    }
+    let requested: Vec<String> = parts.pop().and_then(StructureTag::expect_constructed).unwrap_or_default().iter().map(string).collect();
+    let filter = parts.pop().expect("filter");
+    let base = string(&parts[0]);
+    let scope = int(&parts[1]);
+
+    let directory = directory.lock().unwrap();
+    if let Some((code, message)) = &directory.search_failure {
+        return vec![result(5, *code, message, &[])];
+    }
+
+    let normalized = normalize(&base);
+    if let Some((_, url)) = directory.referrals.iter().find(|(dn, _)| is_within(&normalized, dn)) {
+        return vec![result(5, rc::REFERRAL, "", std::slice::from_ref(url))];
+    }
+    if normalized.is_empty() && scope == 0 {
+        return vec![search_entry(&Entry { dn: String::new(), attrs: vec![("objectClass".into(), vec!["top".into()])] }, &requested), result(5, rc::SUCCESS, "", &[])];
+    }
+    let exists = normalized.is_empty() || directory.entries.iter().any(|entry| is_within(&normalize(&entry.dn), &normalized));
+    if !exists {
+        return vec![result(5, rc::NO_SUCH_OBJECT, "No such object", &[])];
+    }
+
+    let mut responses: Vec<StructureTag> = directory
+        .entries
+        .iter()
+        .filter(|entry| in_scope(&normalize(&entry.dn), &normalized, scope))
+        .filter(|entry| matches(&filter, entry))
+        .map(|entry| search_entry(entry, &requested))
+        .collect();
+    if scope == 2 {
+        for (dn, url) in &directory.referrals {
+            if dn != &normalized && is_within(dn, &normalized) {
+                responses.push(constructed(TagClass::Application, 19, vec![octets(4, url)]));
+            }
+        }
+    }
+    responses.push(result(5, rc::SUCCESS, "", &[]));
+    responses
+}
+
+/// Handles a ModifyRequest.
+fn modify(directory: &Mutex<Directory>, op: StructureTag) -> StructureTag {
+    let mut parts = op.expect_constructed().unwrap_or_default();
+    if parts.len() < 2 {
+        return result(7, rc::PROTOCOL_ERROR, "Malformed modify request", &[]);
+    }
+    let changes = parts.pop().and_then(StructureTag::expect_constructed).unwrap_or_default();
+    let dn = string(&parts[0]);
+
+    let mut directory = directory.lock().unwrap();
+    let Some(entry) = directory.get_mut(&dn) else {
+        return result(7, rc::NO_SUCH_OBJECT, "No such object", &[]);
+    };
+    for change in changes {
+        let change = change.expect_constructed().unwrap_or_default();
+        let (Some(operation), Some(modification)) = (change.first(), change.get(1).cloned()) else {
+            continue;
+        };
+        let modification = modification.expect_constructed().unwrap_or_default();
+        let attribute = modification.first().map(string).unwrap_or_default();
+        let values: Vec<String> = modification
+            .get(1)
+            .cloned()
+            .and_then(StructureTag::expect_constructed)
+            .unwrap_or_default()
+            .iter()
+            .map(string)
+            .collect();
+        let mut current = entry.get(&attribute).cloned().unwrap_or_default();
+        match int(operation) {
+            0 => current.extend(values.into_iter().filter(|v| !current.contains(v)).collect::<Vec<_>>()),
+            1 if values.is_empty() => current.clear(),
+            1 => current.retain(|v| !values.contains(v)),
+            _ => current = values,
+        }
+        entry.replace(&attribute, current);
+    }
+    result(7, rc::SUCCESS, "", &[])
+}
+
+/// Evaluates a search filter against an entry.
+fn matches(filter: &StructureTag, entry: &Entry) -> bool {
+    let children = || match &filter.payload {
+        PL::C(children) => children.clone(),
+        PL::P(_) => Vec::new(),
+    };
+    let assertion = || {
+        let children = children();
+        let attribute = children.first().map(string).unwrap_or_default();
+        let value = children.get(1).map(string).unwrap_or_default();
+        (attribute, value)
+    };
+    let values = |attribute: &str| entry.get(attribute).cloned().unwrap_or_default();
+
+    match filter.id {
+        0 => children().iter().all(|f| matches(f, entry)),
+        1 => children().iter().any(|f| matches(f, entry)),
+        2 => children().first().is_some_and(|f| !matches(f, entry)),
+        3 | 8 => {
+            let (attribute, value) = assertion();
+            values(&attribute).iter().any(|v| v.eq_ignore_ascii_case(&value))
+        }
+        4 => {
+            let children = children();
+            let attribute = children.first().map(string).unwrap_or_default();
+            let pieces = children.get(1).cloned().and_then(StructureTag::expect_constructed).unwrap_or_default();
+            values(&attribute).iter().any(|v| substring_match(&v.to_lowercase(), &pieces))
+        }
+        5 => {
+            let (attribute, value) = assertion();
+            values(&attribute).iter().any(|v| v.as_str() >= value.as_str())
+        }
+        6 => {
+            let (attribute, value) = assertion();
+            values(&attribute).iter().any(|v| v.as_str() <= value.as_str())
+        }
+        7 => {
+            let attribute = string(filter);
+            attribute.eq_ignore_ascii_case("objectClass") || entry.get(&attribute).is_some()
+        }
+        _ => false,
+    }
+}
+
+/// Matches a value against the initial, any and final pieces of a substring filter.
+fn substring_match(value: &str, pieces: &[StructureTag]) -> bool {
+    let mut rest = value;
+    for piece in pieces {
+        let part = string(piece).to_lowercase();
+        match piece.id {
+            0 => match rest.strip_prefix(part.as_str()) {
+                Some(after) => rest = after,
+                None => return false,
+            },
+            1 => match rest.find(part.as_str()) {
+                Some(pos) => rest = &rest[pos + part.len()..],
+                None => return false,
+            },
+            _ => return rest.ends_with(part.as_str()),
+        }
+    }
+    true
+}
+
+/// Builds a SearchResultEntry with the requested attributes.
+fn search_entry(entry: &Entry, requested: &[String]) -> StructureTag {
+    let all = requested.is_empty() || requested.iter().any(|a| a == "*");
+    let attributes = entry
+        .attrs
+        .iter()
+        .filter(|(name, _)| !name.eq_ignore_ascii_case(PASSWORD_ATTRIBUTE) || requested.iter().any(|a| a.eq_ignore_ascii_case(name)))
+        .filter(|(name, _)| all || requested.iter().any(|a| a.eq_ignore_ascii_case(name)))
+        .map(|(name, values)| {
+            constructed(TagClass::Universal, 16, vec![
+                octets(4, name),
+                constructed(TagClass::Universal, 17, values.iter().map(|v| octets(4, v)).collect()),
+            ])
+        })
+        .collect();
+    constructed(TagClass::Application, 4, vec![octets(4, &entry.dn), constructed(TagClass::Universal, 16, attributes)])
+}
+
+/// Builds an LDAPResult-shaped response.
+fn result(op: u64, code: u32, message: &str, referrals: &[String]) -> StructureTag {
+    let mut parts = vec![integer(10, code as i64), octets(4, ""), octets(4, message)];
+    if !referrals.is_empty() {
+        parts.push(constructed(TagClass::Context, 3, referrals.iter().map(|url| octets(4, url)).collect()));
+    }
+    constructed(TagClass::Application, op, parts)
+}
+
+/// Returns whether `dn` is `base` or lies under it; both normalized.
+fn is_within(dn: &str, base: &str) -> bool {
+    base.is_empty() || dn == base || dn.ends_with(&format!(",{}", base))
+}
+
+/// Returns whether `dn` is in the search scope: 0 base, 1 one level, 2 subtree.
+fn in_scope(dn: &str, base: &str, scope: i64) -> bool {
+    match scope {
+        0 => dn == base,
+        1 => dn.split_once(',').map_or(base.is_empty(), |(_, parent)| parent == base),
+        _ => is_within(dn, base),
+    }
+}
+
+/// Lowercases a DN and drops the spaces around its separators.
+fn normalize(dn: &str) -> String {
+    dn.split(',')
+        .map(|rdn| rdn.split('=').map(str::trim).collect::<Vec<_>>().join("="))
+        .collect::<Vec<_>>()
+        .join(",")
+        .to_lowercase()
+}
+
+fn constructed(class: TagClass, id: u64, children: Vec<StructureTag>) -> StructureTag {
+    StructureTag { class, id, payload: PL::C(children) }
+}
+
+fn octets(id: u64, value: &str) -> StructureTag {
+    let class = if id == 4 { TagClass::Universal } else { TagClass::Context };
+    StructureTag { class, id, payload: PL::P(value.as_bytes().to_vec()) }
+}
+
+fn integer(id: u64, value: i64) -> StructureTag {
+    let bytes = value.to_be_bytes();
+    let skip = (0..7)
+        .take_while(|&i| (bytes[i] == 0 && bytes[i + 1] & 0x80 == 0) || (bytes[i] == 0xff && bytes[i + 1] & 0x80 != 0))
+        .count();
+    StructureTag { class: TagClass::Universal, id, payload: PL::P(bytes[skip..].to_vec()) }
+}
+
+fn string(tag: &StructureTag) -> String {
+    match &tag.payload {
+        PL::P(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        PL::C(_) => String::new(),
+    }
+}
+
+fn int(tag: &StructureTag) -> i64 {
+    match &tag.payload {
+        PL::P(bytes) if !bytes.is_empty() => {
+            let init: i64 = if bytes[0] & 0x80 != 0 { -1 } else { 0 };
+            bytes.iter().fold(init, |n, &b| (n << 8) | b as i64)
+        }
+        _ => 0,
+    }
+}
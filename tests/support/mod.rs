@@ -0,0 +1,176 @@
+//! Shared helpers for the integration tests.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+#![allow(dead_code, unused_imports)]
+
+pub mod dynamodb;
+pub mod ldap_server;
+pub mod twilio_server;
+
+use rust_ldap_registration::auth::identity::IdentityProvider;
+use rust_ldap_registration::auth::ldap::{LdapClient, LdapConfig};
+use rust_ldap_registration::config;
+use rust_ldap_registration::db::dynamodb::DynamoDbClient;
+use rust_ldap_registration::grpc::RegistrationServer;
+use rust_ldap_registration::twilio::rate_limit::{RateLimitConfig, RateLimiter};
+use rust_ldap_registration::twilio::{TwilioClient, TwilioConfig};
+use std::sync::Arc;
+
+pub use dynamodb::FakeDynamoDb;
+pub use ldap_server::{rc, Directory, FakeLdapServer};
+pub use twilio_server::FakeTwilioServer;
+
+/// Base DN of the test directory.
+pub const BASE_DN: &str = "dc=example,dc=com";
+
+/// Service account the pool binds as.
+pub const SERVICE_DN: &str = "cn=service,dc=example,dc=com";
+
+/// Password of the service account.
+pub const SERVICE_PASSWORD: &str = "service-secret";
+
+/// DN of the default test user.
+pub const ALICE_DN: &str = "uid=alice,ou=people,dc=example,dc=com";
+
+/// Password of the default test user.
+pub const ALICE_PASSWORD: &str = "alice-secret";
+
+/// Returns a directory with the service account and alice, who has one mobile number.
+pub fn directory() -> Directory {
+    Directory::new()
+        .entry(SERVICE_DN, &[("cn", &["service"]), ("userPassword", &[SERVICE_PASSWORD])])
+        .entry(
+            ALICE_DN,
+            &[
+                ("objectClass", &["person"]),
+                ("uid", &["alice"]),
+                ("mail", &["alice@example.com"]),
+                ("displayName", &["Alice Example"]),
+                ("mobile", &["+1 (415) 555-0101"]),
+                ("userPassword", &[ALICE_PASSWORD]),
+            ],
+        )
+}
+
+/// Returns a directory with the service account and alice as an Active Directory
+/// user, whose entry has no `uid`.
+pub fn ad_directory() -> Directory {
+    Directory::new()
+        .entry(SERVICE_DN, &[("cn", &["service"]), ("userPassword", &[SERVICE_PASSWORD])])
+        .entry(
+            ALICE_DN,
+            &[
+                ("objectCategory", &["person"]),
+                ("objectClass", &["user"]),
+                ("sAMAccountName", &["alice"]),
+                ("userPrincipalName", &["alice@example.com"]),
+                ("mail", &["alice.example@example.com"]),
+                ("userAccountControl", &["512"]),
+                ("mobile", &["+1 (415) 555-0101"]),
+                ("userPassword", &[ALICE_PASSWORD]),
+            ],
+        )
+}
+
+/// Returns an `ldap://` URL on a local port nothing listens on.
+pub async fn closed_url() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let port = listener.local_addr().expect("local address").port();
+    format!("ldap://127.0.0.1:{}", port)
+}
+
+/// Returns LDAP settings for a fake server, with short timeouts and a small pool.
+pub fn ldap_config(url: &str) -> config::LdapConfig {
+    config::LdapConfig {
+        url: url.to_string(),
+        urls: Vec::new(),
+        server_selection: Default::default(),
+        server_cooldown: 1000,
+        base_dn: BASE_DN.to_string(),
+        use_ssl: false,
+        bind_dn: SERVICE_DN.to_string(),
+        bind_password: SERVICE_PASSWORD.to_string(),
+        phone_number_attribute: "mobile".to_string(),
+        phone_number_attributes: Vec::new(),
+        phone_selection: Default::default(),
+        username_attribute: None,
+        profile_attributes: vec!["displayName".to_string()],
+        default_phone_region: Some("US".to_string()),
+        authorized_groups: Vec::new(),
+        group_membership: Default::default(),
+        connection_timeout: 2000,
+        read_timeout: 2000,
+        min_pool_size: 1,
+        max_pool_size: 4,
+        pool_timeout: 2000,
+        max_retries: 0,
+        idle_timeout: 60000,
+        user_filter: None,
+        search_bases: Vec::new(),
+        search_mode: Default::default(),
+        follow_referrals: false,
+        referral_hosts: Vec::new(),
+        referral_hop_limit: 3,
+        page_size: 0,
+        directory_type: Default::default(),
+        username_mapping: Default::default(),
+        tls_mode: None,
+        ca_bundle: None,
+        client_cert: None,
+        client_key: None,
+        hostname_verification: None,
+        trust_store: None,
+        trust_store_password: None,
+        trust_store_type: None,
+    }
+}
+
+/// Creates a client for a fake server.
+pub async fn ldap_client(config: config::LdapConfig) -> LdapClient {
+    LdapClient::new(LdapConfig::from(config)).await.expect("connect to fake LDAP server")
+}
+
+/// Creates a registration server whose Twilio client runs in test mode, where
+/// the code is the last six digits of `test_phone`. DynamoDB is never reached by
+/// the flows under test.
+pub async fn registration_server(identity: Arc<dyn IdentityProvider>, test_phone: &str) -> RegistrationServer {
+    let dynamodb = DynamoDbClient::new("registrations".to_string(), "us-east-1".to_string())
+        .await
+        .expect("create DynamoDB client");
+    registration_server_with_dynamodb(identity, test_phone, dynamodb)
+}
+
+/// Creates a registration server like [`registration_server`] that stores
+/// registrations through the given client, e.g. one for a [`FakeDynamoDb`].
+pub fn registration_server_with_dynamodb(identity: Arc<dyn IdentityProvider>, test_phone: &str, dynamodb: DynamoDbClient) -> RegistrationServer {
+    let mut twilio = twilio_client(true);
+    twilio.set_test_ldap_phone(test_phone.to_string());
+    with_twilio(identity, twilio, dynamodb)
+}
+
+/// Creates a registration server whose Twilio client calls a fake Verify API.
+pub async fn registration_server_with_twilio(identity: Arc<dyn IdentityProvider>, twilio: &FakeTwilioServer) -> RegistrationServer {
+    let dynamodb = DynamoDbClient::new("registrations".to_string(), "us-east-1".to_string())
+        .await
+        .expect("create DynamoDB client");
+    with_twilio(identity, twilio_client(false).with_api_base(twilio.url()), dynamodb)
+}
+
+/// Creates a Twilio client with dummy credentials.
+fn twilio_client(test_mode: bool) -> TwilioClient {
+    TwilioClient::new(TwilioConfig {
+        account_sid: "AC00000000000000000000000000000000".to_string(),
+        auth_token: "token".to_string(),
+        verify_service_sid: "VA00000000000000000000000000000000".to_string(),
+        verification_timeout_secs: 5,
+        test_mode,
+    })
+    .expect("create Twilio client")
+}
+
+/// Creates a registration server around a Twilio client.
+fn with_twilio(identity: Arc<dyn IdentityProvider>, twilio: TwilioClient, dynamodb: DynamoDbClient) -> RegistrationServer {
+    let rate_limiter = RateLimiter::new(RateLimitConfig { max_attempts: 5, window_secs: 300 });
+    RegistrationServer::new(identity, twilio, dynamodb, rate_limiter, 300)
+}
@@ -0,0 +1,148 @@
+//! A slow stand-in for Twilio's Verify API.
+//!
+//! Answers `Verifications` with `pending` and `VerificationCheck` with
+//! `approved` when the code is `CODE`, after a fixed delay, and records how
+//! many requests were in flight at once. Checking `EXHAUSTED_CODE` fails the
+//! way Twilio does once a verification has run out of check attempts, and
+//! sending a code to `LANDLINE` fails the way an SMS to a landline does.
+//!
+//! @author Joseph G Noonan
+//! @copyright 2025
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// Code the server approves.
+pub const CODE: &str = "123456";
+
+/// Code answered with Twilio's max check attempts reached error.
+pub const EXHAUSTED_CODE: &str = "606060";
+
+/// Number codes cannot be sent to.
+pub const LANDLINE: &str = "+15005550009";
+
+/// Request counters shared with the connection tasks.
+#[derive(Debug, Default)]
+struct Counters {
+    requests: AtomicUsize,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+    sent_to: Mutex<Vec<String>>,
+}
+
+/// A running fake Verify API, stopped when dropped.
+pub struct FakeTwilioServer {
+    url: String,
+    counters: Arc<Counters>,
+    task: JoinHandle<()>,
+}
+
+impl FakeTwilioServer {
+    /// Starts a server on an ephemeral local port.
+    ///
+    /// # Arguments
+    /// * `delay` - How long every response takes
+    pub async fn start(delay: Duration) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind fake Twilio server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let counters = Arc::new(Counters::default());
+        let task = tokio::spawn({
+            let counters = counters.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, counters.clone(), delay));
+                }
+            }
+        });
+        Self { url, counters, task }
+    }
+
+    /// Returns the base URL to pass to `TwilioClient::with_api_base`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Returns the number of requests answered or in flight.
+    pub fn requests(&self) -> usize {
+        self.counters.requests.load(Ordering::SeqCst)
+    }
+
+    /// Returns the numbers codes were sent to, in order, including failed sends.
+    pub fn sent_to(&self) -> Vec<String> {
+        self.counters.sent_to.lock().unwrap().clone()
+    }
+
+    /// Returns the most requests that were in flight at the same time.
+    pub fn max_in_flight(&self) -> usize {
+        self.counters.max_in_flight.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for FakeTwilioServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Answers one request and closes the connection.
+async fn serve(mut stream: TcpStream, counters: Arc<Counters>, delay: Duration) {
+    let Some(request) = read_request(&mut stream).await else {
+        return;
+    };
+    counters.requests.fetch_add(1, Ordering::SeqCst);
+    let in_flight = counters.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+    counters.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+    tokio::time::sleep(delay).await;
+    counters.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+    let check = request.starts_with("POST") && request.contains("/VerificationCheck");
+    let to = request.split(['?', '&', '\n']).find_map(|field| field.strip_prefix("To=")).map(|to| to.trim().replace("%2B", "+"));
+    if !check {
+        counters.sent_to.lock().unwrap().extend(to.clone());
+    }
+    let (status_line, body) = if check && request.contains(&format!("Code={}", EXHAUSTED_CODE)) {
+        ("429 Too Many Requests", "{\"code\":60202,\"message\":\"Max check attempts reached\",\"status\":429}".to_string())
+    } else if !check && to.as_deref() == Some(LANDLINE) {
+        ("400 Bad Request", "{\"code\":60205,\"message\":\"SMS is not supported by landline phone number\",\"status\":400}".to_string())
+    } else {
+        let status = if check && request.contains(&format!("Code={}", CODE)) { "approved" } else { "pending" };
+        ("200 OK", format!("{{\"status\":\"{}\"}}", status))
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status_line,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Reads a request's head and its `Content-Length` body.
+async fn read_request(stream: &mut TcpStream) -> Option<String> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        let text = String::from_utf8_lossy(&buffer);
+        if let Some(end) = text.find("\r\n\r\n") {
+            let length = text[..end]
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("content-length").then(|| value.trim().parse::<usize>().ok())?
+                })
+                .unwrap_or(0);
+            if buffer.len() >= end + 4 + length {
+                return Some(text.into_owned());
+            }
+        }
+    }
+}